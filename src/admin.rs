@@ -0,0 +1,223 @@
+//! Operational endpoints: health reporting, delivery, usage and timing
+//! statistics and the maintenance mode toggle.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use rocket::{
+    get,
+    http::Status,
+    post,
+    request::{self, FromRequest, Request},
+    serde::json::Json,
+    State,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config, deliveries, panics,
+    probe::{IrmaProbe, KeyProbe, ProbeStatus},
+    timings, usage, CurrentConfig,
+};
+
+/// Whether the plugin is in maintenance mode, in which no new sessions are
+/// started while sessions already in flight can still complete
+#[derive(Clone)]
+pub struct Maintenance(Arc<AtomicBool>);
+
+impl Maintenance {
+    pub fn new(enabled: bool) -> Self {
+        Maintenance(Arc::new(AtomicBool::new(enabled)))
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, enabled: bool) {
+        self.0.store(enabled, Ordering::Relaxed);
+        log::warn!(
+            "Maintenance mode {}",
+            if enabled { "enabled" } else { "disabled" }
+        );
+    }
+}
+
+/// Toggle maintenance mode whenever the process receives SIGUSR1
+#[cfg(unix)]
+pub async fn toggle_maintenance_on_sigusr1(maintenance: Maintenance) {
+    use rocket::tokio::signal::unix::{signal, SignalKind};
+
+    let mut user_defined = match signal(SignalKind::user_defined1()) {
+        Ok(user_defined) => user_defined,
+        Err(e) => {
+            log::error!(
+                "Could not listen for SIGUSR1, maintenance toggle disabled: {}",
+                e
+            );
+            return;
+        }
+    };
+    while user_defined.recv().await.is_some() {
+        maintenance.set(!maintenance.enabled());
+    }
+}
+
+// Compare secrets in constant time, by comparing their digests without
+// stopping at the first difference, so response times do not reveal how much
+// of a guess was right
+fn secrets_equal(a: &str, b: &str) -> bool {
+    let (a, b) = (Sha256::digest(a), Sha256::digest(b));
+    a.iter()
+        .zip(b.iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+/// Check the bearer token of a request against an api key, failing when no
+/// api key is configured
+pub(crate) fn bearer_matches(request: &Request<'_>, api_key: Option<&str>) -> bool {
+    let authorization = request
+        .headers()
+        .get_one("Authorization")
+        .and_then(|header| header.strip_prefix("Bearer "));
+    matches!((api_key, authorization), (Some(api_key), Some(authorization)) if secrets_equal(api_key, authorization))
+}
+
+// Guard for routes only accessible to operators, identified by the configured
+// admin api key
+pub struct AdminAuthorization;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminAuthorization {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let config = request
+            .rocket()
+            .state::<config::SharedConfig>()
+            .map(|config| config.get());
+        let api_key = config.as_deref().and_then(|config| config.admin_api_key());
+
+        if bearer_matches(request, api_key) {
+            request::Outcome::Success(AdminAuthorization)
+        } else {
+            request::Outcome::Error((Status::Unauthorized, ()))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MaintenanceStatus {
+    enabled: bool,
+}
+
+#[post("/admin/maintenance", data = "<status>")]
+pub async fn set_maintenance(
+    _authorization: AdminAuthorization,
+    maintenance: &State<Maintenance>,
+    status: Json<MaintenanceStatus>,
+) -> Json<MaintenanceStatus> {
+    maintenance.set(status.enabled);
+    Json(MaintenanceStatus {
+        enabled: maintenance.enabled(),
+    })
+}
+
+// Outcomes and latencies of result deliveries to attr_urls, per target host
+#[get("/admin/deliveries")]
+pub async fn delivery_stats(
+    _authorization: AdminAuthorization,
+) -> Json<BTreeMap<String, deliveries::HostStats>> {
+    Json(deliveries::snapshot())
+}
+
+// Started and completed sessions per requested attribute set
+#[get("/admin/usage")]
+pub async fn usage_stats(
+    _authorization: AdminAuthorization,
+) -> Json<BTreeMap<String, usage::Usage>> {
+    Json(usage::snapshot())
+}
+
+// Time sessions take from their start until they complete
+#[get("/admin/timings")]
+pub async fn timing_stats(_authorization: AdminAuthorization) -> Json<timings::Timings> {
+    Json(timings::snapshot())
+}
+
+#[derive(Debug, Serialize)]
+pub struct Health {
+    status: &'static str,
+    maintenance: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    irma_circuit: Option<&'static str>,
+    /// Latest outcome of the background irma server probe
+    #[serde(skip_serializing_if = "Option::is_none")]
+    irma_probe: Option<ProbeStatus>,
+    /// Handler panics since startup
+    panics: u64,
+}
+
+// Health of the plugin, which is only reported unhealthy when the background
+// key self-test is enabled and its latest run failed, since a corrupted or
+// revoked key otherwise goes unnoticed until the first result is signed
+#[get("/health")]
+pub async fn health(
+    config: CurrentConfig,
+    maintenance: &State<Maintenance>,
+    probe: &State<IrmaProbe>,
+    key_probe: &State<KeyProbe>,
+) -> (Status, Json<Health>) {
+    let (status, description) = match key_probe.latest() {
+        Some(false) => (Status::ServiceUnavailable, "keys_unusable"),
+        _ => (Status::Ok, "ok"),
+    };
+    (
+        status,
+        Json(Health {
+            status: description,
+            maintenance: maintenance.enabled(),
+            irma_circuit: config
+                .irma_server()
+                .circuit_state()
+                .map(|state| state.as_str()),
+            irma_probe: probe.latest(),
+            panics: panics::count(),
+        }),
+    )
+}
+
+// Readiness to start new sessions, which is withdrawn in maintenance mode and
+// when the latest probe could not reach the irma server, so load balancers
+// can route new authentications elsewhere
+#[get("/readyz")]
+pub async fn ready(
+    maintenance: &State<Maintenance>,
+    probe: &State<IrmaProbe>,
+) -> (Status, Json<Health>) {
+    let irma_probe = probe.latest();
+    let (status, description) = if maintenance.enabled() {
+        (Status::ServiceUnavailable, "maintenance")
+    } else if irma_probe.is_some_and(|probe| !probe.up) {
+        (Status::ServiceUnavailable, "irma_unreachable")
+    } else {
+        (Status::Ok, "ok")
+    };
+    (
+        status,
+        Json(Health {
+            status: description,
+            maintenance: maintenance.enabled(),
+            irma_circuit: None,
+            irma_probe,
+            panics: panics::count(),
+        }),
+    )
+}
@@ -0,0 +1,109 @@
+//! Audit trail of session lifecycle events, written as json lines. Events
+//! name the requested attributes but never carry their values, and identify
+//! sessions by their redacted token only.
+
+use std::{
+    fs::OpenOptions,
+    io::{BufWriter, Write},
+    sync::mpsc::{self, Receiver, Sender},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent<'a> {
+    Started {
+        session: String,
+        flow: &'static str,
+        attributes: &'a [String],
+    },
+    QrShown {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        session: Option<String>,
+    },
+    Completed {
+        session: String,
+        attributes: &'a [String],
+        /// Seconds the clock of the irma server ran ahead of ours
+        #[serde(rename = "clock_skew_seconds", skip_serializing_if = "Option::is_none")]
+        clock_skew: Option<i64>,
+    },
+    Failed {
+        session: String,
+        reason: &'a str,
+    },
+    ResultDelivered {
+        session: String,
+    },
+}
+
+#[derive(Serialize)]
+struct AuditLine<'a> {
+    /// Seconds since the unix epoch
+    timestamp: u64,
+    #[serde(flatten)]
+    event: AuditEvent<'a>,
+}
+
+/// Handle to the audit log, which is written by a dedicated thread so
+/// recording events never blocks request handling
+pub struct AuditLog(Option<Sender<String>>);
+
+impl AuditLog {
+    /// Start writing to the file at `target`, or to standard output when it
+    /// is "stdout". Without a target, events are discarded.
+    pub fn new(target: Option<&str>) -> Self {
+        AuditLog(target.map(|target| {
+            let (sender, receiver) = mpsc::channel();
+            let target = target.to_string();
+            std::thread::spawn(move || write_lines(&target, receiver));
+            sender
+        }))
+    }
+
+    pub fn record(&self, event: AuditEvent<'_>) {
+        let sender = match &self.0 {
+            Some(sender) => sender,
+            None => return,
+        };
+        let line = AuditLine {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_secs())
+                .unwrap_or(0),
+            event,
+        };
+        match serde_json::to_string(&line) {
+            // Fails only when the writer gave up, which it logged already
+            Ok(line) => drop(sender.send(line)),
+            Err(e) => log::error!("Could not serialize audit event: {}", e),
+        }
+    }
+}
+
+fn write_lines(target: &str, lines: Receiver<String>) {
+    let output: Box<dyn Write> = if target == "stdout" {
+        Box::new(std::io::stdout())
+    } else {
+        match OpenOptions::new().create(true).append(true).open(target) {
+            Ok(file) => Box::new(file),
+            Err(e) => {
+                log::error!("Could not open audit log {}: {}", target, e);
+                return;
+            }
+        }
+    };
+    let mut writer = BufWriter::new(output);
+    while let Ok(line) = lines.recv() {
+        let mut result = writeln!(writer, "{}", line);
+        // Flush once no further events are queued
+        for line in lines.try_iter() {
+            result = result.and(writeln!(writer, "{}", line));
+        }
+        if let Err(e) = result.and_then(|()| writer.flush()) {
+            log::error!("Could not write audit log: {}", e);
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::{borrow::Cow, error::Error as StdError, fmt::Display};
+
+use base64::URL_SAFE_NO_PAD;
+use serde::de::DeserializeOwned;
+
+/// Failure decoding a base64 encoded path parameter
+#[derive(Debug)]
+pub enum Error {
+    Base64(base64::DecodeError),
+    Utf(std::str::Utf8Error),
+    Json(serde_json::Error),
+}
+
+impl From<base64::DecodeError> for Error {
+    fn from(e: base64::DecodeError) -> Error {
+        Error::Base64(e)
+    }
+}
+
+impl From<std::str::Utf8Error> for Error {
+    fn from(e: std::str::Utf8Error) -> Error {
+        Error::Utf(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::Json(e)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Base64(e) => e.fmt(f),
+            Error::Utf(e) => e.fmt(f),
+            Error::Json(e) => e.fmt(f),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Base64(e) => Some(e),
+            Error::Utf(e) => Some(e),
+            Error::Json(e) => Some(e),
+        }
+    }
+}
+
+/// Encode as unpadded url-safe base64, the encoding of all path parameters
+/// we produce
+pub fn encode_b64<T: AsRef<[u8]>>(input: T) -> String {
+    base64::encode_config(input, URL_SAFE_NO_PAD)
+}
+
+// Decode url-safe base64, with or without padding. Input in the standard
+// alphabet is accepted as well, as long as it doesn't mix both alphabets.
+fn decode_lenient(encoded: &str) -> Result<Vec<u8>, Error> {
+    let encoded = encoded.trim_end_matches('=');
+    let standard = encoded.find(|c| matches!(c, '+' | '/'));
+    let url_safe = encoded.contains(|c| matches!(c, '-' | '_'));
+    let encoded = match standard {
+        Some(index) if url_safe => {
+            return Err(base64::DecodeError::InvalidByte(index, encoded.as_bytes()[index]).into())
+        }
+        Some(_) => Cow::Owned(encoded.replace('+', "-").replace('/', "_")),
+        None => Cow::Borrowed(encoded),
+    };
+    Ok(base64::decode_config(encoded.as_ref(), URL_SAFE_NO_PAD)?)
+}
+
+/// Decode a url-safe base64 encoded string
+pub fn decode_b64_str(encoded: &str) -> Result<String, Error> {
+    let decoded = decode_lenient(encoded)?;
+    Ok(std::str::from_utf8(&decoded)?.to_string())
+}
+
+/// Decode a url-safe base64 encoded json value
+pub fn decode_b64_json<T: DeserializeOwned>(encoded: &str) -> Result<T, Error> {
+    let decoded = decode_lenient(encoded)?;
+    Ok(serde_json::from_slice(&decoded)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_b64_json, decode_b64_str, decode_lenient, encode_b64};
+
+    // Encodes to +/+/ in the standard alphabet and -_-_ in the url-safe one
+    const BYTES: [u8; 3] = [0xfb, 0xff, 0xbf];
+
+    #[test]
+    fn encoding_is_unpadded_url_safe() {
+        assert_eq!(encode_b64("hi"), "aGk");
+        assert_eq!(encode_b64(BYTES), "-_-_");
+    }
+
+    #[test]
+    fn padded_and_unpadded_input_decode() {
+        assert_eq!(decode_b64_str("aGk=").unwrap(), "hi");
+        assert_eq!(decode_b64_str("aGk").unwrap(), "hi");
+        assert_eq!(decode_b64_str("aA==").unwrap(), "h");
+        assert_eq!(decode_b64_str("aA").unwrap(), "h");
+    }
+
+    #[test]
+    fn standard_alphabet_decodes() {
+        assert_eq!(decode_lenient("+/+/").unwrap(), BYTES);
+        assert_eq!(decode_lenient("-_-_").unwrap(), BYTES);
+        assert_eq!(decode_b64_str("aGk/").unwrap(), "hi?");
+        assert_eq!(decode_b64_str("aGk_").unwrap(), "hi?");
+    }
+
+    #[test]
+    fn mixed_alphabets_are_refused() {
+        assert!(decode_lenient("-/-/").is_err());
+        assert!(decode_lenient("+_+_").is_err());
+    }
+
+    #[test]
+    fn invalid_input_is_refused() {
+        for encoded in ["a", "aGk=aGk", "aG k", "aGk!", "a.Gk", "%61Gk"] {
+            assert!(decode_lenient(encoded).is_err(), "{} is accepted", encoded);
+        }
+        // Valid base64, but not of utf-8 or json
+        assert!(decode_b64_str(&encode_b64([0xff, 0xfe])).is_err());
+        assert!(decode_b64_json::<Vec<String>>(&encode_b64("not json")).is_err());
+    }
+
+    #[test]
+    fn json_decodes_from_either_padding() {
+        let attributes: Vec<String> = decode_b64_json("WyJlbWFpbCJd").unwrap();
+        assert_eq!(attributes, ["email"]);
+        let attributes: Vec<String> = decode_b64_json("WyJlbWFpbCJdCg==").unwrap();
+        assert_eq!(attributes, ["email"]);
+    }
+}
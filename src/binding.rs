@@ -0,0 +1,66 @@
+use josekit::jws::{self, JwsHeader};
+use rocket::http::{Cookie, CookieJar, SameSite};
+use url::Url;
+
+use crate::{config, irma::SessionToken, Error};
+
+const BINDING_COOKIE: &str = "irma_session";
+
+const NOT_BOUND: &str = "This session was not started from this browser";
+
+/// Token of the irma session an in-band continuation finalizes
+pub(crate) fn session_token(continuation: &str) -> Option<SessionToken> {
+    Url::parse(continuation)
+        .ok()?
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, token)| SessionToken::new(token.into_owned()))
+}
+
+/// Bind the browser to the irma session finalized by the continuation, by
+/// setting a cookie containing the signed session token
+pub fn bind(
+    config: &config::Config,
+    cookies: &CookieJar<'_>,
+    continuation: &str,
+) -> Result<(), Error> {
+    let (binding, token) = match (config.session_binding(), session_token(continuation)) {
+        (Some(binding), Some(token)) => (binding, token),
+        _ => return Ok(()),
+    };
+    let value = jws::serialize_compact(
+        token.expose().as_bytes(),
+        &JwsHeader::new(),
+        binding.signer(),
+    )?;
+    cookies.add(
+        Cookie::build((BINDING_COOKIE, value))
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .secure(config.server_url().starts_with("https://")),
+    );
+    Ok(())
+}
+
+/// Check that the browser finalizing the session is the one bound to it
+pub fn verify(
+    config: &config::Config,
+    cookies: &CookieJar<'_>,
+    token: &SessionToken,
+) -> Result<(), Error> {
+    let binding = match config.session_binding() {
+        Some(binding) => binding,
+        None => return Ok(()),
+    };
+    let cookie = cookies
+        .get(BINDING_COOKIE)
+        .ok_or(Error::Forbidden(NOT_BOUND))?;
+    let (bound_token, _) = jws::deserialize_compact(cookie.value(), binding.verifier())
+        .map_err(|_| Error::Forbidden(NOT_BOUND))?;
+    if bound_token != token.expose().as_bytes() {
+        return Err(Error::Forbidden(NOT_BOUND));
+    }
+    cookies.remove(BINDING_COOKIE);
+    Ok(())
+}
@@ -0,0 +1,81 @@
+//! Error responses, for requests no route handled and for errors of routes
+//! alike, as json unless the client prefers html.
+
+use askama::Template;
+use rocket::{
+    catch,
+    http::{MediaType, Status},
+    response::content::RawHtml,
+    serde::json::Json,
+    Request, Responder,
+};
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct ErrorEnvelope {
+    error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "error.html")]
+struct ErrorTemplate<'a> {
+    code: u16,
+    reason: &'a str,
+    message: Option<&'a str>,
+}
+
+#[derive(Responder)]
+pub enum ErrorPage {
+    Json(Json<ErrorEnvelope>),
+    Html(RawHtml<String>),
+}
+
+impl ErrorPage {
+    /// Page for an error with the given machine-readable code and
+    /// human-readable message
+    pub fn new(
+        request: &Request<'_>,
+        status: Status,
+        error: &str,
+        message: Option<&str>,
+    ) -> ErrorPage {
+        let prefers_html = request
+            .accept()
+            .is_some_and(|accept| accept.preferred().media_type() == &MediaType::HTML);
+        if prefers_html {
+            let page = ErrorTemplate {
+                code: status.code,
+                reason: status.reason().unwrap_or("Error"),
+                message,
+            }
+            .render();
+            if let Ok(page) = page {
+                return ErrorPage::Html(RawHtml(page));
+            }
+        }
+        ErrorPage::Json(Json(ErrorEnvelope {
+            error: error.to_string(),
+            message: message.map(str::to_string),
+            request_id: request
+                .headers()
+                .get_one("X-Request-Id")
+                .map(str::to_string),
+        }))
+    }
+}
+
+#[catch(default)]
+pub fn default(status: Status, request: &Request<'_>) -> (Status, ErrorPage) {
+    #[cfg(feature = "sentry")]
+    crate::sentry_context::capture_status(request, status);
+    let error = status
+        .reason()
+        .unwrap_or("Error")
+        .to_ascii_lowercase()
+        .replace(' ', "_");
+    (status, ErrorPage::new(request, status, &error, None))
+}
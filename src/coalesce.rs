@@ -0,0 +1,112 @@
+//! Coalescing of concurrent start requests carrying the same idempotency key,
+//! so a burst of retries of a single start creates one irma session. Only
+//! requests in flight at the same time are coalesced: once a start completed,
+//! a later request with the same key starts a new session. Requests are only
+//! coalesced when their bodies are identical as well, so a key reused by
+//! another requestor or for another request never shares a session.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use rocket::{
+    request::{self, FromRequest, Request},
+    tokio::sync::OnceCell,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Maximum length of idempotency keys, longer keys are ignored
+const MAX_KEY_LENGTH: usize = 256;
+
+/// Value of the Idempotency-Key header of a request, if any
+pub struct IdempotencyKey(pub Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IdempotencyKey {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        request::Outcome::Success(IdempotencyKey(
+            request
+                .headers()
+                .get_one("Idempotency-Key")
+                .filter(|key| !key.is_empty() && key.len() <= MAX_KEY_LENGTH)
+                .map(str::to_string),
+        ))
+    }
+}
+
+/// Key of the flight of a start request: its idempotency key together with a
+/// digest of the normalized request, which includes the requestor key id.
+/// The request is serialized through a json value, which orders map keys.
+pub fn flight_key<T: Serialize>(
+    idempotency_key: &str,
+    request: &T,
+) -> Result<String, serde_json::Error> {
+    let request = serde_json::to_vec(&serde_json::to_value(request)?)?;
+    Ok(format!("{}:{:x}", idempotency_key, Sha256::digest(request)))
+}
+
+type Flight = Arc<OnceCell<String>>;
+
+/// Starts in flight by idempotency key, resolving to the client url of the
+/// started session
+#[derive(Default)]
+pub struct StartFlights(Mutex<HashMap<String, Flight>>);
+
+// Ends a flight as soon as one of its requests is done with it, also when that
+// request is cancelled, so later requests start anew. Requests still waiting
+// keep their handle to the flight.
+struct Participant<'a> {
+    flights: &'a StartFlights,
+    key: &'a str,
+    flight: Flight,
+}
+
+impl Drop for Participant<'_> {
+    fn drop(&mut self) {
+        let mut flights = self.flights.0.lock().unwrap();
+        if flights
+            .get(self.key)
+            .is_some_and(|current| Arc::ptr_eq(current, &self.flight))
+        {
+            flights.remove(self.key);
+        }
+    }
+}
+
+impl StartFlights {
+    pub fn new() -> Self {
+        StartFlights::default()
+    }
+
+    /// Run `start` unless a start with the same key is in flight, in which
+    /// case its client url is shared. Failures are not shared: when the start
+    /// in flight fails, the next waiting request runs its own start.
+    pub async fn run<E>(
+        &self,
+        key: &str,
+        start: impl Future<Output = Result<String, E>>,
+    ) -> Result<String, E> {
+        let flight = self
+            .0
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .clone();
+        let participant = Participant {
+            flights: self,
+            key,
+            flight,
+        };
+        participant
+            .flight
+            .get_or_try_init(|| start)
+            .await
+            .map(String::clone)
+    }
+}
@@ -1,19 +1,173 @@
-use std::{collections::HashMap, convert::TryFrom, error::Error as StdError, fmt::Display};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    error::Error as StdError,
+    fmt::Display,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
-use josekit::{jwe::JweEncrypter, jws::JwsSigner};
-use serde::Deserialize;
+use josekit::{
+    jwe::{JweEncrypter, JweHeader},
+    jws::{JwsHeader, JwsSigner, JwsVerifier, HS256},
+    jwt::{self, JwtPayload},
+    JoseError,
+};
+use serde::{Deserialize, Serialize};
+use url::Url;
 use verder_helpen_jwt::{EncryptionKeyConfig, SignKeyConfig};
 
-type AttributeMapping = HashMap<String, Vec<String>>;
+type RawAttributeMapping = HashMap<String, Vec<String>>;
+type AttributeMapping = HashMap<String, Vec<super::irma::AttributeId>>;
+
+// Key management algorithms a requestor can decrypt with its private key
+const PUBLIC_KEY_ALGORITHMS: &[&str] = &[
+    "RSA-OAEP",
+    "RSA-OAEP-256",
+    "RSA-OAEP-384",
+    "RSA-OAEP-512",
+    "ECDH-ES",
+    "ECDH-ES+A128KW",
+    "ECDH-ES+A192KW",
+    "ECDH-ES+A256KW",
+];
+
+// Encrypter for a public key, of which the key management algorithm (and
+// with it the `alg` header of produced tokens) follows from the key type
+fn public_key_encrypter(
+    name: &str,
+    key: EncryptionKeyConfig,
+) -> Result<Box<dyn JweEncrypter>, Error> {
+    let encrypter = Box::<dyn JweEncrypter>::try_from(key)?;
+    let algorithm = encrypter.algorithm().name();
+    if !PUBLIC_KEY_ALGORITHMS.contains(&algorithm) {
+        return Err(Error::UnsupportedKeyAlgorithm(
+            name.to_string(),
+            algorithm.to_string(),
+        ));
+    }
+    log::debug!("Encrypting with {} for {}", algorithm, name);
+    Ok(encrypter)
+}
+
+/// Source of a key results are encrypted to: the key itself, or the url of
+/// the JWKS in which the relying party publishes it
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum EncryptionKeySource {
+    Jwks { jwks_url: String, kid: String },
+    Key(EncryptionKeyConfig),
+}
+
+/// Source of the signing key: the private key itself, or a signing service
+/// holding it
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum SigningKeySource {
+    Remote {
+        remote: super::remote_signer::RemoteSignerConfig,
+    },
+    Inline(SignKeyConfig),
+}
+
+/// Key results are encrypted to
+#[derive(Debug)]
+enum ResultKey {
+    Static(Arc<dyn JweEncrypter>),
+    Remote(super::remote_keys::RemoteKey),
+}
+
+impl ResultKey {
+    fn from_source(
+        name: &str,
+        source: EncryptionKeySource,
+        allow_insecure_urls: bool,
+    ) -> Result<ResultKey, Error> {
+        match source {
+            EncryptionKeySource::Key(key) => {
+                Ok(ResultKey::Static(public_key_encrypter(name, key)?.into()))
+            }
+            EncryptionKeySource::Jwks { jwks_url, kid } => {
+                if Url::parse(&jwks_url)?.scheme() != "https" && !allow_insecure_urls {
+                    return Err(Error::InsecureKeyUrl(jwks_url));
+                }
+                Ok(ResultKey::Remote(super::remote_keys::RemoteKey {
+                    url: jwks_url,
+                    kid,
+                }))
+            }
+        }
+    }
+
+    // Encrypter for the key, which for remote keys is only available once
+    // their key set was fetched
+    fn encrypter(&self, cache_dir: Option<&Path>) -> Result<Arc<dyn JweEncrypter>, Error> {
+        match self {
+            ResultKey::Static(encrypter) => Ok(encrypter.clone()),
+            ResultKey::Remote(key) => super::remote_keys::encrypter(key, cache_dir)
+                .ok_or_else(|| Error::KeyUnavailable(key.kid.clone())),
+        }
+    }
+
+    fn key_id(&self) -> Option<&str> {
+        match self {
+            ResultKey::Static(encrypter) => encrypter.key_id(),
+            ResultKey::Remote(key) => Some(&key.kid),
+        }
+    }
+}
+
+// Host of a url, or of a host with optional port
+fn host_of(url_or_host: &str) -> Option<String> {
+    let url = Url::parse(url_or_host)
+        .ok()
+        .filter(|url| url.has_host())
+        .or_else(|| Url::parse(&format!("http://{}", url_or_host)).ok())?;
+    url.host_str().map(str::to_ascii_lowercase)
+}
+
+fn parse_attribute_mapping(mapping: RawAttributeMapping) -> Result<AttributeMapping, Error> {
+    mapping
+        .into_iter()
+        .map(|(attribute, ids)| {
+            let ids = ids
+                .iter()
+                .map(|id| {
+                    super::irma::AttributeId::parse(id)
+                        .ok_or_else(|| Error::InvalidAttributeId(id.clone()))
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            Ok((attribute, ids))
+        })
+        .collect()
+}
 
 #[derive(Debug)]
 pub enum Error {
     UnknownAttribute(String),
+    DuplicateAttributes(Vec<String>),
+    ValueNotAllowed(String),
+    UnknownRequestorKey(String),
+    DisjunctionTooLarge(String, usize),
+    UnsupportedKeyAlgorithm(String, String),
+    SelfTest(String, JoseError),
+    InsecureKeyUrl(String),
+    RemoteKey(String, super::remote_keys::Error),
+    KeyUnavailable(String),
+    InvalidAttributeId(String),
+    RequiresInsecureDevMode(&'static str),
+    RestartRequired(&'static str),
     NotMatching(&'static str),
     InvalidResponse(&'static str),
     Yaml(serde_yaml::Error),
     Json(serde_json::Error),
     Jwt(verder_helpen_jwt::Error),
+    Jose(JoseError),
+    Url(url::ParseError),
+    Io(std::io::Error),
+    Regex(regex::Error),
 }
 
 impl From<serde_yaml::Error> for Error {
@@ -34,10 +188,71 @@ impl From<verder_helpen_jwt::Error> for Error {
     }
 }
 
+impl From<JoseError> for Error {
+    fn from(e: JoseError) -> Error {
+        Error::Jose(e)
+    }
+}
+
+impl From<url::ParseError> for Error {
+    fn from(e: url::ParseError) -> Error {
+        Error::Url(e)
+    }
+}
+
+impl From<regex::Error> for Error {
+    fn from(e: regex::Error) -> Error {
+        Error::Regex(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::UnknownAttribute(a) => f.write_fmt(format_args!("Unknown attribute {a}")),
+            Error::InsecureKeyUrl(url) => {
+                f.write_fmt(format_args!("Key set url {url} must use https"))
+            }
+            Error::RemoteKey(url, e) => {
+                f.write_fmt(format_args!("Could not obtain key set of {url}: {e}"))
+            }
+            Error::KeyUnavailable(kid) => {
+                f.write_fmt(format_args!("Key {kid} is not available (yet)"))
+            }
+            Error::RequiresInsecureDevMode(option) => f.write_fmt(format_args!(
+                "{option} can only be enabled in insecure development mode"
+            )),
+            Error::RestartRequired(setting) => {
+                f.write_fmt(format_args!("Changing {setting} requires a restart"))
+            }
+            Error::InvalidAttributeId(id) => {
+                f.write_fmt(format_args!("Invalid irma attribute id {id}"))
+            }
+            Error::DuplicateAttributes(a) => {
+                f.write_fmt(format_args!("Duplicate attributes {}", a.join(", ")))
+            }
+            Error::ValueNotAllowed(a) => {
+                f.write_fmt(format_args!("Value not allowed for attribute {a}"))
+            }
+            Error::UnknownRequestorKey(id) => {
+                f.write_fmt(format_args!("Unknown requestor key {id}"))
+            }
+            Error::DisjunctionTooLarge(a, max) => f.write_fmt(format_args!(
+                "Attribute {a} maps to more than {max} irma attributes"
+            )),
+            Error::SelfTest(name, e) => {
+                f.write_fmt(format_args!("Self-test of key {name} failed: {e}"))
+            }
+            Error::UnsupportedKeyAlgorithm(name, alg) => f.write_fmt(format_args!(
+                "Key {name} uses key management algorithm {alg}, which is not supported for \
+                 result encryption"
+            )),
             Error::Yaml(e) => e.fmt(f),
             Error::NotMatching(desc) => f.write_str(desc),
             Error::InvalidResponse(desc) => {
@@ -45,6 +260,10 @@ impl Display for Error {
             }
             Error::Json(e) => e.fmt(f),
             Error::Jwt(e) => e.fmt(f),
+            Error::Jose(e) => e.fmt(f),
+            Error::Url(e) => e.fmt(f),
+            Error::Io(e) => e.fmt(f),
+            Error::Regex(e) => e.fmt(f),
         }
     }
 }
@@ -55,37 +274,566 @@ impl StdError for Error {
             Error::Yaml(e) => Some(e),
             Error::Json(e) => Some(e),
             Error::Jwt(e) => Some(e),
+            Error::Jose(e) => Some(e),
+            Error::SelfTest(_, e) => Some(e),
+            Error::RemoteKey(_, e) => Some(e),
+            Error::Url(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::Regex(e) => Some(e),
             _ => None,
         }
     }
 }
 
+fn default_discovery_refresh_interval() -> u64 {
+    60
+}
+
 #[derive(Deserialize, Debug)]
 struct IrmaserverConfig {
     url: String,
+    /// Url through which the app reaches the irma server, when it differs
+    /// from the url above
+    public_url: Option<String>,
     auth_token: Option<String>,
+    discovery: Option<super::irma::Discovery>,
+    #[serde(default = "default_discovery_refresh_interval")]
+    discovery_refresh_interval: u64,
+    circuit_breaker: Option<CircuitBreakerConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CircuitBreakerConfig {
+    /// Consecutive failures to reach the irma server after which calls are
+    /// suspended
+    failure_threshold: u32,
+    /// Seconds to suspend calls for before probing the irma server again
+    cooldown: u64,
 }
 
 impl From<IrmaserverConfig> for super::irma::IrmaServer {
     fn from(config: IrmaserverConfig) -> Self {
-        match config.auth_token {
+        let server = match config.auth_token {
             Some(token) => Self::new_with_auth(&config.url, &token),
             None => Self::new(&config.url),
+        };
+        let server = match config.discovery {
+            Some(discovery) => server.with_discovery(
+                discovery,
+                Duration::from_secs(config.discovery_refresh_interval),
+            ),
+            None => server,
+        };
+        match config.circuit_breaker {
+            Some(breaker) => server.with_circuit_breaker(
+                breaker.failure_threshold,
+                Duration::from_secs(breaker.cooldown),
+            ),
+            None => server,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum CallbackSignatureConfig {
+    SigningKey,
+    SharedSecret { secret: String },
+}
+
+enum CallbackSigner {
+    SigningKey,
+    SharedSecret(Box<dyn JwsSigner>),
+}
+
+impl std::fmt::Debug for CallbackSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CallbackSigner::SigningKey => f.write_str("SigningKey"),
+            // Don't leak the secret
+            CallbackSigner::SharedSecret(_) => f.write_str("SharedSecret"),
         }
     }
 }
 
+impl TryFrom<CallbackSignatureConfig> for CallbackSigner {
+    type Error = Error;
+
+    fn try_from(config: CallbackSignatureConfig) -> Result<CallbackSigner, Error> {
+        Ok(match config {
+            CallbackSignatureConfig::SigningKey => CallbackSigner::SigningKey,
+            CallbackSignatureConfig::SharedSecret { secret } => {
+                CallbackSigner::SharedSecret(Box::new(HS256.signer_from_bytes(secret)?))
+            }
+        })
+    }
+}
+
+/// Format disclosed values of an attribute must have
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AttributeFormatConfig {
+    /// Email address with a single `@` separating a non-empty local part
+    /// from a domain
+    Email,
+    /// Non-empty string of ascii digits
+    Numeric,
+    /// Regular expression the whole value must match
+    Regex { pattern: String },
+}
+
+/// Format of an attribute, described like it is configured
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AttributeFormat {
+    Email,
+    Numeric,
+    Regex {
+        pattern: String,
+        #[serde(skip)]
+        regex: regex::Regex,
+    },
+}
+
+impl TryFrom<AttributeFormatConfig> for AttributeFormat {
+    type Error = Error;
+
+    fn try_from(config: AttributeFormatConfig) -> Result<AttributeFormat, Error> {
+        Ok(match config {
+            AttributeFormatConfig::Email => AttributeFormat::Email,
+            AttributeFormatConfig::Numeric => AttributeFormat::Numeric,
+            // Anchor the pattern, so it has to match the value as a whole
+            AttributeFormatConfig::Regex { pattern } => AttributeFormat::Regex {
+                regex: regex::Regex::new(&format!("^(?:{})$", pattern))?,
+                pattern,
+            },
+        })
+    }
+}
+
+impl AttributeFormat {
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            AttributeFormat::Email => match value.split_once('@') {
+                Some((local, domain)) => {
+                    !local.is_empty()
+                        && !domain.is_empty()
+                        && !domain.contains('@')
+                        && !value.contains(char::is_whitespace)
+                }
+                None => false,
+            },
+            AttributeFormat::Numeric => {
+                !value.is_empty() && value.chars().all(|c| c.is_ascii_digit())
+            }
+            AttributeFormat::Regex { regex, .. } => regex.is_match(value),
+        }
+    }
+}
+
+/// Attributes mapped from an irma disclosure
+#[derive(Debug, Default)]
+pub struct MappedAttributes {
+    /// Disclosed value of each requested attribute
+    pub values: HashMap<String, String>,
+    /// Credential each requested attribute was disclosed from
+    pub credentials: HashMap<String, String>,
+}
+
+impl MappedAttributes {
+    /// Disclosed values grouped by the credential they were disclosed from
+    pub fn by_credential(&self) -> HashMap<String, HashMap<String, String>> {
+        let mut grouped: HashMap<String, HashMap<String, String>> = HashMap::new();
+        for (attribute, value) in &self.values {
+            if let Some(credential) = self.credentials.get(attribute) {
+                grouped
+                    .entry(credential.clone())
+                    .or_default()
+                    .insert(attribute.clone(), value.clone());
+            }
+        }
+        grouped
+    }
+}
+
+/// Keys for the cookie binding a browser to the session it started
+pub struct SessionBinding {
+    signer: Box<dyn JwsSigner>,
+    verifier: Box<dyn JwsVerifier>,
+}
+
+impl std::fmt::Debug for SessionBinding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Don't leak the secret
+        f.write_str("SessionBinding")
+    }
+}
+
+impl SessionBinding {
+    fn from_secret(secret: &str) -> Result<SessionBinding, Error> {
+        Ok(SessionBinding {
+            signer: Box::new(HS256.signer_from_bytes(secret)?),
+            verifier: Box::new(HS256.verifier_from_bytes(secret)?),
+        })
+    }
+
+    pub fn signer(&self) -> &dyn JwsSigner {
+        self.signer.as_ref()
+    }
+
+    pub fn verifier(&self) -> &dyn JwsVerifier {
+        self.verifier.as_ref()
+    }
+}
+
+/// Encoding of signed auth results
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ResultFormat {
+    /// Signed and encrypted nested jwt
+    #[default]
+    Jose,
+    /// Signed COSE_Sign1 structure
+    Cose,
+    /// Signed W3C verifiable presentation jwt
+    Vp,
+}
+
+/// Spelling of the success status in signed results
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StatusSpelling {
+    /// The misspelled "succes" older cores expect
+    #[default]
+    Legacy,
+    /// "success"
+    Standard,
+}
+
+impl StatusSpelling {
+    pub fn success(self) -> &'static str {
+        match self {
+            StatusSpelling::Legacy => "succes",
+            StatusSpelling::Standard => "success",
+        }
+    }
+
+    /// Whether the status is success in either spelling
+    pub fn is_success(status: &str) -> bool {
+        status == StatusSpelling::Legacy.success() || status == StatusSpelling::Standard.success()
+    }
+}
+
+/// How the signed parameters are handed to the irma ui
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum UiParamsHandoff {
+    /// The signed parameters form the query string of the ui url
+    #[default]
+    Query,
+    /// The ui receives a single-use session id in the `sid` query parameter
+    /// with which it fetches the signed parameters from `/params/<sid>`
+    SessionId,
+}
+
+/// When the irma app appends the session token to the return url of in-band
+/// sessions, which app versions handle differently
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AugmentReturnUrl {
+    #[default]
+    Always,
+    Never,
+    /// Only for sessions shown as QR code in the browser, not for sessions
+    /// opened in the app through a deep link
+    WebOnly,
+}
+
+/// Handling of attributes requested more than once
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateAttributes {
+    /// Request each attribute once, keeping the first occurrence
+    #[default]
+    Deduplicate,
+    /// Reject the request
+    Reject,
+}
+
+/// Whether the user has to disclose an attribute for the session to succeed
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Requirement {
+    /// The session fails unless the attribute is disclosed
+    #[default]
+    Required,
+    /// The app proposes disclosing the attribute, but the user can decline
+    Preferred,
+    /// The app proposes not disclosing the attribute, but the user can
+    /// choose to disclose it
+    Optional,
+}
+
+/// Handling of disclosures lacking requested attributes, which the irma server
+/// reports with the MISSING_ATTRIBUTES proof status
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingAttributes {
+    /// Fail the session like any other invalid proof
+    #[default]
+    Fail,
+    /// Redirect back to the continuation with `error=missing_attributes`,
+    /// so the requestor can tell the user to obtain the missing credential
+    Report,
+}
+
+fn default_maintenance_retry_after() -> u64 {
+    300
+}
+
+fn default_irma_retry_after() -> u64 {
+    5
+}
+
+fn default_jwks_refresh_interval() -> u64 {
+    3600
+}
+
+fn default_irma_probe_interval() -> u64 {
+    30
+}
+
+fn default_irma_request_timeout() -> u64 {
+    30
+}
+
+fn default_clock_skew_threshold() -> u64 {
+    30
+}
+
+fn default_start_request_limit() -> u64 {
+    16 * 1024
+}
+
+fn default_ui_token_parameter() -> String {
+    "token".to_string()
+}
+
+fn default_log_redact_patterns() -> Vec<String> {
+    // BSNs are nine digit numbers
+    vec![r"\b[0-9]{9}\b".to_string()]
+}
+
+fn default_log_redact_replacement() -> String {
+    "[REDACTED]".to_string()
+}
+
+fn default_max_disjunction_size() -> usize {
+    32
+}
+
+#[cfg(feature = "mapping-cache")]
+fn default_mapping_cache_size() -> usize {
+    256
+}
+
+fn default_jwt_issuer() -> String {
+    "auth-irma".to_string()
+}
+
+fn default_result_validity() -> u64 {
+    5 * 60
+}
+
+fn default_result_reference_ttl() -> u64 {
+    60
+}
+
+fn default_result_reference_max_results() -> usize {
+    10000
+}
+
+fn default_retention_max_results() -> usize {
+    10000
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ResultRetentionConfig {
+    /// Number of seconds results remain retrievable
+    period: u64,
+    #[serde(default = "default_retention_max_results")]
+    max_results: usize,
+    /// API key the core uses to retrieve retained results
+    api_key: String,
+}
+
+impl ResultRetentionConfig {
+    pub fn period(&self) -> Duration {
+        Duration::from_secs(self.period)
+    }
+
+    pub fn max_results(&self) -> usize {
+        self.max_results
+    }
+
+    pub fn api_key(&self) -> &str {
+        &self.api_key
+    }
+}
+
+/// Limits on the sessions started on behalf of a single requestor
+#[derive(Deserialize, Debug)]
+pub struct RequestorQuota {
+    sessions_per_minute: Option<u32>,
+    max_concurrent_sessions: Option<u32>,
+}
+
+impl RequestorQuota {
+    /// Maximum number of sessions started per minute
+    pub fn sessions_per_minute(&self) -> Option<u32> {
+        self.sessions_per_minute
+    }
+
+    /// Maximum number of sessions in progress at the same time
+    pub fn max_concurrent_sessions(&self) -> Option<u32> {
+        self.max_concurrent_sessions
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct TestModeConfig {
+    /// Canned values returned for each attribute
+    attributes: HashMap<String, String>,
+}
+
 #[derive(Deserialize, Debug)]
 struct RawConfig {
     server_url: String,
     internal_url: String,
     #[cfg(feature = "sentry")]
     sentry_dsn: Option<String>,
+    #[serde(default)]
+    insecure_dev_mode: bool,
+    #[serde(default)]
+    debug_errors: bool,
+    #[serde(default)]
+    allow_insecure_urls: bool,
+    #[serde(default)]
+    insecure_url_hosts: Vec<String>,
+    test_mode: Option<TestModeConfig>,
+    admin_api_key: Option<String>,
+    #[serde(default)]
+    maintenance_mode: bool,
+    #[serde(default = "default_maintenance_retry_after")]
+    maintenance_retry_after: u64,
+    #[serde(default = "default_irma_retry_after")]
+    irma_retry_after: u64,
+    #[serde(default = "default_irma_request_timeout")]
+    irma_request_timeout: u64,
+    #[serde(default = "default_irma_probe_interval")]
+    irma_probe_interval: u64,
+    #[serde(default)]
+    health_key_self_test: bool,
+    #[serde(default = "default_start_request_limit")]
+    start_request_limit: u64,
+    #[serde(default)]
+    coalesce_starts: bool,
     ui_irma_url: String,
-    attributes: AttributeMapping,
+    #[serde(default = "default_ui_token_parameter")]
+    ui_token_parameter: String,
+    #[serde(default)]
+    ui_params_handoff: UiParamsHandoff,
+    #[serde(default)]
+    augment_return_url: AugmentReturnUrl,
+    #[serde(default)]
+    result_in_fragment: bool,
+    #[serde(default)]
+    result_by_reference: bool,
+    #[serde(default = "default_result_reference_ttl")]
+    result_reference_ttl: u64,
+    #[serde(default = "default_result_reference_max_results")]
+    result_reference_max_results: usize,
+    result_retention: Option<ResultRetentionConfig>,
+    #[serde(default = "default_jwt_issuer")]
+    jwt_issuer: String,
+    jwt_audience: Option<String>,
+    #[serde(default = "default_result_validity")]
+    result_validity: u64,
+    #[serde(default)]
+    result_not_before: bool,
+    #[serde(default)]
+    include_auth_time: bool,
+    #[serde(default)]
+    bind_destination: bool,
+    #[serde(default)]
+    group_by_credential: bool,
+    #[serde(default)]
+    track_result_ids: bool,
+    #[serde(default)]
+    result_format: ResultFormat,
+    #[serde(default)]
+    vc_contexts: Vec<String>,
+    #[serde(default)]
+    vc_types: Vec<String>,
+    #[serde(default)]
+    compress_results: bool,
+    #[serde(default)]
+    result_status_spelling: StatusSpelling,
+    callback_content_type: Option<String>,
+    event_webhook_url: Option<String>,
+    audit_log: Option<String>,
+    #[serde(default)]
+    audit_clock_skew: bool,
+    #[serde(default = "default_clock_skew_threshold")]
+    clock_skew_threshold: u64,
+    max_session_age: Option<u64>,
+    #[serde(default)]
+    duplicate_attributes: DuplicateAttributes,
+    #[serde(default)]
+    missing_attributes: MissingAttributes,
+    #[serde(default)]
+    report_failures: bool,
+    presence_only_attribute: Option<String>,
+    #[serde(default)]
+    hide_attribute_ids: bool,
+    #[serde(default)]
+    attribute_formats: HashMap<String, AttributeFormatConfig>,
+    #[serde(default)]
+    attribute_requirements: HashMap<String, Requirement>,
+    #[serde(default)]
+    allowed_attribute_values: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    claim_names: HashMap<String, String>,
+    #[serde(default = "default_max_disjunction_size")]
+    max_disjunction_size: usize,
+    #[cfg(feature = "mapping-cache")]
+    #[serde(default = "default_mapping_cache_size")]
+    mapping_cache_size: usize,
+    session_pointer_host: Option<String>,
+    #[serde(default)]
+    session_pointer_hosts: Vec<String>,
+    #[serde(default = "default_log_redact_patterns")]
+    log_redact_patterns: Vec<String>,
+    #[serde(default = "default_log_redact_replacement")]
+    log_redact_replacement: String,
+    attributes: RawAttributeMapping,
     irma_server: IrmaserverConfig,
-    encryption_pubkey: EncryptionKeyConfig,
-    signing_privkey: SignKeyConfig,
+    encryption_pubkey: EncryptionKeySource,
+    encryption_key_id: Option<String>,
+    audit_encryption_pubkey: Option<EncryptionKeyConfig>,
+    #[serde(default)]
+    requestor_keys: HashMap<String, EncryptionKeySource>,
+    #[serde(default)]
+    requestor_quotas: HashMap<String, RequestorQuota>,
+    #[serde(default = "default_jwks_refresh_interval")]
+    jwks_refresh_interval: u64,
+    jwks_cache_dir: Option<PathBuf>,
+    // Kept as plain value, from which both the signer and the published
+    // public key are derived
+    signing_privkey: serde_json::Value,
+    signing_key_id: Option<String>,
+    callback_signature: Option<CallbackSignatureConfig>,
+    session_binding_secret: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -95,11 +843,78 @@ pub struct Config {
     internal_url: String,
     #[cfg(feature = "sentry")]
     sentry_dsn: Option<String>,
-    ui_irma_url: String,
+    test_mode: Option<TestModeConfig>,
+    debug_errors: bool,
+    allow_insecure_urls: bool,
+    insecure_url_hosts: Vec<String>,
+    admin_api_key: Option<String>,
+    maintenance_mode: bool,
+    maintenance_retry_after: Duration,
+    irma_retry_after: Duration,
+    irma_request_timeout: Duration,
+    irma_probe_interval: Option<Duration>,
+    health_key_self_test: bool,
+    start_request_limit: u64,
+    coalesce_starts: bool,
+    ui_irma_url: Url,
+    ui_token_parameter: String,
+    ui_params_handoff: UiParamsHandoff,
+    augment_return_url: AugmentReturnUrl,
+    result_in_fragment: bool,
+    result_by_reference: bool,
+    result_reference_ttl: Duration,
+    result_reference_max_results: usize,
+    result_retention: Option<ResultRetentionConfig>,
+    jwt_issuer: String,
+    jwt_audience: Option<String>,
+    result_validity: Duration,
+    result_not_before: bool,
+    include_auth_time: bool,
+    bind_destination: bool,
+    group_by_credential: bool,
+    track_result_ids: bool,
+    result_format: ResultFormat,
+    vc_contexts: Vec<String>,
+    vc_types: Vec<String>,
+    compress_results: bool,
+    result_status_spelling: StatusSpelling,
+    callback_content_type: Option<String>,
+    event_webhook_url: Option<String>,
+    audit_log: Option<String>,
+    audit_clock_skew: bool,
+    clock_skew_threshold: Option<Duration>,
+    max_session_age: Option<Duration>,
+    duplicate_attributes: DuplicateAttributes,
+    missing_attributes: MissingAttributes,
+    report_failures: bool,
+    presence_only_attribute: Option<super::irma::AttributeId>,
+    hide_attribute_ids: bool,
+    attribute_formats: HashMap<String, AttributeFormat>,
+    attribute_requirements: HashMap<String, Requirement>,
+    allowed_attribute_values: HashMap<String, Vec<String>>,
+    claim_names: HashMap<String, String>,
+    #[cfg(feature = "mapping-cache")]
+    mapping_cache: super::mapping_cache::MappingCache,
+    session_pointer_host: Option<String>,
+    session_pointer_hosts: Vec<String>,
+    // Hosts session pointers may refer to
+    pointer_hosts: Vec<String>,
+    redactor: super::redact::Redactor,
     attributes: AttributeMapping,
     irma_server: super::irma::IrmaServer,
-    encrypter: Box<dyn JweEncrypter>,
+    encrypter: ResultKey,
+    encryption_key_id: Option<String>,
+    audit_encrypter: Option<Box<dyn JweEncrypter>>,
+    requestor_encrypters: HashMap<String, ResultKey>,
+    requestor_quotas: HashMap<String, RequestorQuota>,
+    jwks_refresh_interval: Duration,
+    jwks_cache_dir: Option<PathBuf>,
     signer: Box<dyn JwsSigner>,
+    signing_key_id: Option<String>,
+    signing_verifier: Option<Box<dyn JwsVerifier>>,
+    jwks: Option<super::jwks::Jwks>,
+    callback_signer: Option<CallbackSigner>,
+    session_binding: Option<SessionBinding>,
 }
 
 // This try_from will no longer be needed once support for field try_from lands
@@ -108,22 +923,377 @@ impl TryFrom<RawConfig> for Config {
     type Error = Error;
 
     fn try_from(config: RawConfig) -> Result<Config, Error> {
-        Ok(Config {
+        if config.test_mode.is_some() && !config.insecure_dev_mode {
+            return Err(Error::RequiresInsecureDevMode("test_mode"));
+        }
+        if config.debug_errors && !config.insecure_dev_mode {
+            return Err(Error::RequiresInsecureDevMode("debug_errors"));
+        }
+        if config.allow_insecure_urls && !config.insecure_dev_mode {
+            return Err(Error::RequiresInsecureDevMode("allow_insecure_urls"));
+        }
+        let known_attributes = &config.attributes;
+        if let Some((attribute, _)) = known_attributes
+            .iter()
+            .find(|(_, ids)| ids.len() > config.max_disjunction_size)
+        {
+            return Err(Error::DisjunctionTooLarge(
+                attribute.clone(),
+                config.max_disjunction_size,
+            ));
+        }
+        let attribute_formats = config
+            .attribute_formats
+            .into_iter()
+            .map(|(attribute, format)| {
+                if !known_attributes.contains_key(&attribute) {
+                    return Err(Error::UnknownAttribute(attribute));
+                }
+                Ok((attribute, AttributeFormat::try_from(format)?))
+            })
+            .collect::<Result<_, Error>>()?;
+        if let Some(attribute) = config
+            .attribute_requirements
+            .keys()
+            .find(|attribute| !known_attributes.contains_key(*attribute))
+        {
+            return Err(Error::UnknownAttribute(attribute.clone()));
+        }
+        if let Some(attribute) = config
+            .allowed_attribute_values
+            .keys()
+            .find(|attribute| !known_attributes.contains_key(*attribute))
+        {
+            return Err(Error::UnknownAttribute(attribute.clone()));
+        }
+        if let Some(attribute) = config
+            .claim_names
+            .keys()
+            .find(|attribute| !known_attributes.contains_key(*attribute))
+        {
+            return Err(Error::UnknownAttribute(attribute.clone()));
+        }
+
+        let pointer_hosts = [
+            Some(config.irma_server.url.as_str()),
+            config.irma_server.public_url.as_deref(),
+            Some(config.server_url.as_str()),
+            config.session_pointer_host.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .chain(config.session_pointer_hosts.iter().map(String::as_str))
+        .filter_map(host_of)
+        .collect();
+
+        let (signer, public_key): (Box<dyn JwsSigner>, _) =
+            match serde_json::from_value(config.signing_privkey.clone())? {
+                SigningKeySource::Inline(key) => (
+                    Box::<dyn JwsSigner>::try_from(key)?,
+                    super::jwks::public_signing_key(&config.signing_privkey)?,
+                ),
+                SigningKeySource::Remote { remote } => {
+                    if Url::parse(&remote.url)?.scheme() != "https" && !config.allow_insecure_urls {
+                        return Err(Error::InsecureKeyUrl(remote.url));
+                    }
+                    let public_key = remote.public_key()?;
+                    (
+                        Box::new(super::remote_signer::RemoteSigner::try_from(remote)?),
+                        Some(public_key),
+                    )
+                }
+            };
+        // Quotas apply to the requestors selected through their key id
+        if let Some(id) = config
+            .requestor_quotas
+            .keys()
+            .find(|id| !config.requestor_keys.contains_key(*id))
+        {
+            return Err(Error::UnknownRequestorKey(id.clone()));
+        }
+
+        let signing_verifier = public_key
+            .as_ref()
+            .map(|jwk| super::jwks::verifier_from_jwk(signer.algorithm().name(), jwk))
+            .transpose()?;
+        let jwks = public_key.map(|jwk| {
+            super::jwks::Jwks::from_public_key(
+                jwk,
+                signer.as_ref(),
+                config.signing_key_id.as_deref().or_else(|| signer.key_id()),
+            )
+        });
+
+        let config = Config {
             server_url: config.server_url,
             internal_url: config.internal_url,
             #[cfg(feature = "sentry")]
             sentry_dsn: config.sentry_dsn,
-            ui_irma_url: config.ui_irma_url,
-            attributes: config.attributes,
+            test_mode: config.test_mode,
+            debug_errors: config.debug_errors,
+            allow_insecure_urls: config.allow_insecure_urls,
+            insecure_url_hosts: config
+                .insecure_url_hosts
+                .iter()
+                .map(|host| host.to_ascii_lowercase())
+                .collect(),
+            admin_api_key: config.admin_api_key,
+            maintenance_mode: config.maintenance_mode,
+            maintenance_retry_after: Duration::from_secs(config.maintenance_retry_after),
+            irma_retry_after: Duration::from_secs(config.irma_retry_after),
+            irma_request_timeout: Duration::from_secs(config.irma_request_timeout),
+            irma_probe_interval: Some(config.irma_probe_interval)
+                .filter(|interval| *interval > 0)
+                .map(Duration::from_secs),
+            health_key_self_test: config.health_key_self_test,
+            start_request_limit: config.start_request_limit,
+            coalesce_starts: config.coalesce_starts,
+            ui_irma_url: Url::parse(&config.ui_irma_url)?,
+            ui_token_parameter: config.ui_token_parameter,
+            ui_params_handoff: config.ui_params_handoff,
+            augment_return_url: config.augment_return_url,
+            result_in_fragment: config.result_in_fragment,
+            result_by_reference: config.result_by_reference,
+            result_reference_ttl: Duration::from_secs(config.result_reference_ttl),
+            result_reference_max_results: config.result_reference_max_results,
+            result_retention: config.result_retention,
+            jwt_issuer: config.jwt_issuer,
+            jwt_audience: config.jwt_audience,
+            result_validity: Duration::from_secs(config.result_validity),
+            result_not_before: config.result_not_before,
+            include_auth_time: config.include_auth_time,
+            bind_destination: config.bind_destination,
+            group_by_credential: config.group_by_credential,
+            track_result_ids: config.track_result_ids,
+            result_format: config.result_format,
+            vc_contexts: config.vc_contexts,
+            vc_types: config.vc_types,
+            compress_results: config.compress_results,
+            result_status_spelling: config.result_status_spelling,
+            callback_content_type: config.callback_content_type,
+            event_webhook_url: config.event_webhook_url,
+            audit_log: config.audit_log,
+            audit_clock_skew: config.audit_clock_skew,
+            clock_skew_threshold: Some(config.clock_skew_threshold)
+                .filter(|threshold| *threshold > 0)
+                .map(Duration::from_secs),
+            max_session_age: config.max_session_age.map(Duration::from_secs),
+            duplicate_attributes: config.duplicate_attributes,
+            missing_attributes: config.missing_attributes,
+            report_failures: config.report_failures,
+            presence_only_attribute: config
+                .presence_only_attribute
+                .map(|id| super::irma::AttributeId::parse(&id).ok_or(Error::InvalidAttributeId(id)))
+                .transpose()?,
+            hide_attribute_ids: config.hide_attribute_ids,
+            attribute_formats,
+            attribute_requirements: config.attribute_requirements,
+            allowed_attribute_values: config.allowed_attribute_values,
+            claim_names: config.claim_names,
+            #[cfg(feature = "mapping-cache")]
+            mapping_cache: super::mapping_cache::MappingCache::new(config.mapping_cache_size),
+            session_pointer_host: config.session_pointer_host,
+            session_pointer_hosts: config.session_pointer_hosts,
+            pointer_hosts,
+            redactor: super::redact::Redactor::new(
+                &config.log_redact_patterns,
+                config.log_redact_replacement,
+            )?,
+            attributes: parse_attribute_mapping(config.attributes)?,
             irma_server: super::irma::IrmaServer::from(config.irma_server),
-            encrypter: Box::<dyn JweEncrypter>::try_from(config.encryption_pubkey)?,
-            signer: Box::<dyn JwsSigner>::try_from(config.signing_privkey)?,
-        })
+            encrypter: ResultKey::from_source(
+                "encryption_pubkey",
+                config.encryption_pubkey,
+                config.allow_insecure_urls,
+            )?,
+            encryption_key_id: config.encryption_key_id,
+            audit_encrypter: config
+                .audit_encryption_pubkey
+                .map(|key| public_key_encrypter("audit_encryption_pubkey", key))
+                .transpose()?,
+            requestor_encrypters: config
+                .requestor_keys
+                .into_iter()
+                .map(|(id, key)| {
+                    let name = format!("requestor_keys.{id}");
+                    let key = ResultKey::from_source(&name, key, config.allow_insecure_urls)?;
+                    Ok((id, key))
+                })
+                .collect::<Result<_, Error>>()?,
+            requestor_quotas: config.requestor_quotas,
+            jwks_refresh_interval: Duration::from_secs(config.jwks_refresh_interval),
+            jwks_cache_dir: config.jwks_cache_dir,
+            signer,
+            signing_key_id: config.signing_key_id,
+            signing_verifier,
+            jwks,
+            callback_signer: config
+                .callback_signature
+                .map(CallbackSigner::try_from)
+                .transpose()?,
+            session_binding: config
+                .session_binding_secret
+                .as_deref()
+                .map(SessionBinding::from_secret)
+                .transpose()?,
+        };
+        config.self_test()?;
+        Ok(config)
     }
 }
 
 impl Config {
+    /// Sign and encrypt a dummy payload with every configured key, so keys
+    /// that don't work with their algorithm are found when loading the
+    /// configuration instead of when the first result is encrypted. The
+    /// signature is verified with the public half of the signing key, when
+    /// its type is supported. Without the private keys of the encryption
+    /// keys, decryption is not verified.
+    pub fn self_test(&self) -> Result<(), Error> {
+        let mut payload = JwtPayload::new();
+        payload.set_claim("self_test", Some(true.into()))?;
+
+        let signed = jwt::encode_with_signer(&payload, &JwsHeader::new(), self.signer())
+            .map_err(|e| Error::SelfTest("signing_privkey".to_string(), e))?;
+        if let Some(verifier) = self.signing_verifier() {
+            jwt::decode_with_verifier(&signed, verifier)
+                .map_err(|e| Error::SelfTest("signing_privkey".to_string(), e))?;
+        }
+
+        // Cose results only support wrapping their content key with RSA-OAEP
+        if self.result_format == ResultFormat::Cose {
+            for (name, encrypter) in self.static_result_encrypters() {
+                let algorithm = encrypter.algorithm().name();
+                if !super::cose::supports_key_management(algorithm) {
+                    return Err(Error::UnsupportedKeyAlgorithm(name, algorithm.to_string()));
+                }
+            }
+        }
+
+        // Keys from key sets are tested when their key set is fetched
+        let encrypters = self.static_result_encrypters().chain(
+            self.audit_encrypter()
+                .map(|encrypter| ("audit_encryption_pubkey".to_string(), encrypter)),
+        );
+        for (name, encrypter) in encrypters {
+            jwt::encode_with_encrypter(&payload, &JweHeader::new(), encrypter)
+                .map_err(|e| Error::SelfTest(name, e))?;
+        }
+        Ok(())
+    }
+
+    // Result keys configured as keys rather than key sets, with their names
+    fn static_result_encrypters(&self) -> impl Iterator<Item = (String, &dyn JweEncrypter)> {
+        std::iter::once(("encryption_pubkey".to_string(), &self.encrypter))
+            .chain(
+                self.requestor_encrypters
+                    .iter()
+                    .map(|(id, key)| (format!("requestor_keys.{id}"), key)),
+            )
+            .filter_map(|(name, key)| match key {
+                ResultKey::Static(encrypter) => Some((name, encrypter.as_ref())),
+                ResultKey::Remote(_) => None,
+            })
+    }
+
+    /// Apply the configured policy for duplicate attributes to a requested
+    /// list of attributes. The result should be used for both requesting and
+    /// mapping the response, to keep the two aligned.
+    pub fn normalize_attributes(&self, attributes: &[String]) -> Result<Vec<String>, Error> {
+        let mut result: Vec<String> = vec![];
+        let mut duplicates: Vec<String> = vec![];
+        for attribute in attributes {
+            if !result.contains(attribute) {
+                result.push(attribute.clone());
+            } else if !duplicates.contains(attribute) {
+                duplicates.push(attribute.clone());
+            }
+        }
+
+        if !duplicates.is_empty() && self.duplicate_attributes == DuplicateAttributes::Reject {
+            return Err(Error::DuplicateAttributes(duplicates));
+        }
+        Ok(result)
+    }
+
     pub fn map_attributes(&self, attributes: &[String]) -> Result<crate::irma::ConDisCon, Error> {
+        #[cfg(feature = "mapping-cache")]
+        {
+            if let Some(mapped) = self.mapping_cache.get(attributes) {
+                return Ok(mapped);
+            }
+            let mapped = self.map_attributes_uncached(attributes)?;
+            self.mapping_cache.insert(attributes, mapped.clone());
+            Ok(mapped)
+        }
+        #[cfg(not(feature = "mapping-cache"))]
+        self.map_attributes_uncached(attributes)
+    }
+
+    /// Map requested attributes to a disclosure request in which some of
+    /// them have to be disclosed with a given value. Only values allowlisted
+    /// for the attribute can be required.
+    pub fn map_attributes_with_values(
+        &self,
+        attributes: &[String],
+        values: &HashMap<String, String>,
+    ) -> Result<crate::irma::ConDisCon, Error> {
+        if values.is_empty() {
+            return self.map_attributes(attributes);
+        }
+        for (attribute, value) in values {
+            let allowed = attributes.contains(attribute)
+                && self
+                    .allowed_attribute_values
+                    .get(attribute)
+                    .is_some_and(|allowed| allowed.contains(value));
+            if !allowed {
+                return Err(Error::ValueNotAllowed(attribute.clone()));
+            }
+        }
+
+        // The irma server only accepts disclosures of the required value, so
+        // responses are mapped as usual
+        let mapped = self.map_attributes_uncached(attributes)?;
+        Ok(attributes
+            .iter()
+            .zip(mapped)
+            .map(|(attribute, dis)| match values.get(attribute) {
+                Some(value) => dis
+                    .into_iter()
+                    .map(|con| {
+                        con.into_iter()
+                            .map(|irma_attribute| match irma_attribute {
+                                super::irma::Attribute::Simple(id)
+                                | super::irma::Attribute::WithValue { id, .. } => {
+                                    super::irma::Attribute::WithValue {
+                                        id,
+                                        value: value.clone(),
+                                    }
+                                }
+                            })
+                            .collect()
+                    })
+                    .collect(),
+                None => dis,
+            })
+            .collect())
+    }
+
+    fn map_attributes_uncached(
+        &self,
+        attributes: &[String],
+    ) -> Result<crate::irma::ConDisCon, Error> {
+        // The irma server does not accept empty disclosure requests, so
+        // sessions without attributes disclose a single, configured attribute
+        if attributes.is_empty() {
+            if let Some(presence_only_attribute) = &self.presence_only_attribute {
+                return Ok(vec![vec![vec![super::irma::Attribute::Simple(
+                    presence_only_attribute.as_str().to_string(),
+                )]]]);
+            }
+        }
+
         let mut result: super::irma::ConDisCon = vec![];
         for attribute in attributes {
             let mut dis: Vec<Vec<super::irma::Attribute>> = vec![];
@@ -133,27 +1303,132 @@ impl Config {
                 .ok_or_else(|| Error::UnknownAttribute(attribute.clone()))?
             {
                 dis.push(vec![super::irma::Attribute::Simple(
-                    request_attribute.clone(),
+                    request_attribute.as_str().to_string(),
                 )]);
             }
+            // An empty inner conjunction lets the user disclose nothing, and
+            // the app proposes the first option of a disjunction
+            match self.requirement(attribute) {
+                Requirement::Required => {}
+                Requirement::Preferred => dis.push(vec![]),
+                Requirement::Optional => dis.insert(0, vec![]),
+            }
             result.push(dis);
         }
         Ok(result)
     }
 
+    /// Configured attributes with the irma attributes they can be disclosed
+    /// from
+    pub fn attributes(&self) -> impl Iterator<Item = (&str, &[super::irma::AttributeId])> {
+        self.attributes
+            .iter()
+            .map(|(attribute, ids)| (attribute.as_str(), ids.as_slice()))
+    }
+
+    /// Whether an attribute has to be disclosed, which it has unless
+    /// configured otherwise
+    pub fn requirement(&self, attribute: &str) -> Requirement {
+        self.attribute_requirements
+            .get(attribute)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Format disclosed values of an attribute must have, if any
+    pub fn attribute_format(&self, attribute: &str) -> Option<&AttributeFormat> {
+        self.attribute_formats.get(attribute)
+    }
+
+    /// Key under which an attribute appears in results, which defaults to
+    /// the attribute name
+    pub fn claim_name<'a>(&'a self, attribute: &'a str) -> &'a str {
+        self.claim_names
+            .get(attribute)
+            .map_or(attribute, String::as_str)
+    }
+
+    /// Whether the irma attribute ids should be kept out of public listings
+    pub fn hide_attribute_ids(&self) -> bool {
+        self.hide_attribute_ids
+    }
+
+    /// Host the session pointer should refer to, given the host through which
+    /// the browser reached us. That host is only used when allowlisted,
+    /// falling back to the configured host.
+    pub fn session_pointer_host<'a>(&'a self, request_host: Option<&'a str>) -> Option<&'a str> {
+        request_host
+            .filter(|request_host| {
+                self.session_pointer_hosts
+                    .iter()
+                    .any(|host| host.eq_ignore_ascii_case(request_host))
+            })
+            .or(self.session_pointer_host.as_deref())
+    }
+
+    /// Hosts of the irma server, this plugin and the session pointer
+    /// overrides, which are the only hosts session pointers may refer to
+    pub fn pointer_hosts(&self) -> &[String] {
+        &self.pointer_hosts
+    }
+
+    pub fn missing_attributes(&self) -> MissingAttributes {
+        self.missing_attributes
+    }
+
+    /// Whether failed in-band sessions redirect to the continuation with the
+    /// reason of the failure
+    pub fn report_failures(&self) -> bool {
+        self.report_failures
+    }
+
+    /// Redactor to apply to error messages before they are logged
+    pub fn redactor(&self) -> &super::redact::Redactor {
+        &self.redactor
+    }
+
+    /// Whether sessions without any requested attributes are allowed
+    pub fn allows_presence_only(&self) -> bool {
+        self.presence_only_attribute.is_some()
+    }
+
     pub fn map_response(
         &self,
         attributes: &[String],
         response: crate::irma::IrmaResult,
-    ) -> Result<HashMap<String, String>, Error> {
-        if attributes.len() != response.disclosed.len() {
-            return Err(Error::NotMatching("mismatch between request and response"));
+    ) -> Result<MappedAttributes, Error> {
+        // Presence-only sessions succeed with an empty set of attributes,
+        // as long as the configured attribute was disclosed
+        if attributes.is_empty() {
+            if let Some(presence_only_attribute) = &self.presence_only_attribute {
+                let disclosed = response
+                    .disclosure_round(0, 1)
+                    .ok_or(Error::NotMatching("mismatch between request and response"))?;
+                if disclosed[0].len() != 1 || disclosed[0][0].id != presence_only_attribute.as_str()
+                {
+                    return Err(Error::InvalidResponse(
+                        "Incorrect attribute in presence-only session",
+                    ));
+                }
+                return Ok(MappedAttributes::default());
+            }
         }
 
-        let mut result: HashMap<String, String> = HashMap::new();
+        // Only the first disclosure round corresponds to the attributes we
+        // requested, any further rounds stem from chained sessions and are
+        // ignored here.
+        let disclosed = response
+            .disclosure_round(0, attributes.len())
+            .ok_or(Error::NotMatching("mismatch between request and response"))?;
+
+        let mut result = MappedAttributes::default();
 
-        for (i, attribute) in attributes.iter().enumerate() {
-            if response.disclosed[i].len() != 1 {
+        for (attribute, conjunction) in attributes.iter().zip(disclosed) {
+            // Declined attributes are left out of the result
+            if conjunction.is_empty() && self.requirement(attribute) != Requirement::Required {
+                continue;
+            }
+            if conjunction.len() != 1 {
                 return Err(Error::InvalidResponse(
                     "Incorrect number of attributes in inner conjunction",
                 ));
@@ -162,12 +1437,27 @@ impl Config {
                 .attributes
                 .get(attribute)
                 .ok_or_else(|| Error::UnknownAttribute(attribute.clone()))?;
-            if !allowed_irma_attributes.contains(&response.disclosed[i][0].id) {
-                return Err(Error::InvalidResponse(
+            let irma_attribute = allowed_irma_attributes
+                .iter()
+                .find(|allowed| allowed.as_str() == conjunction[0].id)
+                .ok_or(Error::InvalidResponse(
                     "Incorrect attribute in inner conjunction",
-                ));
+                ))?;
+            if let Some(format) = self.attribute_formats.get(attribute) {
+                if !format.matches(&conjunction[0].rawvalue) {
+                    return Err(Error::InvalidResponse(
+                        "Attribute value does not match the configured format",
+                    ));
+                }
             }
-            result.insert(attribute.clone(), response.disclosed[i][0].rawvalue.clone());
+            let claim_name = self.claim_name(attribute);
+            result
+                .values
+                .insert(claim_name.to_string(), conjunction[0].rawvalue.clone());
+            result.credentials.insert(
+                claim_name.to_string(),
+                irma_attribute.credential().to_string(),
+            );
         }
 
         Ok(result)
@@ -177,6 +1467,29 @@ impl Config {
         &self.irma_server
     }
 
+    /// Replace the configured irma server, used to point the plugin at a
+    /// mock irma server during development
+    #[cfg(feature = "mock-irma")]
+    pub fn set_irma_server(&mut self, url: &str) {
+        self.pointer_hosts.extend(host_of(url));
+        self.irma_server = super::irma::IrmaServer::new(url);
+    }
+
+    /// Whether responses to internal errors include the error details
+    pub fn debug_errors(&self) -> bool {
+        self.debug_errors
+    }
+
+    /// Whether attr_urls and continuations may use plain http
+    pub fn allow_insecure_urls(&self) -> bool {
+        self.allow_insecure_urls
+    }
+
+    /// Hosts besides loopback that may receive results over plain http
+    pub fn insecure_url_hosts(&self) -> &[String] {
+        &self.insecure_url_hosts
+    }
+
     pub fn server_url(&self) -> &str {
         &self.server_url
     }
@@ -190,23 +1503,419 @@ impl Config {
         self.sentry_dsn.as_deref()
     }
 
-    pub fn ui_irma_url(&self) -> &str {
+    /// Whether sessions complete with canned values instead of using irma
+    pub fn test_mode_enabled(&self) -> bool {
+        self.test_mode.is_some()
+    }
+
+    /// Canned value for an attribute in test mode
+    pub fn test_mode_value(&self, attribute: &str) -> Option<&str> {
+        self.test_mode
+            .as_ref()?
+            .attributes
+            .get(attribute)
+            .map(String::as_str)
+    }
+
+    /// API key operators use to access the admin endpoints
+    pub fn admin_api_key(&self) -> Option<&str> {
+        self.admin_api_key.as_deref()
+    }
+
+    /// Whether to start in maintenance mode
+    pub fn maintenance_mode(&self) -> bool {
+        self.maintenance_mode
+    }
+
+    pub fn maintenance_retry_after(&self) -> Duration {
+        self.maintenance_retry_after
+    }
+
+    /// Time after which to retry starting a session when the irma server is
+    /// unreachable
+    pub fn irma_retry_after(&self) -> Duration {
+        self.irma_retry_after
+    }
+
+    /// Deadline for requests waiting on the irma server or on result
+    /// callbacks, after which they fail with 504
+    pub fn irma_request_timeout(&self) -> Duration {
+        self.irma_request_timeout
+    }
+
+    /// Interval at which the irma server is probed in the background, if
+    /// probing is enabled
+    pub fn irma_probe_interval(&self) -> Option<Duration> {
+        self.irma_probe_interval
+    }
+
+    /// Whether /health runs the key self-test on every request
+    pub fn health_key_self_test(&self) -> bool {
+        self.health_key_self_test
+    }
+
+    /// Maximum size in bytes of start request bodies
+    pub fn start_request_limit(&self) -> u64 {
+        self.start_request_limit
+    }
+
+    /// Whether concurrent start requests with the same idempotency key share
+    /// a single irma session
+    pub fn coalesce_starts(&self) -> bool {
+        self.coalesce_starts
+    }
+
+    pub fn ui_irma_url(&self) -> &Url {
         &self.ui_irma_url
     }
 
-    pub fn encrypter(&self) -> &dyn JweEncrypter {
-        self.encrypter.as_ref()
+    /// Name of the query parameter carrying the signed parameters to the ui
+    pub fn ui_token_parameter(&self) -> &str {
+        &self.ui_token_parameter
+    }
+
+    pub fn ui_params_handoff(&self) -> UiParamsHandoff {
+        self.ui_params_handoff
+    }
+
+    pub fn augment_return_url(&self) -> AugmentReturnUrl {
+        self.augment_return_url
+    }
+
+    pub fn result_in_fragment(&self) -> bool {
+        self.result_in_fragment
+    }
+
+    pub fn result_by_reference(&self) -> bool {
+        self.result_by_reference
+    }
+
+    pub fn result_reference_ttl(&self) -> Duration {
+        self.result_reference_ttl
+    }
+
+    /// Maximum number of results awaiting retrieval by reference, beyond
+    /// which the least recently used result is evicted
+    pub fn result_reference_max_results(&self) -> usize {
+        self.result_reference_max_results
+    }
+
+    pub fn result_retention(&self) -> Option<&ResultRetentionConfig> {
+        self.result_retention.as_ref()
+    }
+
+    /// Issuer claim of signed results
+    pub fn jwt_issuer(&self) -> &str {
+        &self.jwt_issuer
+    }
+
+    /// Audience claim of signed results, typically identifying the core
+    pub fn jwt_audience(&self) -> Option<&str> {
+        self.jwt_audience.as_deref()
+    }
+
+    /// Time for which signed results remain valid
+    pub fn result_validity(&self) -> Duration {
+        self.result_validity
+    }
+
+    pub fn result_not_before(&self) -> bool {
+        self.result_not_before
+    }
+
+    pub fn include_auth_time(&self) -> bool {
+        self.include_auth_time
+    }
+
+    /// Whether results name the continuation or attr_url they are delivered
+    /// to in a destination claim
+    pub fn bind_destination(&self) -> bool {
+        self.bind_destination
+    }
+
+    /// Whether results include the disclosed attributes grouped by the
+    /// credential they were disclosed from
+    pub fn group_by_credential(&self) -> bool {
+        self.group_by_credential
+    }
+
+    /// Whether issued result ids are tracked, so requestors can mark results
+    /// as consumed
+    pub fn track_result_ids(&self) -> bool {
+        self.track_result_ids
+    }
+
+    pub fn result_format(&self) -> ResultFormat {
+        self.result_format
+    }
+
+    /// Contexts of vp results, on top of the base credentials context
+    pub fn vc_contexts(&self) -> &[String] {
+        &self.vc_contexts
+    }
+
+    /// Types of the credential in vp results, on top of VerifiableCredential
+    pub fn vc_types(&self) -> &[String] {
+        &self.vc_types
+    }
+
+    /// Whether to deflate jose results before encryption
+    pub fn compress_results(&self) -> bool {
+        self.compress_results
+    }
+
+    pub fn result_status_spelling(&self) -> StatusSpelling {
+        self.result_status_spelling
+    }
+
+    /// Content type of out-of-band result callbacks, when overriding the one
+    /// matching the result format
+    pub fn callback_content_type(&self) -> Option<&str> {
+        self.callback_content_type.as_deref()
+    }
+
+    /// Url notified of every completed session, without attribute values
+    pub fn event_webhook_url(&self) -> Option<&str> {
+        self.event_webhook_url.as_deref()
+    }
+
+    /// File to append the json lines audit log to, or "stdout"
+    pub fn audit_log(&self) -> Option<&str> {
+        self.audit_log.as_deref()
+    }
+
+    /// Whether completion events in the audit log include the clock skew
+    /// between this plugin and the irma server
+    pub fn audit_clock_skew(&self) -> bool {
+        self.audit_clock_skew
+    }
+
+    /// Clock skew between this plugin and the irma server beyond which a
+    /// warning is logged, if enabled
+    pub fn clock_skew_threshold(&self) -> Option<Duration> {
+        self.clock_skew_threshold
+    }
+
+    /// Maximum time between starting an out-of-band session and receiving
+    /// its completion callback
+    pub fn max_session_age(&self) -> Option<Duration> {
+        self.max_session_age
+    }
+
+    // The stores of sessions and results are sized when the server starts,
+    // so a reload may not change the settings they are sized by
+    fn check_store_settings(&self, new: &Config) -> Result<(), Error> {
+        let retention = |config: &Config| {
+            config
+                .result_retention()
+                .map(|retention| (retention.period(), retention.max_results()))
+        };
+        let changed = [
+            (
+                "max_session_age",
+                self.max_session_age() != new.max_session_age(),
+            ),
+            (
+                "result_reference_ttl",
+                self.result_reference_ttl() != new.result_reference_ttl(),
+            ),
+            (
+                "result_reference_max_results",
+                self.result_reference_max_results() != new.result_reference_max_results(),
+            ),
+            ("result_retention", retention(self) != retention(new)),
+            (
+                "result_validity",
+                self.result_validity() != new.result_validity(),
+            ),
+        ];
+        match changed.into_iter().find(|(_, changed)| *changed) {
+            Some((setting, _)) => Err(Error::RestartRequired(setting)),
+            None => Ok(()),
+        }
+    }
+
+    /// Key id of the recipient key for encrypted output, if known
+    pub fn encryption_key_id(&self) -> Option<&str> {
+        self.encryption_key_id
+            .as_deref()
+            .or_else(|| self.encrypter.key_id())
+    }
+
+    /// Whether any requestor has a quota, in which case start requests have
+    /// to select a requestor key
+    pub fn has_requestor_quotas(&self) -> bool {
+        !self.requestor_quotas.is_empty()
+    }
+
+    /// Quota of the requestor with the given key id, if any
+    pub fn requestor_quota(&self, requestor_key: &str) -> Option<&RequestorQuota> {
+        self.requestor_quotas.get(requestor_key)
+    }
+
+    /// Encrypter and key id for a result, which is the requestor key with the
+    /// given id when set, and the default encryption key otherwise
+    pub fn result_encrypter(
+        &self,
+        requestor_key: Option<&str>,
+    ) -> Result<(Arc<dyn JweEncrypter>, Option<&str>), Error> {
+        match requestor_key {
+            Some(id) => {
+                let (id, key) = self
+                    .requestor_encrypters
+                    .get_key_value(id)
+                    .ok_or_else(|| Error::UnknownRequestorKey(id.to_string()))?;
+                let key_id = match key {
+                    ResultKey::Static(_) => id.as_str(),
+                    ResultKey::Remote(key) => &key.kid,
+                };
+                Ok((key.encrypter(self.jwks_cache_dir())?, Some(key_id)))
+            }
+            None => Ok((
+                self.encrypter.encrypter(self.jwks_cache_dir())?,
+                self.encryption_key_id(),
+            )),
+        }
+    }
+
+    /// Urls of the key sets publishing result encryption keys
+    pub fn remote_key_urls(&self) -> Vec<&str> {
+        let mut urls: Vec<&str> = std::iter::once(&self.encrypter)
+            .chain(self.requestor_encrypters.values())
+            .filter_map(|key| match key {
+                ResultKey::Static(_) => None,
+                ResultKey::Remote(key) => Some(key.url.as_str()),
+            })
+            .collect();
+        urls.sort_unstable();
+        urls.dedup();
+        urls
+    }
+
+    /// Interval at which key sets are fetched again
+    pub fn jwks_refresh_interval(&self) -> Duration {
+        self.jwks_refresh_interval
+    }
+
+    /// Directory keeping the last fetched copy of every key set, used when
+    /// fetching fails at startup
+    pub fn jwks_cache_dir(&self) -> Option<&Path> {
+        self.jwks_cache_dir.as_deref()
+    }
+
+    /// Encrypter for audit copies of results, when enabled
+    pub fn audit_encrypter(&self) -> Option<&dyn JweEncrypter> {
+        self.audit_encrypter.as_deref()
     }
 
     pub fn signer(&self) -> &dyn JwsSigner {
         self.signer.as_ref()
     }
 
-    pub fn _from_string(config: &str) -> Result<Config, Error> {
-        Ok(serde_yaml::from_str(config)?)
+    /// Key id of the signing key for signed output, if known
+    pub fn signing_key_id(&self) -> Option<&str> {
+        self.signing_key_id
+            .as_deref()
+            .or_else(|| self.signer.key_id())
+    }
+
+    /// Verifier for signatures of the signing key, when the type of the key
+    /// is supported
+    pub fn signing_verifier(&self) -> Option<&dyn JwsVerifier> {
+        self.signing_verifier.as_deref()
+    }
+
+    /// Public half of the signing key as JWK Set, when its type is supported
+    pub fn jwks(&self) -> Option<&super::jwks::Jwks> {
+        self.jwks.as_ref()
+    }
+
+    /// Signer used for the detached signature on out-of-band result
+    /// callbacks, if configured.
+    pub fn callback_signer(&self) -> Option<&dyn JwsSigner> {
+        self.callback_signer.as_ref().map(|signer| match signer {
+            CallbackSigner::SigningKey => self.signer(),
+            CallbackSigner::SharedSecret(signer) => signer.as_ref(),
+        })
+    }
+
+    /// Keys for binding browsers to in-band sessions, when enabled
+    pub fn session_binding(&self) -> Option<&SessionBinding> {
+        self.session_binding.as_ref()
+    }
+
+    /// Key id for the callback signature, only known when signing with the
+    /// signing key
+    pub fn callback_key_id(&self) -> Option<&str> {
+        match self.callback_signer {
+            Some(CallbackSigner::SigningKey) => self.signing_key_id(),
+            _ => None,
+        }
+    }
+
+    /// Parse a configuration in either json or yaml. Documents that are
+    /// syntactically json are parsed as such, anything else as yaml.
+    pub fn from_string(config: &str) -> Result<Config, Error> {
+        match serde_json::from_str(config) {
+            Ok(config) => Ok(config),
+            Err(e) if e.is_syntax() || e.is_eof() => Ok(serde_yaml::from_str(config)?),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn from_reader<T: std::io::Read>(mut reader: T) -> Result<Config, Error> {
+        let mut config = String::new();
+        reader.read_to_string(&mut config)?;
+        Config::from_string(&config)
+    }
+
+    /// Read the configuration from file, in the format given by its
+    /// extension, falling back to detecting the format for other extensions
+    pub fn from_path(path: &Path) -> Result<Config, Error> {
+        let file = File::open(path)?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Ok(serde_json::from_reader(file)?),
+            Some("yml" | "yaml") => Ok(serde_yaml::from_reader(file)?),
+            _ => Config::from_reader(file),
+        }
+    }
+}
+
+/// Configuration shared between requests, which can be replaced at runtime by
+/// reloading it from the file it was originally read from. Settings used to
+/// set up the server itself, such as the store lifetimes, are only read at
+/// startup.
+#[derive(Clone)]
+pub struct SharedConfig {
+    path: Option<PathBuf>,
+    current: Arc<RwLock<Arc<Config>>>,
+}
+
+impl SharedConfig {
+    pub fn new(config: Config, path: Option<PathBuf>) -> SharedConfig {
+        super::redact::activate(config.redactor());
+        SharedConfig {
+            path,
+            current: Arc::new(RwLock::new(Arc::new(config))),
+        }
+    }
+
+    /// The currently active configuration
+    pub fn get(&self) -> Arc<Config> {
+        self.current.read().unwrap().clone()
     }
 
-    pub fn from_reader<T: std::io::Read>(reader: T) -> Result<Config, Error> {
-        Ok(serde_yaml::from_reader(reader)?)
+    /// Reload the configuration from file. The active configuration is only
+    /// replaced when the new configuration is valid.
+    pub fn reload(&self) -> Result<(), Error> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let config = Config::from_path(path)?;
+        self.get().check_store_settings(&config)?;
+        super::redact::activate(config.redactor());
+        *self.current.write().unwrap() = Arc::new(config);
+        Ok(())
     }
 }
@@ -0,0 +1,161 @@
+/// Add a parameter carrying the authentication result to a continuation url.
+///
+/// By default the result is added as a query parameter, placed
+/// before any fragment the continuation might have, so that the fragment is
+/// preserved as is. When `in_fragment` is set, the result is instead added to
+/// the fragment so it never leaves the browser, appending to the existing
+/// fragment when there is one.
+pub fn append_result(continuation: &str, key: &str, result: &str, in_fragment: bool) -> String {
+    let (base, fragment) = match continuation.split_once('#') {
+        Some((base, fragment)) => (base, Some(fragment)),
+        None => (continuation, None),
+    };
+
+    if in_fragment {
+        match fragment {
+            Some(fragment) if !fragment.is_empty() => {
+                format!(
+                    "{base}#{fragment}{}{key}={result}",
+                    fragment_separator(fragment)
+                )
+            }
+            _ => format!("{base}#{key}={result}"),
+        }
+    } else {
+        let separator = if base.contains('?') { '&' } else { '?' };
+        match fragment {
+            Some(fragment) => format!("{base}{separator}{key}={result}#{fragment}"),
+            None => format!("{base}{separator}{key}={result}"),
+        }
+    }
+}
+
+// Fragments are used both for key-value parameters (`#a=b`) and by single page
+// applications for client side routing (`#/done` or `#/done?a=b`). For the
+// latter the result should end up in the query part of the route.
+fn fragment_separator(fragment: &str) -> char {
+    if fragment.contains('?') {
+        '&'
+    } else if fragment.starts_with('/') || !fragment.contains('=') {
+        '?'
+    } else {
+        '&'
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::append_result;
+
+    const QUERY: bool = false;
+    const FRAGMENT: bool = true;
+
+    #[test]
+    fn result_is_added_to_the_query() {
+        for (continuation, expected) in [
+            ("https://a.example/c", "https://a.example/c?result=R"),
+            (
+                "https://a.example/c?x=1",
+                "https://a.example/c?x=1&result=R",
+            ),
+            ("https://a.example/c#", "https://a.example/c?result=R#"),
+            (
+                "https://a.example/c#f=1",
+                "https://a.example/c?result=R#f=1",
+            ),
+            (
+                "https://a.example/c?x=1#f=1",
+                "https://a.example/c?x=1&result=R#f=1",
+            ),
+        ] {
+            assert_eq!(
+                append_result(continuation, "result", "R", QUERY),
+                expected,
+                "{}",
+                continuation
+            );
+        }
+    }
+
+    #[test]
+    fn result_is_added_to_the_fragment() {
+        for (continuation, expected) in [
+            ("https://a.example/c", "https://a.example/c#result=R"),
+            (
+                "https://a.example/c?x=1",
+                "https://a.example/c?x=1#result=R",
+            ),
+            ("https://a.example/c#", "https://a.example/c#result=R"),
+            (
+                "https://a.example/c#f=1",
+                "https://a.example/c#f=1&result=R",
+            ),
+            (
+                "https://a.example/c?x=1#f=1",
+                "https://a.example/c?x=1#f=1&result=R",
+            ),
+        ] {
+            assert_eq!(
+                append_result(continuation, "result", "R", FRAGMENT),
+                expected,
+                "{}",
+                continuation
+            );
+        }
+    }
+
+    #[test]
+    fn result_reference_uses_its_own_key() {
+        assert_eq!(
+            append_result("https://a.example/c?x=1", "result_ref", "R", QUERY),
+            "https://a.example/c?x=1&result_ref=R"
+        );
+        assert_eq!(
+            append_result("https://a.example/c#f=1", "result_ref", "R", FRAGMENT),
+            "https://a.example/c#f=1&result_ref=R"
+        );
+    }
+
+    #[test]
+    fn result_is_added_to_the_query_of_routes() {
+        for (continuation, expected) in [
+            ("https://a.example/c#/", "https://a.example/c#/?result=R"),
+            (
+                "https://a.example/c#/done",
+                "https://a.example/c#/done?result=R",
+            ),
+            (
+                "https://a.example/c#/done?a=1",
+                "https://a.example/c#/done?a=1&result=R",
+            ),
+            (
+                "https://a.example/c?x=1#/done",
+                "https://a.example/c?x=1#/done?result=R",
+            ),
+            // Hashbang routes
+            (
+                "https://a.example/c#!/done",
+                "https://a.example/c#!/done?result=R",
+            ),
+        ] {
+            assert_eq!(
+                append_result(continuation, "result", "R", FRAGMENT),
+                expected,
+                "{}",
+                continuation
+            );
+        }
+    }
+
+    #[test]
+    fn routes_are_preserved_when_adding_to_the_query() {
+        assert_eq!(
+            append_result("https://a.example/c#/done?a=1", "result", "R", QUERY),
+            "https://a.example/c?result=R#/done?a=1"
+        );
+        assert_eq!(
+            append_result("https://a.example/c?x=1#/done", "result", "R", QUERY),
+            "https://a.example/c?x=1&result=R#/done"
+        );
+    }
+}
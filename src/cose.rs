@@ -0,0 +1,274 @@
+use std::{
+    error::Error as StdError,
+    fmt::Display,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use base64::URL_SAFE_NO_PAD;
+use coset::{
+    iana, CoseEncrypt, CoseEncryptBuilder, CoseRecipientBuilder, CoseSign1Builder, HeaderBuilder,
+    TaggedCborSerializable,
+};
+use josekit::{
+    jwe::{JweContentEncryption, JweDecrypter, JweEncrypter, JweHeader, A256GCM},
+    jws::JwsSigner,
+    JoseError,
+};
+use rand::RngCore;
+use serde::Serialize;
+use verder_helpen_proto::AuthResult;
+
+use crate::jwe::{CredentialGroups, ResultClaims};
+
+#[derive(Debug)]
+pub enum Error {
+    Jose(JoseError),
+    Cbor(String),
+    UnsupportedAlgorithm(String),
+    UnsupportedKeyManagement(String),
+    Malformed(&'static str),
+}
+
+impl From<JoseError> for Error {
+    fn from(e: JoseError) -> Error {
+        Error::Jose(e)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Jose(e) => e.fmt(f),
+            Error::Cbor(desc) => f.write_fmt(format_args!("Could not encode cbor: {desc}")),
+            Error::UnsupportedAlgorithm(alg) => f.write_fmt(format_args!(
+                "Signing algorithm {alg} not supported for COSE"
+            )),
+            Error::UnsupportedKeyManagement(alg) => f.write_fmt(format_args!(
+                "Key management algorithm {alg} not supported for COSE"
+            )),
+            Error::Malformed(desc) => f.write_fmt(format_args!("Malformed COSE result: {desc}")),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Jose(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ResultPayload<'a> {
+    #[serde(flatten)]
+    result: &'a serde_json::Value,
+    iat: u64,
+    exp: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nbf: Option<u64>,
+    iss: &'a str,
+    jti: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aud: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auth_time: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disclosed_keys: Option<&'a [String]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    credentials: Option<&'a CredentialGroups>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nonce: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    destination: Option<&'a str>,
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn cose_algorithm(signer: &dyn JwsSigner) -> Result<iana::Algorithm, Error> {
+    match signer.algorithm().name() {
+        "RS256" => Ok(iana::Algorithm::RS256),
+        "RS384" => Ok(iana::Algorithm::RS384),
+        "RS512" => Ok(iana::Algorithm::RS512),
+        "PS256" => Ok(iana::Algorithm::PS256),
+        "PS384" => Ok(iana::Algorithm::PS384),
+        "PS512" => Ok(iana::Algorithm::PS512),
+        "ES256" => Ok(iana::Algorithm::ES256),
+        "ES384" => Ok(iana::Algorithm::ES384),
+        "ES512" => Ok(iana::Algorithm::ES512),
+        "EdDSA" => Ok(iana::Algorithm::EdDSA),
+        alg => Err(Error::UnsupportedAlgorithm(alg.to_string())),
+    }
+}
+
+// COSE counterpart of the key management algorithm of a result key. Only
+// algorithms wrapping a random content key are supported, as key agreement
+// would need ephemeral keys converted to COSE keys.
+fn cose_key_management(alg: &str) -> Result<iana::Algorithm, Error> {
+    match alg {
+        "RSA-OAEP" => Ok(iana::Algorithm::RSAES_OAEP_RFC_8017_default),
+        "RSA-OAEP-256" => Ok(iana::Algorithm::RSAES_OAEP_SHA_256),
+        "RSA-OAEP-512" => Ok(iana::Algorithm::RSAES_OAEP_SHA_512),
+        alg => Err(Error::UnsupportedKeyManagement(alg.to_string())),
+    }
+}
+
+/// Whether results can be encrypted to keys using the key management algorithm
+/// `alg`
+pub fn supports_key_management(alg: &str) -> bool {
+    cose_key_management(alg).is_ok()
+}
+
+// Length of the authentication tag AES-GCM appends to ciphertexts
+const GCM_TAG_LEN: usize = 16;
+
+// Header passed to josekit when wrapping and unwrapping content keys
+fn key_management_header() -> JweHeader {
+    let mut header = JweHeader::new();
+    header.set_content_encryption(A256GCM.name());
+    header
+}
+
+/// Sign an auth result as a tagged COSE_Sign1 structure with a cbor payload
+/// containing the same claims as the jwt form, and encrypt that to the result
+/// key as a tagged COSE_Encrypt structure with a single recipient, encoded as
+/// url-safe base64. The content is encrypted with A256GCM under a random key,
+/// which is wrapped to the result key.
+pub fn sign_and_encrypt_auth_result(
+    auth_result: &AuthResult,
+    claims: &ResultClaims,
+    signer: &dyn JwsSigner,
+    key_id: Option<&str>,
+    encrypter: &dyn JweEncrypter,
+    encryption_key_id: Option<&str>,
+) -> Result<String, Error> {
+    let mut result = serde_json::to_value(auth_result).map_err(|e| Error::Cbor(e.to_string()))?;
+    if let Some(result) = result.as_object_mut() {
+        result.insert(
+            "status".to_string(),
+            crate::jwe::result_status(auth_result, claims.status_spelling)?,
+        );
+    }
+
+    let now = SystemTime::now();
+    let payload = ResultPayload {
+        result: &result,
+        iat: unix_time(now),
+        exp: unix_time(now + claims.validity),
+        nbf: claims.not_before.then(|| unix_time(now)),
+        iss: &claims.issuer,
+        jti: &claims.jti,
+        aud: claims.audience.as_deref(),
+        auth_time: claims.auth_time.map(unix_time),
+        disclosed_keys: claims.disclosed_keys.as_deref(),
+        credentials: claims.credentials.as_ref(),
+        nonce: claims.nonce.as_deref(),
+        destination: claims.destination.as_deref(),
+    };
+    let mut encoded_payload = vec![];
+    ciborium::ser::into_writer(&payload, &mut encoded_payload)
+        .map_err(|e| Error::Cbor(e.to_string()))?;
+
+    let mut protected = HeaderBuilder::new().algorithm(cose_algorithm(signer)?);
+    if let Some(kid) = key_id {
+        protected = protected.key_id(kid.as_bytes().to_vec());
+    }
+    let protected = protected.build();
+    let sign1 = CoseSign1Builder::new()
+        .protected(protected)
+        .payload(encoded_payload)
+        .try_create_signature(&[], |data| signer.sign(data))?
+        .build();
+    let sign1 = sign1
+        .to_tagged_vec()
+        .map_err(|e| Error::Cbor(e.to_string()))?;
+
+    let encrypted = encrypt(&sign1, encrypter, encryption_key_id)?
+        .to_tagged_vec()
+        .map_err(|e| Error::Cbor(e.to_string()))?;
+    Ok(base64::encode_config(encrypted, URL_SAFE_NO_PAD))
+}
+
+fn encrypt(
+    plaintext: &[u8],
+    encrypter: &dyn JweEncrypter,
+    key_id: Option<&str>,
+) -> Result<CoseEncrypt, Error> {
+    let key_management = cose_key_management(encrypter.algorithm().name())?;
+    let mut content_key = vec![0; A256GCM.key_len()];
+    rand::thread_rng().fill_bytes(&mut content_key);
+    let mut iv = vec![0; A256GCM.iv_len()];
+    rand::thread_rng().fill_bytes(&mut iv);
+
+    let header = key_management_header();
+    let wrapped_key = encrypter
+        .encrypt(&content_key, &header, &mut header.clone())?
+        .ok_or_else(|| Error::UnsupportedKeyManagement(encrypter.algorithm().name().to_string()))?;
+    let mut recipient_header = HeaderBuilder::new().algorithm(key_management);
+    if let Some(kid) = key_id.or_else(|| encrypter.key_id()) {
+        recipient_header = recipient_header.key_id(kid.as_bytes().to_vec());
+    }
+    let recipient = CoseRecipientBuilder::new()
+        .unprotected(recipient_header.build())
+        .ciphertext(wrapped_key)
+        .build();
+
+    // AES-GCM ciphertexts in COSE carry the authentication tag at their end
+    Ok(CoseEncryptBuilder::new()
+        .protected(
+            HeaderBuilder::new()
+                .algorithm(iana::Algorithm::A256GCM)
+                .build(),
+        )
+        .unprotected(HeaderBuilder::new().iv(iv.clone()).build())
+        .try_create_ciphertext(plaintext, &[], |plaintext, aad| {
+            let (mut ciphertext, tag) =
+                A256GCM.encrypt(&content_key, Some(iv.as_slice()), plaintext, aad)?;
+            ciphertext.extend(tag.unwrap_or_default());
+            Ok::<_, Error>(ciphertext)
+        })?
+        .add_recipient(recipient)
+        .build())
+}
+
+/// Decrypt a result encrypted by [`sign_and_encrypt_auth_result`], returning
+/// the tagged COSE_Sign1 structure in it
+pub fn decrypt_result(encoded: &str, decrypter: &dyn JweDecrypter) -> Result<Vec<u8>, Error> {
+    let encoded = base64::decode_config(encoded, URL_SAFE_NO_PAD)
+        .map_err(|_| Error::Malformed("invalid base64"))?;
+    let encrypted =
+        CoseEncrypt::from_tagged_slice(&encoded).map_err(|e| Error::Cbor(e.to_string()))?;
+    if encrypted.protected.header.alg != Some(coset::Algorithm::Assigned(iana::Algorithm::A256GCM))
+    {
+        return Err(Error::Malformed("unexpected content encryption"));
+    }
+    let recipient = match encrypted.recipients.as_slice() {
+        [recipient] => recipient,
+        _ => return Err(Error::Malformed("expected a single recipient")),
+    };
+    let content_key = decrypter.decrypt(
+        recipient.ciphertext.as_deref(),
+        &A256GCM,
+        &key_management_header(),
+    )?;
+    let iv = &encrypted.unprotected.iv;
+    encrypted.decrypt(&[], |ciphertext, aad| {
+        let tag_start = ciphertext
+            .len()
+            .checked_sub(GCM_TAG_LEN)
+            .ok_or(Error::Malformed("ciphertext too short"))?;
+        let (ciphertext, tag) = ciphertext.split_at(tag_start);
+        Ok(A256GCM.decrypt(
+            &content_key,
+            Some(iv.as_slice()),
+            ciphertext,
+            aad,
+            Some(tag),
+        )?)
+    })
+}
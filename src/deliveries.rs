@@ -0,0 +1,94 @@
+//! Accounting for deliveries of results to attr_urls, per target host, so
+//! operators can alert on failing deliveries.
+
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+
+use serde::Serialize;
+
+/// Upper bounds in milliseconds of the latency histogram buckets, the last
+/// bucket counting everything slower
+const LATENCY_BUCKETS_MS: [u64; 8] = [50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+static STATS: Mutex<BTreeMap<String, HostStats>> = Mutex::new(BTreeMap::new());
+
+/// Class of a failed delivery
+#[derive(Debug, Clone, Copy)]
+pub enum Failure {
+    Connect,
+    Timeout,
+    ClientError,
+    ServerError,
+    Other,
+}
+
+impl Failure {
+    /// Classify the outcome of a delivery, returning `None` for successes
+    pub fn classify(result: &Result<reqwest::Response, reqwest::Error>) -> Option<Failure> {
+        match result {
+            Ok(response) if response.status().is_client_error() => Some(Failure::ClientError),
+            Ok(response) if response.status().is_server_error() => Some(Failure::ServerError),
+            Ok(_) => None,
+            Err(e) if e.is_timeout() => Some(Failure::Timeout),
+            Err(e) if e.is_connect() => Some(Failure::Connect),
+            Err(_) => Some(Failure::Other),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Failures {
+    connect: u64,
+    timeout: u64,
+    client_error: u64,
+    server_error: u64,
+    other: u64,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Latency {
+    /// Deliveries per bucket, matching the bounds in `bounds_ms`
+    buckets: Vec<u64>,
+    bounds_ms: &'static [u64],
+    sum_ms: u64,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct HostStats {
+    attempts: u64,
+    successes: u64,
+    failures: Failures,
+    latency: Latency,
+}
+
+/// Record a delivery attempt to `host` that took `elapsed`
+pub fn record(host: &str, elapsed: Duration, failure: Option<Failure>) {
+    let mut stats = STATS.lock().unwrap();
+    let stats = stats.entry(host.to_string()).or_default();
+
+    stats.attempts += 1;
+    match failure {
+        None => stats.successes += 1,
+        Some(Failure::Connect) => stats.failures.connect += 1,
+        Some(Failure::Timeout) => stats.failures.timeout += 1,
+        Some(Failure::ClientError) => stats.failures.client_error += 1,
+        Some(Failure::ServerError) => stats.failures.server_error += 1,
+        Some(Failure::Other) => stats.failures.other += 1,
+    }
+
+    let elapsed_ms = elapsed.as_millis() as u64;
+    if stats.latency.buckets.is_empty() {
+        stats.latency.buckets = vec![0; LATENCY_BUCKETS_MS.len() + 1];
+        stats.latency.bounds_ms = &LATENCY_BUCKETS_MS;
+    }
+    let bucket = LATENCY_BUCKETS_MS
+        .iter()
+        .position(|bound| elapsed_ms <= *bound)
+        .unwrap_or(LATENCY_BUCKETS_MS.len());
+    stats.latency.buckets[bucket] += 1;
+    stats.latency.sum_ms += elapsed_ms;
+}
+
+/// Delivery statistics per target host since the process started
+pub fn snapshot() -> BTreeMap<String, HostStats> {
+    STATS.lock().unwrap().clone()
+}
@@ -0,0 +1,73 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::config::Config;
+
+/// Notification of a completed session for analytics and auditing, sent once
+/// the result was delivered or the session failed. Events never carry
+/// attribute values.
+#[derive(Debug, Serialize)]
+pub struct CompletionEvent {
+    /// The requestor_key_id of the session, identifying the requestor when
+    /// the session was started on its behalf
+    pub requestor: Option<String>,
+    pub status: &'static str,
+    /// Why the session failed, for failed sessions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<&'static str>,
+    /// Completion time in seconds since the unix epoch
+    pub timestamp: u64,
+    /// The jti of the issued result, absent when no result was issued
+    pub correlation_id: Option<String>,
+}
+
+impl CompletionEvent {
+    pub fn success(requestor: Option<&str>, correlation_id: String) -> Self {
+        CompletionEvent::new(requestor, "success", None, Some(correlation_id))
+    }
+
+    /// Event for a session that failed, or whose result could not be
+    /// delivered, for the given reason
+    pub fn failure(requestor: Option<&str>, reason: &'static str) -> Self {
+        CompletionEvent::new(requestor, "failure", Some(reason), None)
+    }
+
+    fn new(
+        requestor: Option<&str>,
+        status: &'static str,
+        reason: Option<&'static str>,
+        correlation_id: Option<String>,
+    ) -> Self {
+        CompletionEvent {
+            requestor: requestor.map(str::to_string),
+            status,
+            reason,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_secs())
+                .unwrap_or(0),
+            correlation_id,
+        }
+    }
+}
+
+/// Post the event to the configured webhook, if any, in the background.
+/// Delivery is best-effort, failures are only logged.
+pub fn notify(config: &Config, event: CompletionEvent) {
+    let webhook_url = match config.event_webhook_url() {
+        Some(webhook_url) => webhook_url.to_string(),
+        None => return,
+    };
+    rocket::tokio::spawn(async move {
+        let result = reqwest::Client::new()
+            .post(&webhook_url)
+            .json(&event)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = result {
+            log::warn!("Failure posting completion event: {}", e);
+        }
+    });
+}
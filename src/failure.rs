@@ -0,0 +1,88 @@
+use crate::{config, irma, Error};
+
+/// Reason an authentication failed, as reported to the requestor. Reasons
+/// never carry attribute values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureReason {
+    Cancelled,
+    Timeout,
+    InvalidProof,
+    MissingAttributes,
+    ConstraintViolation,
+    ExpiredSession,
+}
+
+impl FailureReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FailureReason::Cancelled => "cancelled",
+            FailureReason::Timeout => "timeout",
+            FailureReason::InvalidProof => "invalid_proof",
+            FailureReason::MissingAttributes => "missing_attributes",
+            FailureReason::ConstraintViolation => "constraint_violation",
+            FailureReason::ExpiredSession => "expired_session",
+        }
+    }
+
+    /// Reason for a failed session, when the error was caused by the
+    /// session's outcome rather than by a fault on our side
+    pub fn of(error: &Error) -> Option<FailureReason> {
+        match error {
+            Error::Irma(irma::Error::Cancelled()) => Some(FailureReason::Cancelled),
+            Error::Irma(irma::Error::Timeout()) => Some(FailureReason::Timeout),
+            Error::Irma(irma::Error::Invalid()) => Some(FailureReason::InvalidProof),
+            Error::Irma(irma::Error::MissingAttributes()) => Some(FailureReason::MissingAttributes),
+            Error::Config(config::Error::InvalidResponse(_)) => Some(FailureReason::InvalidProof),
+            Error::Config(config::Error::NotMatching(_)) => {
+                Some(FailureReason::ConstraintViolation)
+            }
+            Error::Gone(_) => Some(FailureReason::ExpiredSession),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FailureReason;
+    use crate::{config, irma, Error};
+
+    fn reason(error: Error) -> Option<&'static str> {
+        FailureReason::of(&error).map(FailureReason::as_str)
+    }
+
+    #[test]
+    fn session_outcomes_map_to_reasons() {
+        let irma = |e| reason(Error::Irma(e));
+        assert_eq!(irma(irma::Error::Cancelled()), Some("cancelled"));
+        assert_eq!(irma(irma::Error::Timeout()), Some("timeout"));
+        assert_eq!(irma(irma::Error::Invalid()), Some("invalid_proof"));
+        assert_eq!(
+            irma(irma::Error::MissingAttributes()),
+            Some("missing_attributes")
+        );
+
+        let config = |e| reason(Error::Config(e));
+        assert_eq!(
+            config(config::Error::InvalidResponse("Incorrect attribute")),
+            Some("invalid_proof")
+        );
+        assert_eq!(
+            config(config::Error::NotMatching("Value not allowed")),
+            Some("constraint_violation")
+        );
+
+        assert_eq!(
+            reason(Error::Gone("Unknown or expired session")),
+            Some("expired_session")
+        );
+    }
+
+    #[test]
+    fn faults_have_no_reason() {
+        assert_eq!(reason(Error::Irma(irma::Error::CircuitOpen())), None);
+        assert_eq!(reason(Error::Irma(irma::Error::Incomplete())), None);
+        assert_eq!(reason(Error::Irma(irma::Error::UnknownSession())), None);
+        assert_eq!(reason(Error::BadRequest("No attributes requested")), None);
+    }
+}
@@ -1,6 +1,19 @@
-use std::{convert::TryFrom, error::Error as StdError, fmt::Display};
-
+use std::{
+    collections::hash_map::RandomState,
+    convert::TryFrom,
+    error::Error as StdError,
+    fmt::{Debug, Display},
+    future::Future,
+    hash::BuildHasher,
+    io::{BufReader, Read},
+    sync::{Mutex, OnceLock, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use bytes::Bytes;
+use rocket::tokio::{sync::mpsc, task};
 use serde::{Deserialize, Serialize};
+use url::Url;
 
 #[derive(Debug)]
 pub enum Error {
@@ -10,6 +23,12 @@ pub enum Error {
     Cancelled(),
     Timeout(),
     Invalid(),
+    MissingAttributes(),
+    TooLarge(),
+    InvalidPointer(),
+    UnknownSession(),
+    CircuitOpen(),
+    InvalidResponse(&'static str),
 }
 
 impl From<reqwest::Error> for Error {
@@ -33,9 +52,46 @@ impl Display for Error {
             Error::Cancelled() => f.write_str("Cancelled session"),
             Error::Timeout() => f.write_str("Session timed out"),
             Error::Invalid() => f.write_str("Invalid proof"),
+            Error::MissingAttributes() => f.write_str("Credential lacks requested attributes"),
+            Error::TooLarge() => f.write_str("Response too large"),
+            Error::InvalidPointer() => f.write_str("Invalid session pointer"),
+            Error::UnknownSession() => f.write_str("Unknown session"),
+            Error::CircuitOpen() => f.write_str("Irma server calls suspended after failures"),
+            Error::InvalidResponse(desc) => write!(f, "Invalid irma server response: {}", desc),
+        }
+    }
+}
+impl Error {
+    /// HTTP status the irma server responded with, if it was an error status
+    pub fn status(&self) -> Option<reqwest::StatusCode> {
+        match self {
+            Error::Reqwest(e) => e.status(),
+            _ => None,
+        }
+    }
+
+    /// Whether the irma server could not be reached or is temporarily unable
+    /// to handle requests
+    pub fn is_unavailable(&self) -> bool {
+        match self {
+            Error::Reqwest(e) => {
+                e.is_connect()
+                    || e.is_timeout()
+                    || matches!(
+                        e.status(),
+                        Some(
+                            reqwest::StatusCode::BAD_GATEWAY
+                                | reqwest::StatusCode::SERVICE_UNAVAILABLE
+                                | reqwest::StatusCode::GATEWAY_TIMEOUT
+                        )
+                    )
+            }
+            Error::CircuitOpen() => true,
+            _ => false,
         }
     }
 }
+
 impl StdError for Error {
     fn source(&self) -> Option<&(dyn StdError + 'static)> {
         match self {
@@ -46,10 +102,48 @@ impl StdError for Error {
     }
 }
 
+/// Fully qualified irma attribute identifier, of the form
+/// `scheme.issuer.credential.attribute`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeId {
+    id: String,
+}
+
+impl AttributeId {
+    /// Parse and validate an attribute identifier
+    pub fn parse(id: &str) -> Option<AttributeId> {
+        let parts: Vec<&str> = id.split('.').collect();
+        if parts.len() != 4 || parts.iter().any(|part| part.is_empty()) {
+            return None;
+        }
+        Some(AttributeId { id: id.to_string() })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.id
+    }
+
+    /// Identifier of the credential containing the attribute, of the form
+    /// `scheme.issuer.credential`
+    pub fn credential(&self) -> &str {
+        // Parsing guarantees four components
+        self.id
+            .rsplit_once('.')
+            .map(|(credential, _)| credential)
+            .unwrap_or_default()
+    }
+}
+
 #[derive(Serialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum Attribute {
     Simple(String),
+    /// Attribute that has to be disclosed with the given value
+    WithValue {
+        #[serde(rename = "type")]
+        id: String,
+        value: String,
+    },
 }
 
 pub type ConDisCon = Vec<Vec<Vec<Attribute>>>;
@@ -92,13 +186,75 @@ struct SessionPointer {
     irma_qr: SessionType,
 }
 
+/// Token of an irma session. Anyone holding it can fetch the session result
+/// from the irma server, so it is only shown redacted when formatted. The
+/// raw value has to be asked for explicitly.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct SessionToken(String);
+
+impl SessionToken {
+    pub fn new(token: String) -> Self {
+        SessionToken(token)
+    }
+
+    /// The raw token, for communicating with the irma server and the app
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// Form of the token that is safe to log: its first characters and a
+    /// hash keyed per process, so log lines of a session can be correlated
+    /// without revealing the token
+    pub fn display_token(&self) -> String {
+        static HASH_KEY: OnceLock<RandomState> = OnceLock::new();
+        let hash = HASH_KEY.get_or_init(RandomState::new).hash_one(&self.0);
+        let prefix: String = self.0.chars().take(6).collect();
+        format!("{}..{:08x}", prefix, hash as u32)
+    }
+}
+
+impl Display for SessionToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.display_token())
+    }
+}
+
+impl Debug for SessionToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SessionToken({})", self.display_token())
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct SessionResponse {
-    token: String,
+    token: SessionToken,
     session_ptr: SessionPointer,
 }
 
+impl SessionResponse {
+    // Check that the app will be able to use the session pointer, catching a
+    // misconfigured irma server before the user scans a dead QR code
+    fn into_session(self) -> Result<IrmaSession, Error> {
+        if self.token.expose().is_empty() {
+            return Err(Error::InvalidResponse("empty session token"));
+        }
+        let pointer_url = Url::parse(&self.session_ptr.u)
+            .map_err(|_| Error::InvalidResponse("unparseable session pointer url"))?;
+        if !matches!(pointer_url.scheme(), "http" | "https") || !pointer_url.has_host() {
+            return Err(Error::InvalidResponse(
+                "session pointer url is not an http url",
+            ));
+        }
+
+        Ok(IrmaSession {
+            qr: serde_json::to_string(&self.session_ptr)?,
+            token: self.token,
+        })
+    }
+}
+
 #[derive(Deserialize, Serialize, PartialEq, Debug)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum ProofStatus {
@@ -117,6 +273,9 @@ pub enum ProofStatus {
 #[strum(serialize_all = "shouty_snake_case")]
 pub enum SessionStatus {
     Initialized,
+    /// The app connected and waits for the user to enter the pairing code
+    /// shown by the frontend
+    Pairing,
     Connected,
     Cancelled,
     Done,
@@ -129,17 +288,60 @@ pub struct AttributeResult {
     pub rawvalue: String,
 }
 
+// Results of sessions that did not complete lack a proof status and
+// disclosures
 #[derive(Deserialize, Serialize, Debug)]
 struct RawIrmaResult {
     status: SessionStatus,
-    #[serde(rename = "proofStatus")]
-    proof_status: ProofStatus,
+    #[serde(rename = "proofStatus", default)]
+    proof_status: Option<ProofStatus>,
+    #[serde(default)]
     disclosed: Vec<Vec<AttributeResult>>,
 }
 
 #[derive(Debug)]
 pub struct IrmaResult {
     pub disclosed: Vec<Vec<AttributeResult>>,
+    /// Time according to the irma server when it sent the result, taken from
+    /// the Date header of its response
+    pub server_time: Option<SystemTime>,
+}
+
+impl IrmaResult {
+    /// Get the disclosed conjunctions of a single disclosure round.
+    ///
+    /// When a session is chained into follow-up sessions, the irma server
+    /// reports the disclosures of all rounds one after the other. The
+    /// requested round and the rounds before it consist of `round_size` inner
+    /// conjunctions, matching the number of discons requested in them. Later
+    /// rounds may differ in size. Returns `None` when the response does not
+    /// contain the requested round.
+    pub fn disclosure_round(
+        &self,
+        round: usize,
+        round_size: usize,
+    ) -> Option<&[Vec<AttributeResult>]> {
+        if round_size == 0 {
+            return if self.disclosed.is_empty() && round == 0 {
+                Some(&[])
+            } else {
+                None
+            };
+        }
+        self.disclosed
+            .get(round * round_size..(round + 1) * round_size)
+    }
+
+    /// Seconds the clock of the irma server runs ahead of `now`, negative
+    /// when it runs behind. The Date header has a resolution of one second,
+    /// so a skew of a second is noise.
+    pub fn clock_skew(&self, now: SystemTime) -> Option<i64> {
+        let server_time = self.server_time?;
+        Some(match server_time.duration_since(now) {
+            Ok(ahead) => ahead.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64),
+        })
+    }
 }
 
 impl TryFrom<RawIrmaResult> for IrmaResult {
@@ -150,9 +352,11 @@ impl TryFrom<RawIrmaResult> for IrmaResult {
             SessionStatus::Cancelled => Err(Error::Cancelled()),
             SessionStatus::Timeout => Err(Error::Timeout()),
             SessionStatus::Done => match value.proof_status {
-                ProofStatus::Valid => Ok(IrmaResult {
+                Some(ProofStatus::Valid) => Ok(IrmaResult {
                     disclosed: value.disclosed,
+                    server_time: None,
                 }),
+                Some(ProofStatus::MissingAttributes) => Err(Error::MissingAttributes()),
                 _ => Err(Error::Invalid()),
             },
             _ => Err(Error::Incomplete()),
@@ -160,16 +364,200 @@ impl TryFrom<RawIrmaResult> for IrmaResult {
     }
 }
 
+/// Validate a session pointer before signing it, requiring it to point at one
+/// of the allowed hosts. Returns the pointer re-serialized from its parsed
+/// form, dropping anything we don't know.
+pub fn validate_pointer(qr: &str, allowed_hosts: &[String]) -> Result<String, Error> {
+    let pointer: SessionPointer = serde_json::from_str(qr).map_err(|_| Error::InvalidPointer())?;
+    let url = Url::parse(&pointer.u).map_err(|_| Error::InvalidPointer())?;
+    let host = url.host_str().ok_or(Error::InvalidPointer())?;
+    if !matches!(url.scheme(), "http" | "https")
+        || !allowed_hosts
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(host))
+    {
+        return Err(Error::InvalidPointer());
+    }
+    Ok(serde_json::to_string(&pointer)?)
+}
+
+/// Point the app to the irma server through another host, for deployments
+/// where the irma server is reachable through several hostnames. The host may
+/// include a port.
+pub fn override_pointer_host(qr: &str, host: &str) -> Result<String, Error> {
+    let mut pointer: SessionPointer = serde_json::from_str(qr)?;
+    let mut url = Url::parse(&pointer.u).map_err(|_| Error::InvalidPointer())?;
+    let authority =
+        Url::parse(&format!("{}://{}", url.scheme(), host)).map_err(|_| Error::InvalidPointer())?;
+    url.set_host(authority.host_str())
+        .map_err(|_| Error::InvalidPointer())?;
+    url.set_port(authority.port())
+        .map_err(|_| Error::InvalidPointer())?;
+    pointer.u = url.to_string();
+    Ok(serde_json::to_string(&pointer)?)
+}
+
 #[derive(Debug)]
 pub struct IrmaSession {
     pub qr: String,
-    pub token: String,
+    pub token: SessionToken,
+}
+
+/// Method for discovering the irma server base url at runtime
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Discovery {
+    /// Resolve a DNS SRV record, using the target with the lowest priority
+    Srv {
+        name: String,
+        #[serde(default = "default_srv_scheme")]
+        scheme: String,
+    },
+    /// Read the base url from an environment variable
+    Env { variable: String },
+}
+
+fn default_srv_scheme() -> String {
+    "http".to_string()
+}
+
+impl Discovery {
+    async fn resolve(&self) -> Option<String> {
+        match self {
+            Discovery::Srv { name, scheme } => {
+                let resolver = match hickory_resolver::TokioAsyncResolver::tokio_from_system_conf()
+                {
+                    Ok(resolver) => resolver,
+                    Err(e) => {
+                        log::warn!("Could not set up resolver for irma server discovery: {}", e);
+                        return None;
+                    }
+                };
+                let records = match resolver.srv_lookup(name.as_str()).await {
+                    Ok(records) => records,
+                    Err(e) => {
+                        log::warn!("Could not resolve irma server SRV record: {}", e);
+                        return None;
+                    }
+                };
+                let record = records
+                    .iter()
+                    .min_by_key(|record| (record.priority(), u16::MAX - record.weight()))?;
+                let target = record.target().to_utf8();
+                Some(format!(
+                    "{}://{}:{}",
+                    scheme,
+                    target.trim_end_matches('.'),
+                    record.port()
+                ))
+            }
+            Discovery::Env { variable } => match std::env::var(variable) {
+                Ok(url) => Some(url),
+                Err(e) => {
+                    log::warn!("Could not read irma server url from {}: {}", variable, e);
+                    None
+                }
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ServiceDiscovery {
+    method: Discovery,
+    refresh_interval: Duration,
+    resolved: RwLock<Option<(Instant, String)>>,
+}
+
+/// State of the circuit breaker around calls to the irma server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Calls go through
+    Closed,
+    /// Calls fail immediately until the cooldown has passed
+    Open,
+    /// The cooldown has passed, the next call probes whether the irma server
+    /// recovered
+    HalfOpen,
+}
+
+impl CircuitState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half_open",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+}
+
+/// Stops calling the irma server for a cooldown period after a number of
+/// consecutive failures to reach it
+#[derive(Debug)]
+struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    fn state(&self) -> CircuitState {
+        match self.state.lock().unwrap().opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at) if opened_at.elapsed() < self.cooldown => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    fn allow(&self) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        match state.opened_at {
+            Some(opened_at) if opened_at.elapsed() < self.cooldown => Err(Error::CircuitOpen()),
+            // Let a single call probe the server, holding off others for
+            // another cooldown period unless it succeeds
+            Some(_) => {
+                state.opened_at = Some(Instant::now());
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn record<T>(&self, result: &Result<T, Error>) {
+        let mut state = self.state.lock().unwrap();
+        match result {
+            Err(e) if e.is_unavailable() => {
+                state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+                if state.consecutive_failures >= self.failure_threshold {
+                    if state.opened_at.is_none() {
+                        log::warn!("Irma server unreachable, suspending calls");
+                    }
+                    state.opened_at = Some(Instant::now());
+                }
+            }
+            // Any response means the server is reachable
+            _ => {
+                if state.opened_at.is_some() {
+                    log::info!("Irma server reachable again, resuming calls");
+                }
+                *state = BreakerState::default();
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct IrmaServer {
     server_url: String,
     auth_token: Option<String>,
+    discovery: Option<ServiceDiscovery>,
+    breaker: Option<CircuitBreaker>,
 }
 
 impl IrmaServer {
@@ -177,6 +565,8 @@ impl IrmaServer {
         IrmaServer {
             server_url: server_url.to_string(),
             auth_token: None,
+            discovery: None,
+            breaker: None,
         }
     }
 
@@ -184,32 +574,204 @@ impl IrmaServer {
         IrmaServer {
             server_url: server_url.to_string(),
             auth_token: Some(auth_token.to_string()),
+            discovery: None,
+            breaker: None,
+        }
+    }
+
+    /// Discover the server url at runtime, refreshing it after
+    /// `refresh_interval`. The static url is used whenever discovery fails
+    /// and no url was discovered before.
+    pub fn with_discovery(mut self, method: Discovery, refresh_interval: Duration) -> IrmaServer {
+        self.discovery = Some(ServiceDiscovery {
+            method,
+            refresh_interval,
+            resolved: RwLock::new(None),
+        });
+        self
+    }
+
+    /// Fail calls immediately for `cooldown` after `failure_threshold`
+    /// consecutive failures to reach the server
+    pub fn with_circuit_breaker(
+        mut self,
+        failure_threshold: u32,
+        cooldown: Duration,
+    ) -> IrmaServer {
+        self.breaker = Some(CircuitBreaker {
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            state: Mutex::new(BreakerState::default()),
+        });
+        self
+    }
+
+    /// State of the circuit breaker, if enabled
+    pub fn circuit_state(&self) -> Option<CircuitState> {
+        self.breaker.as_ref().map(CircuitBreaker::state)
+    }
+
+    // Perform a call to the server at its current url through the circuit
+    // breaker, logging its duration and outcome so slow round-trips can be
+    // told apart. The url is resolved once and handed to the call, so the
+    // host logged is the one actually called.
+    async fn guarded<T, F>(
+        &self,
+        operation: &'static str,
+        call: impl FnOnce(String) -> F,
+    ) -> Result<T, Error>
+    where
+        F: Future<Output = Result<T, Error>>,
+    {
+        let started = Instant::now();
+        let server_url = self.server_url().await;
+        let host = Url::parse(&server_url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_else(|| server_url.clone());
+        let result = match &self.breaker {
+            Some(breaker) => match breaker.allow() {
+                Ok(()) => {
+                    let result = call(server_url).await;
+                    breaker.record(&result);
+                    result
+                }
+                Err(e) => Err(e),
+            },
+            None => call(server_url).await,
+        };
+
+        let elapsed = started.elapsed().as_millis();
+        match &result {
+            Ok(_) => log::debug!(
+                target: "irma",
+                "{} on {} succeeded in {} ms",
+                operation,
+                host,
+                elapsed
+            ),
+            Err(e) => log::debug!(
+                target: "irma",
+                "{} on {} failed in {} ms (status {}): {}",
+                operation,
+                host,
+                elapsed,
+                e.status()
+                    .map(|status| status.as_u16().to_string())
+                    .unwrap_or_else(|| "none".to_string()),
+                e
+            ),
+        }
+        result
+    }
+
+    async fn server_url(&self) -> String {
+        let discovery = match &self.discovery {
+            Some(discovery) => discovery,
+            None => return self.server_url.clone(),
+        };
+
+        let cached = discovery.resolved.read().unwrap().clone();
+        if let Some((resolved_at, url)) = &cached {
+            if resolved_at.elapsed() < discovery.refresh_interval {
+                return url.clone();
+            }
+        }
+
+        match discovery.method.resolve().await {
+            Some(url) => {
+                *discovery.resolved.write().unwrap() = Some((Instant::now(), url.clone()));
+                url
+            }
+            // Keep using the last known url until discovery recovers
+            None => cached
+                .map(|(_, url)| url)
+                .unwrap_or_else(|| self.server_url.clone()),
         }
     }
 
     pub async fn start(&self, request: &IrmaRequest) -> Result<IrmaSession, Error> {
+        self.guarded("start", |server_url| {
+            self.start_unguarded(server_url, request)
+        })
+        .await
+    }
+
+    pub async fn start_with_callback(
+        &self,
+        request: &IrmaRequest,
+        callback_url: &str,
+    ) -> Result<IrmaSession, Error> {
+        self.guarded("start_with_callback", |server_url| {
+            self.start_with_callback_unguarded(server_url, request, callback_url)
+        })
+        .await
+    }
+
+    pub async fn get_result(&self, token: &SessionToken) -> Result<IrmaResult, Error> {
+        self.guarded("get_result", |server_url| {
+            self.get_result_unguarded(server_url, token)
+        })
+        .await
+    }
+
+    /// Check whether the irma server can be reached. Only server errors
+    /// count as failures, as the probed url itself is not an api endpoint.
+    /// Probes bypass the circuit breaker.
+    pub async fn probe(&self, timeout: Duration) -> Result<(), Error> {
+        let response = reqwest::Client::new()
+            .get(self.server_url().await)
+            .timeout(timeout)
+            .send()
+            .await?;
+        if response.status().is_server_error() {
+            response.error_for_status()?;
+        }
+        Ok(())
+    }
+
+    /// Current status of a session
+    pub async fn status(&self, token: &SessionToken) -> Result<SessionStatus, Error> {
+        self.guarded("status", |server_url| {
+            self.status_unguarded(server_url, token)
+        })
+        .await
+    }
+
+    /// Cancel a session, after which the app can no longer complete it
+    pub async fn cancel(&self, token: &SessionToken) -> Result<(), Error> {
+        self.guarded("cancel", |server_url| {
+            self.cancel_unguarded(server_url, token)
+        })
+        .await
+    }
+
+    async fn start_unguarded(
+        &self,
+        server_url: String,
+        request: &IrmaRequest,
+    ) -> Result<IrmaSession, Error> {
         let client = reqwest::Client::new();
 
-        let mut session_request = client
-            .post(format!("{}/session", self.server_url))
-            .json(request);
+        let mut session_request = client.post(format!("{}/session", server_url)).json(request);
 
         if let Some(token) = &self.auth_token {
             session_request = session_request.header("Authorization", token);
         }
 
-        let session_response: SessionResponse = session_request.send().await?.json().await?;
-
-        let qr = serde_json::to_string(&session_response.session_ptr)?;
+        let session_response: SessionResponse = session_request
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
 
-        Ok(IrmaSession {
-            qr,
-            token: session_response.token,
-        })
+        session_response.into_session()
     }
 
-    pub async fn start_with_callback(
+    async fn start_with_callback_unguarded(
         &self,
+        server_url: String,
         request: &IrmaRequest,
         callback_url: &str,
     ) -> Result<IrmaSession, Error> {
@@ -219,32 +781,169 @@ impl IrmaServer {
         };
         let client = reqwest::Client::new();
         let mut session_request = client
-            .post(format!("{}/session", self.server_url))
+            .post(format!("{}/session", server_url))
             .json(&extended_request);
 
         if let Some(token) = &self.auth_token {
             session_request = session_request.header("Authorization", token);
         }
 
-        let session_response: SessionResponse = session_request.send().await?.json().await?;
+        let session_response: SessionResponse = session_request
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
 
-        let qr = serde_json::to_string(&session_response.session_ptr)?;
+        session_response.into_session()
+    }
 
-        Ok(IrmaSession {
-            qr,
-            token: session_response.token,
-        })
+    async fn status_unguarded(
+        &self,
+        server_url: String,
+        token: &SessionToken,
+    ) -> Result<SessionStatus, Error> {
+        let response = reqwest::Client::new()
+            .get(&format!(
+                "{}/session/{}/status",
+                server_url,
+                token.expose()
+            ))
+            .send()
+            .await
+            // The url contains the raw token, keep it out of the error
+            .map_err(reqwest::Error::without_url)?;
+        if matches!(
+            response.status(),
+            reqwest::StatusCode::BAD_REQUEST | reqwest::StatusCode::NOT_FOUND
+        ) {
+            return Err(Error::UnknownSession());
+        }
+
+        Ok(response
+            .error_for_status()
+            .map_err(reqwest::Error::without_url)?
+            .json()
+            .await
+            .map_err(reqwest::Error::without_url)?)
+    }
+
+    async fn cancel_unguarded(
+        &self,
+        server_url: String,
+        token: &SessionToken,
+    ) -> Result<(), Error> {
+        let response = reqwest::Client::new()
+            .delete(&format!("{}/session/{}", server_url, token.expose()))
+            .send()
+            .await
+            .map_err(reqwest::Error::without_url)?;
+        if matches!(
+            response.status(),
+            reqwest::StatusCode::BAD_REQUEST | reqwest::StatusCode::NOT_FOUND
+        ) {
+            return Err(Error::UnknownSession());
+        }
+
+        response
+            .error_for_status()
+            .map_err(reqwest::Error::without_url)?;
+        Ok(())
     }
 
-    pub async fn get_result(&self, token: &str) -> Result<IrmaResult, Error> {
+    async fn get_result_unguarded(
+        &self,
+        server_url: String,
+        token: &SessionToken,
+    ) -> Result<IrmaResult, Error> {
         let client = reqwest::Client::new();
-        let session_result: RawIrmaResult = client
-            .get(&format!("{}/session/{}/result", self.server_url, token))
+        let mut response = client
+            .get(&format!(
+                "{}/session/{}/result",
+                server_url,
+                token.expose()
+            ))
             .send()
-            .await?
-            .json()
-            .await?;
+            .await
+            // The url contains the raw token, keep it out of the error
+            .map_err(reqwest::Error::without_url)?;
+        // The irma server responds with an error for tokens of sessions it
+        // doesn't know (anymore)
+        if matches!(
+            response.status(),
+            reqwest::StatusCode::BAD_REQUEST | reqwest::StatusCode::NOT_FOUND
+        ) {
+            return Err(Error::UnknownSession());
+        }
+        let server_time = response
+            .headers()
+            .get(reqwest::header::DATE)
+            .and_then(|date| date.to_str().ok())
+            .and_then(|date| httpdate::parse_http_date(date).ok());
+
+        // Deserialize the body while it arrives, on a blocking thread that is
+        // fed chunk by chunk, so only a few chunks are held in memory next to
+        // the parsed result. The size limit is enforced as chunks arrive.
+        let (sender, receiver) = mpsc::channel(RESULT_CHUNK_BUFFER);
+        let parser = task::spawn_blocking(move || {
+            serde_json::from_reader::<_, RawIrmaResult>(BufReader::new(ChunkReader {
+                receiver,
+                current: Bytes::new(),
+            }))
+        });
+        let mut size = 0;
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(reqwest::Error::without_url)?
+        {
+            size += chunk.len();
+            if size > MAX_RESULT_SIZE {
+                // Dropping the sender ends the body for the parser
+                return Err(Error::TooLarge());
+            }
+            // The parser only stops early on invalid json, reported below
+            if sender.send(chunk).await.is_err() {
+                break;
+            }
+        }
+        drop(sender);
+        let session_result = match parser.await {
+            Ok(session_result) => session_result?,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        };
+
+        Ok(IrmaResult {
+            server_time,
+            ..IrmaResult::try_from(session_result)?
+        })
+    }
+}
 
-        IrmaResult::try_from(session_result)
+/// Maximum size of a session result accepted from the irma server
+const MAX_RESULT_SIZE: usize = 1024 * 1024;
+
+/// Number of body chunks waiting for the parser before reading the body
+/// pauses
+const RESULT_CHUNK_BUFFER: usize = 4;
+
+// Blocking reader over body chunks sent by the task reading the response,
+// ending when that task drops its sender
+struct ChunkReader {
+    receiver: mpsc::Receiver<Bytes>,
+    current: Bytes,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.current.is_empty() {
+            match self.receiver.blocking_recv() {
+                Some(chunk) => self.current = chunk,
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.current.len());
+        buf[..len].copy_from_slice(&self.current.split_to(len));
+        Ok(len)
     }
 }
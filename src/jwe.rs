@@ -0,0 +1,287 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use josekit::{
+    jwe::{JweDecrypter, JweEncrypter, JweHeader},
+    jws::{self, JwsHeader, JwsSigner, JwsVerifier},
+    jwt::{self, JwtPayload, JwtPayloadValidator},
+    JoseError,
+};
+use serde::Serialize;
+use verder_helpen_proto::AuthResult;
+
+use crate::config::StatusSpelling;
+
+/// Claims added to the signed auth result on top of those in the result itself
+#[derive(Debug)]
+pub struct ResultClaims {
+    /// Time after issuing at which the result expires
+    pub validity: Duration,
+    /// Whether to add a not-before claim, set to the time of issuing
+    pub not_before: bool,
+    pub issuer: String,
+    pub audience: Option<String>,
+    /// Moment at which the irma server reported a valid disclosure
+    pub auth_time: Option<SystemTime>,
+    /// Requested attributes that were actually disclosed, in request order.
+    /// Requested attributes missing from this list were skipped by the user.
+    pub disclosed_keys: Option<Vec<String>>,
+    pub status_spelling: StatusSpelling,
+    /// Unique id of the result, allowing requestors to detect replays
+    pub jti: String,
+    /// Disclosed attributes grouped by the credential they came from
+    pub credentials: Option<CredentialGroups>,
+    /// Nonce the core sent along with the start request
+    pub nonce: Option<String>,
+    /// Continuation or attr_url the result is delivered to, so a relying
+    /// party can refuse results meant for another
+    pub destination: Option<String>,
+}
+
+/// Attribute values per credential id
+pub type CredentialGroups = HashMap<String, HashMap<String, String>>;
+
+pub(crate) fn to_value<T: Serialize>(value: T) -> Result<serde_json::Value, JoseError> {
+    serde_json::to_value(value).map_err(|e| JoseError::InvalidJson(e.into()))
+}
+
+/// Status claim of an auth result, with success in the configured spelling
+pub(crate) fn result_status(
+    auth_result: &AuthResult,
+    spelling: StatusSpelling,
+) -> Result<serde_json::Value, JoseError> {
+    let status = to_value(&auth_result.status)?;
+    match status.as_str() {
+        Some(status) if StatusSpelling::is_success(status) => Ok(spelling.success().into()),
+        _ => Ok(status),
+    }
+}
+
+/// Set the claims signed jwt results have in common on a payload issued at
+/// `now`: the registered claims, the status and the claims describing the
+/// session. The disclosed attributes are left to the format.
+pub(crate) fn set_result_claims(
+    payload: &mut JwtPayload,
+    auth_result: &AuthResult,
+    claims: &ResultClaims,
+    now: SystemTime,
+) -> Result<(), JoseError> {
+    payload.set_issued_at(&now);
+    payload.set_expires_at(&(now + claims.validity));
+    if claims.not_before {
+        payload.set_not_before(&now);
+    }
+    payload.set_issuer(&claims.issuer);
+    payload.set_jwt_id(&claims.jti);
+    if let Some(audience) = &claims.audience {
+        payload.set_audience(vec![audience.as_str()]);
+    }
+    payload.set_claim(
+        "status",
+        Some(result_status(auth_result, claims.status_spelling)?),
+    )?;
+    if let Some(session_url) = &auth_result.session_url {
+        payload.set_claim("session_url", Some(to_value(session_url)?))?;
+    }
+    if let Some(auth_time) = claims.auth_time {
+        let auth_time = auth_time
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| JoseError::InvalidClaim(e.into()))?;
+        payload.set_claim("auth_time", Some(to_value(auth_time.as_secs())?))?;
+    }
+    if let Some(disclosed_keys) = &claims.disclosed_keys {
+        payload.set_claim("disclosed_keys", Some(to_value(disclosed_keys)?))?;
+    }
+    if let Some(nonce) = &claims.nonce {
+        payload.set_claim("nonce", Some(to_value(nonce)?))?;
+    }
+    if let Some(destination) = &claims.destination {
+        payload.set_claim("destination", Some(to_value(destination)?))?;
+    }
+    Ok(())
+}
+
+/// Sign and encrypt an auth result, producing the nested jwt format of
+/// `verder_helpen_jwt::sign_and_encrypt_auth_result` with the additional
+/// claims added to the signed payload. The given key ids end up in the `kid`
+/// headers of the inner jws and outer jwe respectively. When compressing, the
+/// nested jws is deflated before encryption (`zip: DEF`).
+pub fn sign_and_encrypt_auth_result(
+    auth_result: &AuthResult,
+    claims: &ResultClaims,
+    signer: &dyn JwsSigner,
+    signing_key_id: Option<&str>,
+    encrypter: &dyn JweEncrypter,
+    encryption_key_id: Option<&str>,
+    compress: bool,
+) -> Result<String, JoseError> {
+    let mut sig_payload = JwtPayload::new();
+    set_result_claims(&mut sig_payload, auth_result, claims, SystemTime::now())?;
+    if let Some(attributes) = &auth_result.attributes {
+        sig_payload.set_claim("attributes", Some(to_value(attributes)?))?;
+    }
+    if let Some(credentials) = &claims.credentials {
+        sig_payload.set_claim("credentials", Some(to_value(credentials)?))?;
+    }
+    let mut sig_header = JwsHeader::new();
+    if let Some(kid) = signing_key_id {
+        sig_header.set_key_id(kid);
+    }
+    let jws = jwt::encode_with_signer(&sig_payload, &sig_header, signer)?;
+
+    let mut enc_payload = JwtPayload::new();
+    enc_payload.set_claim("njwt", Some(to_value(jws)?))?;
+    let mut enc_header = JweHeader::new();
+    if let Some(kid) = encryption_key_id {
+        enc_header.set_key_id(kid);
+    }
+    if compress {
+        enc_header.set_compression("DEF");
+    }
+    jwt::encode_with_encrypter(&enc_payload, &enc_header, encrypter)
+}
+
+/// Sign the parameters handed to the irma ui, so the ui can trust the
+/// continuation it redirects back to
+pub fn sign_irma_params(
+    continuation: Option<&str>,
+    qr: &str,
+    validity: Duration,
+    signer: &dyn JwsSigner,
+    key_id: Option<&str>,
+) -> Result<String, JoseError> {
+    let now = SystemTime::now();
+    let mut payload = JwtPayload::new();
+    payload.set_issued_at(&now);
+    payload.set_expires_at(&(now + validity));
+    if let Some(continuation) = continuation {
+        payload.set_claim("continuation", Some(to_value(continuation)?))?;
+    }
+    payload.set_claim("qr", Some(to_value(qr)?))?;
+
+    let mut header = JwsHeader::new();
+    header.set_token_type("JWT");
+    if let Some(kid) = key_id {
+        header.set_key_id(kid);
+    }
+    jwt::encode_with_signer(&payload, &header, signer)
+}
+
+/// Irma ui parameters as signed by `sign_irma_params`
+#[derive(Debug, Serialize)]
+pub struct IrmaParams {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub continuation: Option<String>,
+    pub qr: String,
+}
+
+/// Verify irma ui parameters signed by `sign_irma_params`, failing when the
+/// signature is invalid or the parameters expired
+pub fn verify_irma_params(
+    token: &str,
+    verifier: &dyn JwsVerifier,
+) -> Result<IrmaParams, JoseError> {
+    let (payload, header) = jwt::decode_with_verifier(token, verifier)?;
+    check_jwt_header("typ", header.token_type())?;
+    let mut validator = JwtPayloadValidator::new();
+    validator.set_base_time(SystemTime::now());
+    validator.validate(&payload)?;
+
+    let claim = |name: &str| payload.claim(name).and_then(|value| value.as_str());
+    Ok(IrmaParams {
+        continuation: claim("continuation").map(str::to_string),
+        qr: claim("qr")
+            .ok_or_else(|| JoseError::InvalidClaim(anyhow::anyhow!("Missing qr claim")))?
+            .to_string(),
+    })
+}
+
+/// Create a JWS with detached payload (RFC 7515, appendix F) over a body,
+/// allowing the receiver to authenticate it before decrypting
+pub fn sign_detached(
+    body: &str,
+    signer: &dyn JwsSigner,
+    key_id: Option<&str>,
+) -> Result<String, JoseError> {
+    let mut header = JwsHeader::new();
+    if let Some(kid) = key_id {
+        header.set_key_id(kid);
+    }
+    let jws = jws::serialize_compact(body.as_bytes(), &header, signer)?;
+    // A compact jws always consists of three parts: header, payload, signature
+    let header = jws.split('.').next().unwrap_or_default();
+    let signature = jws.rsplit('.').next().unwrap_or_default();
+    Ok(format!("{header}..{signature}"))
+}
+
+// Tokens may leave out typ and cty, but when present they should mark a jwt
+fn check_jwt_header(name: &str, value: Option<&str>) -> Result<(), JoseError> {
+    match value {
+        None => Ok(()),
+        Some(value) if value.eq_ignore_ascii_case("JWT") => Ok(()),
+        Some(value) => Err(JoseError::InvalidJwtFormat(anyhow::anyhow!(
+            "Unexpected {} header {}",
+            name,
+            value
+        ))),
+    }
+}
+
+/// Decrypt the outer layer of a nested jwt as produced by
+/// `sign_and_encrypt_auth_result`, returning the signed jwt nested in its
+/// `njwt` claim. Compressed tokens are inflated transparently.
+pub fn decrypt_result(token: &str, decrypter: &dyn JweDecrypter) -> Result<String, JoseError> {
+    let (enc_payload, enc_header) = jwt::decode_with_decrypter(token, decrypter)?;
+    check_jwt_header("typ", enc_header.token_type())?;
+    check_jwt_header("cty", enc_header.content_type())?;
+    enc_payload
+        .claim("njwt")
+        .and_then(|njwt| njwt.as_str())
+        .map(str::to_string)
+        .ok_or_else(|| JoseError::InvalidJwtFormat(anyhow::anyhow!("Missing njwt claim")))
+}
+
+/// Verify the signature of a signed jwt nested in a result, returning its
+/// claims without checking their validity period
+pub fn verify_result(njwt: &str, verifier: &dyn JwsVerifier) -> Result<JwtPayload, JoseError> {
+    let (sig_payload, sig_header) = jwt::decode_with_verifier(njwt, verifier)?;
+    check_jwt_header("typ", sig_header.token_type())?;
+    Ok(sig_payload)
+}
+
+/// Decrypt and verify a nested jwt as produced by
+/// `sign_and_encrypt_auth_result`, returning the disclosed attributes. Fails
+/// when either layer can't be decrypted or verified with the given keys, when
+/// the signed result is expired, or when it isn't nested in an `njwt` claim.
+/// Compressed tokens are inflated transparently.
+pub fn decrypt_and_verify_attributes(
+    token: &str,
+    decrypter: &dyn JweDecrypter,
+    verifier: &dyn JwsVerifier,
+) -> Result<HashMap<String, String>, JoseError> {
+    let njwt = decrypt_result(token, decrypter)?;
+    let sig_payload = verify_result(&njwt, verifier)?;
+    let mut validator = JwtPayloadValidator::new();
+    validator.set_base_time(SystemTime::now());
+    validator.validate(&sig_payload)?;
+    // Accept both spellings of success, whichever the producer uses
+    match sig_payload
+        .claim("status")
+        .and_then(|status| status.as_str())
+    {
+        Some(status) if StatusSpelling::is_success(status) => {}
+        _ => {
+            return Err(JoseError::InvalidClaim(anyhow::anyhow!(
+                "Result status is not success"
+            )))
+        }
+    }
+
+    match sig_payload.claim("attributes") {
+        Some(attributes) => serde_json::from_value(attributes.clone())
+            .map_err(|e| JoseError::InvalidClaim(e.into())),
+        None => Ok(HashMap::new()),
+    }
+}
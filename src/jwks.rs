@@ -0,0 +1,128 @@
+//! Public half of the signing key as JWK Set, so verifiers can fetch the
+//! current key instead of receiving it out-of-band.
+
+use josekit::{
+    jwk::{
+        alg::{ec::EcKeyPair, ed::EdKeyPair, rsa::RsaKeyPair},
+        Jwk,
+    },
+    jws::{
+        EdDSA, JwsSigner, JwsVerifier, ES256, ES384, ES512, PS256, PS384, PS512, RS256, RS384,
+        RS512,
+    },
+    JoseError,
+};
+use rocket::{
+    get,
+    http::{ContentType, Header, Status},
+    response::{self, Responder, Response},
+    Request,
+};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::{config, CurrentConfig};
+
+/// Time verifiers may cache the key set, kept short so rotated keys are
+/// picked up quickly
+const MAX_AGE_SECONDS: u64 = 300;
+
+// The part of the signing key configuration needed to derive its public key
+#[derive(Deserialize)]
+struct SignKeyMaterial {
+    #[serde(rename = "type")]
+    key_type: String,
+    key: String,
+}
+
+/// Serialized JWK Set with its entity tag
+#[derive(Debug, Clone)]
+pub struct Jwks {
+    body: String,
+    etag: String,
+}
+
+/// Derive the public half of the signing key from the signing key
+/// configuration, when its type is supported. Only public parameters end up
+/// in the key.
+pub fn public_signing_key(key_config: &Value) -> Result<Option<Jwk>, config::Error> {
+    let material: SignKeyMaterial = serde_json::from_value(key_config.clone())?;
+    let key_pair = material.key.as_bytes();
+    Ok(Some(match material.key_type.as_str() {
+        "RSA" => RsaKeyPair::from_pem(key_pair)?.to_jwk_public_key(),
+        "EC" => EcKeyPair::from_pem(key_pair, None)?.to_jwk_public_key(),
+        "EdDSA" => EdKeyPair::from_pem(key_pair)?.to_jwk_public_key(),
+        key_type => {
+            log::warn!("No public key for signing key of type {}", key_type);
+            return Ok(None);
+        }
+    }))
+}
+
+/// Verifier for signatures made with the given algorithm by the holder of the
+/// private half of a public key
+pub fn verifier_from_jwk(algorithm: &str, jwk: &Jwk) -> Result<Box<dyn JwsVerifier>, JoseError> {
+    Ok(match algorithm {
+        "RS256" => Box::new(RS256.verifier_from_jwk(jwk)?),
+        "RS384" => Box::new(RS384.verifier_from_jwk(jwk)?),
+        "RS512" => Box::new(RS512.verifier_from_jwk(jwk)?),
+        "PS256" => Box::new(PS256.verifier_from_jwk(jwk)?),
+        "PS384" => Box::new(PS384.verifier_from_jwk(jwk)?),
+        "PS512" => Box::new(PS512.verifier_from_jwk(jwk)?),
+        "ES256" => Box::new(ES256.verifier_from_jwk(jwk)?),
+        "ES384" => Box::new(ES384.verifier_from_jwk(jwk)?),
+        "ES512" => Box::new(ES512.verifier_from_jwk(jwk)?),
+        "EdDSA" => Box::new(EdDSA.verifier_from_jwk(jwk)?),
+        algorithm => {
+            return Err(JoseError::UnsupportedSignatureAlgorithm(anyhow::anyhow!(
+                "Unsupported signing algorithm {}",
+                algorithm
+            )))
+        }
+    })
+}
+
+impl Jwks {
+    /// Key set holding the public key of the signer
+    pub fn from_public_key(mut jwk: Jwk, signer: &dyn JwsSigner, key_id: Option<&str>) -> Jwks {
+        jwk.set_key_use("sig");
+        jwk.set_algorithm(signer.algorithm().name());
+        if let Some(key_id) = key_id {
+            jwk.set_key_id(key_id);
+        }
+
+        let jwk: Map<String, Value> = jwk.into();
+        let body = json!({ "keys": [jwk] }).to_string();
+        // A digest rather than the std hasher, so replicas built with other
+        // toolchains agree on the tag of the same key set
+        let etag = format!("\"{:x}\"", Sha256::digest(&body));
+        Jwks { body, etag }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Jwks {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let cache_control = Header::new(
+            "Cache-Control",
+            format!("public, max-age={}", MAX_AGE_SECONDS),
+        );
+        if request.headers().get_one("If-None-Match") == Some(self.etag.as_str()) {
+            return Response::build()
+                .status(Status::NotModified)
+                .raw_header("ETag", self.etag)
+                .header(cache_control)
+                .ok();
+        }
+        Response::build_from(self.body.respond_to(request)?)
+            .header(ContentType::new("application", "jwk-set+json"))
+            .raw_header("ETag", self.etag)
+            .header(cache_control)
+            .ok()
+    }
+}
+
+#[get("/.well-known/jwks.json")]
+pub async fn jwks(config: CurrentConfig) -> Option<Jwks> {
+    config.jwks().cloned()
+}
@@ -0,0 +1,1886 @@
+use std::{
+    collections::HashMap,
+    error::Error as StdError,
+    fmt::Display,
+    future::Future,
+    ops::{Deref, DerefMut},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use askama::Template;
+use audit::AuditEvent;
+use config::{AugmentReturnUrl, MissingAttributes, ResultFormat, UiParamsHandoff};
+use events::CompletionEvent;
+use failure::FailureReason;
+use irma::{IrmaDisclosureRequest, IrmaRequest};
+use josekit::JoseError;
+use rocket::{
+    catchers,
+    data::{self, Data, FromData, ToByteUnit},
+    fairing::AdHoc,
+    get,
+    http::{ContentType, CookieJar, Header, Status},
+    post,
+    request::{self, FromRequest, Request},
+    response::{Redirect, Response},
+    routes,
+    serde::json::Json,
+    Build, Responder, Rocket, State,
+};
+use serde::{Deserialize, Serialize};
+use verder_helpen_proto::{AuthResult, AuthStatus, StartAuthResponse};
+
+mod admin;
+mod audit;
+mod b64;
+mod binding;
+mod catchers;
+mod coalesce;
+pub mod config;
+mod continuation;
+pub mod cose;
+mod deliveries;
+mod events;
+mod failure;
+pub mod irma;
+pub mod jwe;
+mod jwks;
+#[cfg(feature = "mapping-cache")]
+mod mapping_cache;
+#[cfg(feature = "mock-irma")]
+pub mod mock_irma;
+pub mod panics;
+mod probe;
+mod quotas;
+pub mod redact;
+mod remote_keys;
+mod remote_signer;
+#[cfg(feature = "sentry")]
+mod sentry_context;
+mod session_status;
+pub mod smoke_test;
+mod store;
+mod test_mode;
+mod timings;
+mod usage;
+pub mod verify_result;
+mod vp;
+
+// Validity of the signed parameters handed to the irma ui, matching the
+// default lifetime of an irma session
+const IRMA_PARAMS_VALIDITY: Duration = Duration::from_secs(5 * 60);
+
+// Time the irma ui has to fetch its parameters after being redirected to
+const IRMA_UI_PARAMS_TTL: Duration = Duration::from_secs(60);
+
+// Time an unscanned session can be restarted, matching the time the irma
+// server keeps such a session
+const RESTARTABLE_SESSION_TTL: Duration = Duration::from_secs(5 * 60);
+
+// Maximum length in bytes of nonces, which travel along in callback and
+// continuation urls
+const MAX_NONCE_LENGTH: usize = 256;
+
+#[derive(Debug)]
+enum Error {
+    Irma(irma::Error),
+    Config(config::Error),
+    Param(b64::Error),
+    Json(serde_json::Error),
+    Jose(JoseError),
+    Cose(cose::Error),
+    Template(askama::Error),
+    InvalidUrl(&'static str, url::ParseError),
+    BadRequest(&'static str),
+    Forbidden(&'static str),
+    Gone(&'static str),
+    Conflict(&'static str),
+    Unavailable(&'static str, Duration),
+    IrmaUnavailable(irma::Error, Duration),
+    DeadlineExceeded(&'static str),
+    QuotaExceeded(Duration),
+}
+
+impl Error {
+    // Machine-readable code identifying the kind of error
+    fn code(&self) -> &'static str {
+        match self {
+            Error::Irma(_) => "irma",
+            Error::Config(_) => "config",
+            Error::Param(_) => "invalid_parameter",
+            Error::Json(_) => "json",
+            Error::Jose(_) => "jose",
+            Error::Cose(_) => "cose",
+            Error::Template(_) => "template",
+            Error::InvalidUrl(_, _) => "invalid_url",
+            Error::BadRequest(_) => "bad_request",
+            Error::Forbidden(_) => "forbidden",
+            Error::Gone(_) => "gone",
+            Error::Conflict(_) => "conflict",
+            Error::Unavailable(_, _) => "unavailable",
+            Error::IrmaUnavailable(_, _) => "irma_unavailable",
+            Error::DeadlineExceeded(_) => "deadline_exceeded",
+            Error::QuotaExceeded(_) => "quota_exceeded",
+        }
+    }
+
+    // Whether the error is caused by the client, and answered with a 4xx
+    #[cfg(feature = "sentry")]
+    fn is_client_error(&self) -> bool {
+        matches!(
+            self,
+            Error::Param(_)
+                | Error::InvalidUrl(_, _)
+                | Error::BadRequest(_)
+                | Error::Forbidden(_)
+                | Error::Gone(_)
+                | Error::Conflict(_)
+                | Error::QuotaExceeded(_)
+                | Error::Irma(irma::Error::InvalidPointer())
+                | Error::Config(
+                    config::Error::DuplicateAttributes(_)
+                        | config::Error::ValueNotAllowed(_)
+                        | config::Error::UnknownRequestorKey(_)
+                )
+        )
+    }
+}
+
+impl<'r, 'o: 'r> rocket::response::Responder<'r, 'o> for Error {
+    fn respond_to(self, request: &'r rocket::Request<'_>) -> rocket::response::Result<'o> {
+        #[cfg(feature = "sentry")]
+        sentry_context::capture_error(request, &self);
+        // Errors other than internal ones are described to the client
+        let page = |status: Status, message: Option<&str>| {
+            (
+                status,
+                catchers::ErrorPage::new(request, status, self.code(), message),
+            )
+                .respond_to(request)
+        };
+        match &self {
+            Error::BadRequest(desc) => page(Status::BadRequest, Some(desc)),
+            Error::Forbidden(desc) => page(Status::Forbidden, Some(desc)),
+            Error::Gone(desc) => page(Status::Gone, Some(desc)),
+            Error::Conflict(desc) => page(Status::Conflict, Some(desc)),
+            e @ (Error::Param(_) | Error::InvalidUrl(_, _)) => {
+                page(Status::BadRequest, Some(&e.to_string()))
+            }
+            Error::Unavailable(desc, retry_after) => {
+                Response::build_from(page(Status::ServiceUnavailable, Some(desc))?)
+                    .raw_header("Retry-After", retry_after.as_secs().to_string())
+                    .ok()
+            }
+            Error::IrmaUnavailable(e, retry_after) => {
+                log::warn!("Irma server unavailable: {}", e);
+                // The irma error may mention internal urls
+                Response::build_from(page(Status::ServiceUnavailable, None)?)
+                    .raw_header("Retry-After", retry_after.as_secs().to_string())
+                    .ok()
+            }
+            e @ Error::QuotaExceeded(retry_after) => {
+                Response::build_from(page(Status::TooManyRequests, Some(&e.to_string()))?)
+                    .raw_header("Retry-After", retry_after.as_secs().max(1).to_string())
+                    .raw_header("X-RateLimit-Remaining", "0")
+                    .ok()
+            }
+            Error::DeadlineExceeded(route) => {
+                log::warn!("Request to {} exceeded its deadline", route);
+                // Answered by the catcher, like other errors without details
+                Err(Status::GatewayTimeout)
+            }
+            e @ (Error::Irma(irma::Error::InvalidPointer())
+            | Error::Config(
+                config::Error::DuplicateAttributes(_)
+                | config::Error::ValueNotAllowed(_)
+                | config::Error::UnknownRequestorKey(_),
+            )) => page(Status::BadRequest, Some(&e.to_string())),
+            _ => {
+                // Log ourselves instead of through rocket's Debug responder,
+                // so sensitive values are also redacted from debug details
+                let config = match request.rocket().state::<config::SharedConfig>() {
+                    Some(config) => config.get(),
+                    None => {
+                        log::error!("Internal error");
+                        return Err(Status::InternalServerError);
+                    }
+                };
+                let message = format!("{:?}", self);
+                let message = config.redactor().redact(&message);
+                log::error!("Internal error: {}", message);
+                // Details may contain internal urls and tokens, so they are
+                // only exposed when explicitly enabled
+                if config.debug_errors() {
+                    (Status::InternalServerError, message.into_owned()).respond_to(request)
+                } else {
+                    Err(Status::InternalServerError)
+                }
+            }
+        }
+    }
+}
+
+impl From<irma::Error> for Error {
+    fn from(e: irma::Error) -> Error {
+        Error::Irma(e)
+    }
+}
+
+impl From<config::Error> for Error {
+    fn from(e: config::Error) -> Error {
+        Error::Config(e)
+    }
+}
+
+impl From<b64::Error> for Error {
+    fn from(e: b64::Error) -> Error {
+        Error::Param(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::Json(e)
+    }
+}
+
+impl From<JoseError> for Error {
+    fn from(e: JoseError) -> Error {
+        if remote_signer::is_unavailable(&e) {
+            log::warn!("{}", e);
+            return Error::Unavailable("Signing service unavailable", remote_signer::RETRY_AFTER);
+        }
+        Error::Jose(e)
+    }
+}
+
+impl From<cose::Error> for Error {
+    fn from(e: cose::Error) -> Error {
+        match e {
+            cose::Error::Jose(e) => Error::from(e),
+            e => Error::Cose(e),
+        }
+    }
+}
+
+impl From<askama::Error> for Error {
+    fn from(e: askama::Error) -> Error {
+        Error::Template(e)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Irma(e) => e.fmt(f),
+            Error::Config(e) => e.fmt(f),
+            Error::Param(e) => e.fmt(f),
+            Error::Json(e) => e.fmt(f),
+            Error::Jose(e) => e.fmt(f),
+            Error::Cose(e) => e.fmt(f),
+            Error::Template(e) => e.fmt(f),
+            Error::InvalidUrl(field, e) => write!(f, "Invalid {}: {}", field, e),
+            Error::BadRequest(desc) => f.write_str(desc),
+            Error::Forbidden(desc) => f.write_str(desc),
+            Error::Gone(desc) => f.write_str(desc),
+            Error::Conflict(desc) => f.write_str(desc),
+            Error::Unavailable(desc, _) => f.write_str(desc),
+            Error::IrmaUnavailable(e, _) => e.fmt(f),
+            Error::DeadlineExceeded(route) => {
+                write!(f, "Request to {} exceeded its deadline", route)
+            }
+            Error::QuotaExceeded(_) => f.write_str("Quota exceeded"),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Irma(e) => Some(e),
+            Error::Config(e) => Some(e),
+            Error::Param(e) => Some(e),
+            Error::Json(e) => Some(e),
+            Error::Jose(e) => Some(e),
+            Error::Cose(e) => Some(e),
+            Error::Template(e) => Some(e),
+            Error::InvalidUrl(_, e) => Some(e),
+            Error::BadRequest(_) => None,
+            Error::Forbidden(_) => None,
+            Error::Gone(_) => None,
+            Error::Conflict(_) => None,
+            Error::Unavailable(_, _) => None,
+            Error::IrmaUnavailable(e, _) => Some(e),
+            Error::DeadlineExceeded(_) => None,
+            Error::QuotaExceeded(_) => None,
+        }
+    }
+}
+
+// Standalone irma page, not served by any route yet
+#[allow(dead_code)]
+#[derive(Template)]
+#[template(path = "auth.html")]
+struct AuthTemplate<'a> {
+    continuation: &'a str,
+    // Session pointer as json that is safe to embed in a script element
+    session_ptr: String,
+}
+
+impl<'a> AuthTemplate<'a> {
+    #[allow(dead_code)]
+    fn new(qr: &str, continuation: &'a str) -> Result<AuthTemplate<'a>, Error> {
+        if !continuation.starts_with("https://") && !continuation.starts_with("http://") {
+            return Err(Error::BadRequest("Continuation must be an http(s) url"));
+        }
+        let session_ptr = serde_json::from_str::<serde_json::Value>(qr)?;
+        if !session_ptr.is_object() {
+            return Err(Error::BadRequest("Invalid session pointer"));
+        }
+        // Escape everything that could end the script element or be read as
+        // markup, json parsers read the escapes back as the original text
+        let session_ptr = serde_json::to_string(&session_ptr)?
+            .replace('<', "\\u003c")
+            .replace('>', "\\u003e")
+            .replace('&', "\\u0026");
+        Ok(AuthTemplate {
+            continuation,
+            session_ptr,
+        })
+    }
+}
+
+// Signed irma ui parameters awaiting retrieval by the ui
+struct ParamsStore(store::TtlStore<String>);
+
+// Host through which the browser reached us, as given in the Host header
+struct RequestHost(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestHost {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        request::Outcome::Success(RequestHost(request.host().map(|host| host.to_string())))
+    }
+}
+
+fn irma_ui_redirect(
+    config: &config::Config,
+    params: &ParamsStore,
+    host: &RequestHost,
+    qr: &str,
+    continuation: Option<&str>,
+) -> Result<Redirect, Error> {
+    let mut qr = irma::validate_pointer(&b64::decode_b64_str(qr)?, config.pointer_hosts())?;
+    if let Some(host) = config.session_pointer_host(host.0.as_deref()) {
+        qr = irma::override_pointer_host(&qr, host)?;
+    }
+
+    let token = jwe::sign_irma_params(
+        continuation,
+        &qr,
+        IRMA_PARAMS_VALIDITY,
+        config.signer(),
+        config.signing_key_id(),
+    )?;
+
+    let mut ui_url = config.ui_irma_url().clone();
+    match config.ui_params_handoff() {
+        UiParamsHandoff::Query => {
+            ui_url
+                .query_pairs_mut()
+                .append_pair(config.ui_token_parameter(), &token);
+        }
+        UiParamsHandoff::SessionId => {
+            let sid = params.0.insert(token);
+            ui_url.query_pairs_mut().append_pair("sid", &sid);
+        }
+    }
+    Ok(Redirect::to(ui_url.to_string()))
+}
+
+#[derive(Responder)]
+#[response(content_type = "application/jwt")]
+struct ParamsResponse {
+    token: String,
+    allow_origin: Header<'static>,
+}
+
+#[get("/params/<sid>")]
+async fn irma_ui_params(
+    config: CurrentConfig,
+    params: &State<ParamsStore>,
+    sid: String,
+) -> Option<ParamsResponse> {
+    params.0.take(&sid).map(|token| ParamsResponse {
+        token,
+        allow_origin: Header::new(
+            "Access-Control-Allow-Origin",
+            config.ui_irma_url().origin().ascii_serialization(),
+        ),
+    })
+}
+
+#[derive(Responder)]
+struct VerifiedParams {
+    params: Json<jwe::IrmaParams>,
+    allow_origin: Header<'static>,
+}
+
+// Verify ui parameters signed by us, for frontends that cannot verify the
+// signature themselves
+#[get("/verify_params?<token>")]
+async fn verify_ui_params(
+    config: CurrentConfig,
+    token: String,
+) -> Result<Option<VerifiedParams>, Error> {
+    let verifier = match config.signing_verifier() {
+        Some(verifier) => verifier,
+        None => return Ok(None),
+    };
+    match jwe::verify_irma_params(&token, verifier) {
+        Ok(params) => Ok(Some(VerifiedParams {
+            params: Json(params),
+            allow_origin: Header::new(
+                "Access-Control-Allow-Origin",
+                config.ui_irma_url().origin().ascii_serialization(),
+            ),
+        })),
+        Err(e) => {
+            log::debug!("Invalid ui parameters: {}", e);
+            Err(Error::BadRequest("Invalid or expired parameters"))
+        }
+    }
+}
+
+#[get("/auth/<qr>/<continuation>")]
+async fn auth_ui(
+    config: CurrentConfig,
+    maintenance: &State<admin::Maintenance>,
+    audit: &State<audit::AuditLog>,
+    params: &State<ParamsStore>,
+    host: RequestHost,
+    cookies: &CookieJar<'_>,
+    qr: String,
+    continuation: String,
+) -> Result<Redirect, Error> {
+    check_maintenance(&config, maintenance)?;
+    let continuation = b64::decode_b64_str(&continuation)?;
+    binding::bind(&config, cookies, &continuation)?;
+
+    let redirect = irma_ui_redirect(&config, params, &host, &qr, Some(&continuation))?;
+    audit.record(AuditEvent::QrShown {
+        session: binding::session_token(&continuation).map(|token| token.display_token()),
+    });
+    Ok(redirect)
+}
+
+// UI for out-of-band sessions without a browser continuation
+#[get("/auth/<qr>")]
+async fn auth_ui_without_continuation(
+    config: CurrentConfig,
+    maintenance: &State<admin::Maintenance>,
+    audit: &State<audit::AuditLog>,
+    params: &State<ParamsStore>,
+    host: RequestHost,
+    qr: String,
+) -> Result<Redirect, Error> {
+    check_maintenance(&config, maintenance)?;
+    let redirect = irma_ui_redirect(&config, params, &host, &qr, None)?;
+    audit.record(AuditEvent::QrShown { session: None });
+    Ok(redirect)
+}
+
+// Results retained for retrieval by the core through their session_url
+struct RetainedResultStore(store::TtlStore<String>);
+
+// Encode an auth result in the configured result format
+fn encode_auth_result(
+    config: &config::Config,
+    auth_result: &AuthResult,
+    claims: &jwe::ResultClaims,
+    requestor_key: Option<&str>,
+) -> Result<String, Error> {
+    match config.result_format() {
+        ResultFormat::Jose => {
+            let (encrypter, encryption_key_id) = config.result_encrypter(requestor_key)?;
+            Ok(jwe::sign_and_encrypt_auth_result(
+                auth_result,
+                claims,
+                config.signer(),
+                config.signing_key_id(),
+                encrypter.as_ref(),
+                encryption_key_id,
+                config.compress_results(),
+            )?)
+        }
+        ResultFormat::Cose => {
+            let (encrypter, encryption_key_id) = config.result_encrypter(requestor_key)?;
+            Ok(cose::sign_and_encrypt_auth_result(
+                auth_result,
+                claims,
+                config.signer(),
+                config.signing_key_id(),
+                encrypter.as_ref(),
+                encryption_key_id,
+            )?)
+        }
+        ResultFormat::Vp => {
+            let (encrypter, encryption_key_id) = config.result_encrypter(requestor_key)?;
+            Ok(vp::sign_and_encrypt_auth_result(
+                auth_result,
+                claims,
+                config.signer(),
+                config.signing_key_id(),
+                encrypter.as_ref(),
+                encryption_key_id,
+                config.vc_contexts(),
+                config.vc_types(),
+            )?)
+        }
+    }
+}
+
+// Who a result is for, as carried along with the session from its start
+struct ResultRecipient<'a> {
+    // Id of the requestor key to encrypt the result to
+    requestor_key: Option<&'a str>,
+    // Nonce of the core to echo in the result
+    nonce: Option<&'a str>,
+    // Continuation or attr_url the result is delivered to
+    destination: &'a str,
+}
+
+// Sign and encrypt an auth result, to the requestor key of the recipient when
+// set, returning it together with its jti. When result retention is enabled,
+// the result is also retained for later retrieval by the core through its
+// session_url.
+#[allow(clippy::too_many_arguments)]
+fn sign_auth_result(
+    config: &config::Config,
+    retained: &RetainedResultStore,
+    issued: &IssuedResults,
+    requested: &[String],
+    mut auth_result: AuthResult,
+    credentials: Option<jwe::CredentialGroups>,
+    auth_time: SystemTime,
+    recipient: ResultRecipient,
+) -> Result<(String, String), Error> {
+    let requestor_key = recipient.requestor_key;
+    let disclosed_keys = auth_result.attributes.as_ref().map(|disclosed| {
+        requested
+            .iter()
+            .map(|attribute| config.claim_name(attribute))
+            .filter(|claim_name| disclosed.contains_key(*claim_name))
+            .map(str::to_string)
+            .collect()
+    });
+    let claims = jwe::ResultClaims {
+        validity: config.result_validity(),
+        not_before: config.result_not_before(),
+        issuer: config.jwt_issuer().to_string(),
+        audience: config.jwt_audience().map(str::to_string),
+        auth_time: config.include_auth_time().then_some(auth_time),
+        disclosed_keys,
+        status_spelling: config.result_status_spelling(),
+        jti: store::random_id(),
+        credentials,
+        nonce: recipient.nonce.map(str::to_string),
+        destination: config
+            .bind_destination()
+            .then(|| recipient.destination.to_string()),
+    };
+    if config.track_result_ids() {
+        issued.0.insert_with_id(claims.jti.clone(), ());
+    }
+    if let Some(audit_encrypter) = config.audit_encrypter() {
+        // Only the holder of the audit key can decrypt this copy
+        let audit_copy = jwe::sign_and_encrypt_auth_result(
+            &auth_result,
+            &claims,
+            config.signer(),
+            config.signing_key_id(),
+            audit_encrypter,
+            audit_encrypter.key_id(),
+            config.compress_results(),
+        )?;
+        log::info!(target: "audit", "Result {}: {}", claims.jti, audit_copy);
+    }
+
+    if config.result_retention().is_none() {
+        let auth_result = encode_auth_result(config, &auth_result, &claims, requestor_key)?;
+        return Ok((auth_result, claims.jti));
+    }
+
+    let id = store::random_id();
+    auth_result.session_url = Some(format!("{}/retained_result/{}", config.server_url(), id));
+    let auth_result = encode_auth_result(config, &auth_result, &claims, requestor_key)?;
+    retained.0.insert_with_id(id, auth_result.clone());
+    Ok((auth_result, claims.jti))
+}
+
+// Ids of issued results that have not been consumed yet, kept for the
+// validity of the results
+struct IssuedResults(store::TtlStore<()>);
+
+// Mark a result as consumed by its jti, failing when it was consumed before
+// or is unknown, so requestors can detect replayed results
+#[post("/consume_result/<jti>")]
+async fn consume_result(issued: &State<IssuedResults>, jti: String) -> Status {
+    match issued.0.take(&jti) {
+        Some(()) => Status::NoContent,
+        None => Status::Conflict,
+    }
+}
+
+// Media type of encoded auth results
+fn result_content_type(config: &config::Config) -> ContentType {
+    match config.result_format() {
+        ResultFormat::Jose | ResultFormat::Vp => ContentType::new("application", "jwt"),
+        ResultFormat::Cose => ContentType::new("application", "cose"),
+    }
+}
+
+// Guard for routes only accessible to the core, which identifies itself
+// through the api key configured for result retention
+struct CoreAuthorization;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CoreAuthorization {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let config = request
+            .rocket()
+            .state::<config::SharedConfig>()
+            .map(|config| config.get());
+        let api_key = config
+            .as_deref()
+            .and_then(|config| config.result_retention())
+            .map(|retention| retention.api_key());
+
+        if admin::bearer_matches(request, api_key) {
+            request::Outcome::Success(CoreAuthorization)
+        } else {
+            request::Outcome::Error((Status::Unauthorized, ()))
+        }
+    }
+}
+
+#[get("/retained_result/<id>")]
+async fn retained_result(
+    _authorization: CoreAuthorization,
+    config: CurrentConfig,
+    retained: &State<RetainedResultStore>,
+    id: String,
+) -> Option<(ContentType, String)> {
+    retained
+        .0
+        .get(&id)
+        .map(|result| (result_content_type(&config), result))
+}
+
+#[get("/decorated_continue/<attributes>/<continuation>?<token>&<failure>&<key>&<nonce>")]
+async fn decorated_continue(
+    config: CurrentConfig,
+    results: &State<ResultStore>,
+    retained: &State<RetainedResultStore>,
+    issued: &State<IssuedResults>,
+    quotas: &State<quotas::Quotas>,
+    audit: &State<audit::AuditLog>,
+    cookies: &CookieJar<'_>,
+    token: Option<String>,
+    failure: Option<String>,
+    key: Option<String>,
+    nonce: Option<String>,
+    attributes: String,
+    continuation: String,
+) -> Result<Redirect, Error> {
+    let token = irma::SessionToken::new(token.ok_or(Error::BadRequest(
+        "The token query parameter identifying the irma session is required",
+    ))?);
+    binding::verify(&config, cookies, &token)?;
+    let continuation = b64::decode_b64_str(&continuation)?;
+    let failure_continuation = failure.as_deref().map(b64::decode_b64_str).transpose()?;
+    let requestor_key = key.as_deref().map(b64::decode_b64_str).transpose()?;
+    let nonce = nonce.as_deref().map(b64::decode_b64_str).transpose()?;
+    let attributes = b64::decode_b64_json::<Vec<String>>(&attributes)?;
+    // The session is over, whether its result turns out usable or not
+    quotas.session_ended(&token);
+
+    let (disclosed, clock_skew) = match within_deadline(
+        &config,
+        "decorated_continue",
+        disclosed_attributes(&config, &token, &attributes),
+    )
+    .await
+    {
+        Ok(disclosed) => disclosed,
+        Err(e) => {
+            let reason = FailureReason::of(&e);
+            audit.record(AuditEvent::Failed {
+                session: token.display_token(),
+                reason: reason.map_or("error", FailureReason::as_str),
+            });
+            events::notify(
+                &config,
+                CompletionEvent::failure(
+                    requestor_key.as_deref(),
+                    reason.map_or("error", FailureReason::as_str),
+                ),
+            );
+            match reason.filter(|reason| {
+                failure_continuation.is_some() || reports_failure(&config, *reason)
+            }) {
+                Some(reason) => {
+                    return Ok(Redirect::to(continuation::append_result(
+                        failure_continuation.as_deref().unwrap_or(&continuation),
+                        "error",
+                        reason.as_str(),
+                        config.result_in_fragment(),
+                    )))
+                }
+                None => return Err(e),
+            }
+        }
+    };
+    let auth_time = SystemTime::now();
+
+    let credentials = config
+        .group_by_credential()
+        .then(|| disclosed.by_credential());
+    let auth_result = AuthResult {
+        status: AuthStatus::Success,
+        attributes: Some(disclosed.values),
+        session_url: None,
+    };
+    let (auth_result, jti) = match sign_auth_result(
+        &config,
+        retained,
+        issued,
+        &attributes,
+        auth_result,
+        credentials,
+        auth_time,
+        ResultRecipient {
+            requestor_key: requestor_key.as_deref(),
+            nonce: nonce.as_deref(),
+            destination: &continuation,
+        },
+    ) {
+        Ok(signed) => signed,
+        Err(e) => {
+            events::notify(
+                &config,
+                CompletionEvent::failure(requestor_key.as_deref(), "error"),
+            );
+            return Err(e);
+        }
+    };
+    usage::session_completed(&attributes);
+    timings::session_completed(&token);
+    audit.record(AuditEvent::Completed {
+        session: token.display_token(),
+        attributes: &attributes,
+        clock_skew,
+    });
+
+    let redirect = continuation_redirect(&config, results, &continuation, auth_result);
+    events::notify(
+        &config,
+        CompletionEvent::success(requestor_key.as_deref(), jti),
+    );
+    Ok(redirect)
+}
+
+// Bound the time a request waits on the irma server or a requestor's
+// callback. Exceeding the deadline drops, and thereby cancels, the pending
+// work.
+async fn within_deadline<T, E: Into<Error>>(
+    config: &config::Config,
+    route: &'static str,
+    work: impl Future<Output = Result<T, E>>,
+) -> Result<T, Error> {
+    match rocket::tokio::time::timeout(config.irma_request_timeout(), work).await {
+        Ok(result) => result.map_err(Into::into),
+        Err(_) => Err(Error::DeadlineExceeded(route)),
+    }
+}
+
+// Clock skew between the irma server and this plugin as seen in a session
+// result, warning when it exceeds the configured threshold. Returns the skew
+// when it is to be included in the audit log.
+fn clock_skew(config: &config::Config, session_result: &irma::IrmaResult) -> Option<i64> {
+    let skew = session_result.clock_skew(SystemTime::now())?;
+    if let Some(threshold) = config.clock_skew_threshold() {
+        if skew.unsigned_abs() > threshold.as_secs() {
+            log::warn!(
+                "Clock of the irma server is {} seconds {} ours",
+                skew.unsigned_abs(),
+                if skew > 0 { "ahead of" } else { "behind" }
+            );
+        }
+    }
+    config.audit_clock_skew().then_some(skew)
+}
+
+// Fetch the result of an irma session and map it to the requested attributes,
+// together with the clock skew to audit
+async fn disclosed_attributes(
+    config: &config::Config,
+    token: &irma::SessionToken,
+    attributes: &[String],
+) -> Result<(config::MappedAttributes, Option<i64>), Error> {
+    let session_result = config
+        .irma_server()
+        .get_result(token)
+        .await
+        .map_err(|e| match e {
+            irma::Error::UnknownSession() => Error::Gone("Unknown or expired session"),
+            e => Error::from(e),
+        })?;
+    let clock_skew = clock_skew(config, &session_result);
+    Ok((config.map_response(attributes, session_result)?, clock_skew))
+}
+
+// Whether failures for the given reason are reported to the continuation
+// instead of ending the session with an error
+fn reports_failure(config: &config::Config, reason: FailureReason) -> bool {
+    config.report_failures()
+        || (reason == FailureReason::MissingAttributes
+            && config.missing_attributes() == MissingAttributes::Report)
+}
+
+// Redirect the browser to the continuation, passing it the result or a
+// reference to it
+fn continuation_redirect(
+    config: &config::Config,
+    results: &ResultStore,
+    continuation: &str,
+    auth_result: String,
+) -> Redirect {
+    if config.result_by_reference() {
+        let result_ref = results.0.insert(auth_result);
+        Redirect::to(continuation::append_result(
+            continuation,
+            "result_ref",
+            &result_ref,
+            config.result_in_fragment(),
+        ))
+    } else {
+        Redirect::to(continuation::append_result(
+            continuation,
+            "result",
+            &auth_result,
+            config.result_in_fragment(),
+        ))
+    }
+}
+
+// Results awaiting a single retrieval by reference
+struct ResultStore(store::TtlStore<String>);
+
+// Fetch a result once by reference. References are unguessable, so unknown
+// references are treated as expired, evicted or already fetched.
+#[get("/result/<result_ref>")]
+async fn fetch_result(
+    config: CurrentConfig,
+    results: &State<ResultStore>,
+    result_ref: String,
+) -> Result<(ContentType, String), Error> {
+    results
+        .0
+        .take(&result_ref)
+        .map(|result| (result_content_type(&config), result))
+        .ok_or(Error::Gone("Result expired or already fetched"))
+}
+
+// Tokens of out-of-band sessions that may still complete, expiring after the
+// configured maximum session age
+struct PendingSessions(store::TtlStore<()>);
+
+// Requests of started irma sessions, by session token, so unscanned sessions
+// can be restarted with a fresh QR code
+struct RestartableSessions(store::TtlStore<AuthRequest>);
+
+#[derive(Debug, Deserialize)]
+struct IrmaServerPost {
+    token: irma::SessionToken,
+}
+#[post(
+    "/session_complete/<attributes>/<attr_url>?<key>&<nonce>",
+    data = "<token>"
+)]
+async fn session_complete(
+    config: CurrentConfig,
+    retained: &State<RetainedResultStore>,
+    issued: &State<IssuedResults>,
+    pending: &State<PendingSessions>,
+    quotas: &State<quotas::Quotas>,
+    audit: &State<audit::AuditLog>,
+    token: Json<IrmaServerPost>,
+    key: Option<String>,
+    nonce: Option<String>,
+    attributes: String,
+    attr_url: String,
+) -> Result<(), Error> {
+    let attr_url = b64::decode_b64_str(&attr_url)?;
+    let requestor_key = key.as_deref().map(b64::decode_b64_str).transpose()?;
+    let nonce = nonce.as_deref().map(b64::decode_b64_str).transpose()?;
+    let attributes = b64::decode_b64_json::<Vec<String>>(&attributes)?;
+
+    if config.max_session_age().is_some() && pending.0.take(token.token.expose()).is_none() {
+        events::notify(
+            &config,
+            CompletionEvent::failure(
+                requestor_key.as_deref(),
+                FailureReason::ExpiredSession.as_str(),
+            ),
+        );
+        return Err(Error::Gone("Session expired or unknown"));
+    }
+    quotas.session_ended(&token.token);
+
+    let disclosed = within_deadline(
+        &config,
+        "session_complete",
+        config.irma_server().get_result(&token.token),
+    )
+    .await
+    .and_then(|session_result| {
+        let clock_skew = clock_skew(&config, &session_result);
+        Ok((
+            config.map_response(&attributes, session_result)?,
+            clock_skew,
+        ))
+    });
+    let auth_time = SystemTime::now();
+    let (disclosed, clock_skew) = match disclosed {
+        Ok(disclosed) => disclosed,
+        Err(e) => {
+            let reason = FailureReason::of(&e).map_or("error", FailureReason::as_str);
+            audit.record(AuditEvent::Failed {
+                session: token.token.display_token(),
+                reason,
+            });
+            events::notify(
+                &config,
+                CompletionEvent::failure(requestor_key.as_deref(), reason),
+            );
+            return Err(e);
+        }
+    };
+    let credentials = config
+        .group_by_credential()
+        .then(|| disclosed.by_credential());
+    let auth_result = AuthResult {
+        status: AuthStatus::Success,
+        attributes: Some(disclosed.values),
+        session_url: None,
+    };
+    let (auth_result, jti) = match sign_auth_result(
+        &config,
+        retained,
+        issued,
+        &attributes,
+        auth_result,
+        credentials,
+        auth_time,
+        ResultRecipient {
+            requestor_key: requestor_key.as_deref(),
+            nonce: nonce.as_deref(),
+            destination: &attr_url,
+        },
+    ) {
+        Ok(signed) => signed,
+        Err(e) => {
+            events::notify(
+                &config,
+                CompletionEvent::failure(requestor_key.as_deref(), "error"),
+            );
+            return Err(e);
+        }
+    };
+
+    usage::session_completed(&attributes);
+    timings::session_completed(&token.token);
+    audit.record(AuditEvent::Completed {
+        session: token.token.display_token(),
+        attributes: &attributes,
+        clock_skew,
+    });
+
+    let delivered = within_deadline(
+        &config,
+        "session_complete",
+        deliver_result(&config, &attr_url, auth_result),
+    )
+    .await;
+    let requestor = requestor_key.as_deref();
+    events::notify(
+        &config,
+        match delivered {
+            Ok(true) => CompletionEvent::success(requestor, jti),
+            Ok(false) => CompletionEvent::failure(requestor, "undelivered"),
+            Err(_) => CompletionEvent::failure(requestor, "error"),
+        },
+    );
+    if delivered? {
+        audit.record(AuditEvent::ResultDelivered {
+            session: token.token.display_token(),
+        });
+    }
+    Ok(())
+}
+
+// Deliver a result out-of-band to the attr_url of the session, returning
+// whether it was delivered. Delivery failures are only logged, as there is
+// nobody to report them to.
+async fn deliver_result(
+    config: &config::Config,
+    attr_url: &str,
+    auth_result: String,
+) -> Result<bool, Error> {
+    let client = reqwest::Client::new();
+    let content_type = match config.callback_content_type() {
+        Some(content_type) => content_type.to_string(),
+        None => result_content_type(config).to_string(),
+    };
+    let mut callback = client.post(attr_url).header("Content-Type", content_type);
+    if let Some(signer) = config.callback_signer() {
+        callback = callback.header(
+            "X-Callback-Signature",
+            jwe::sign_detached(&auth_result, signer, config.callback_key_id())?,
+        );
+    }
+    let started = Instant::now();
+    let result = callback.body(auth_result).send().await;
+    let host = url::Url::parse(attr_url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_else(|| "unknown".to_string());
+    deliveries::record(
+        &host,
+        started.elapsed(),
+        deliveries::Failure::classify(&result),
+    );
+    if let Err(e) = &result {
+        // Log only
+        log::error!("Failure reporting results: {}", e);
+    }
+    Ok(result.is_ok())
+}
+
+// Request to start an authentication session. This mirrors the
+// StartAuthRequest from the protocol, except that the continuation is optional
+// for out-of-band sessions, where results are only delivered to the attr_url,
+// and that failed in-band sessions can be sent to a separate continuation.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct AuthRequest {
+    attributes: Vec<String>,
+    continuation: Option<String>,
+    failure_continuation: Option<String>,
+    attr_url: Option<String>,
+    // Values some of the attributes have to be disclosed with, restricted to
+    // the configured allowed values
+    #[serde(default)]
+    attribute_values: HashMap<String, String>,
+    // Id of the configured requestor key to encrypt the result to, for
+    // brokers starting sessions on behalf of several requestors
+    requestor_key_id: Option<String>,
+    // Opaque value of the core echoed in the signed result, binding the
+    // result to this request
+    nonce: Option<String>,
+    // How the user is going to open the session in the app
+    #[serde(default)]
+    client: Client,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Client {
+    // Scanning a QR code shown in the browser
+    #[default]
+    Web,
+    // Following a deep link into the app on the same device
+    App,
+}
+
+// Refuse to start new sessions in maintenance mode. Sessions already in
+// flight are still finalized.
+fn check_maintenance(
+    config: &config::Config,
+    maintenance: &admin::Maintenance,
+) -> Result<(), Error> {
+    if maintenance.enabled() {
+        return Err(Error::Unavailable(
+            "In maintenance",
+            config.maintenance_retry_after(),
+        ));
+    }
+    Ok(())
+}
+
+// Json body of a start request, read with its own strict size limit instead
+// of rocket's json limit, so oversized requests are refused before they are
+// buffered
+struct StartRequest(AuthRequest);
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for StartRequest {
+    type Error = ();
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let limit = match request.rocket().state::<config::SharedConfig>() {
+            Some(config) => config.get().start_request_limit(),
+            None => return data::Outcome::Error((Status::InternalServerError, ())),
+        };
+        let body = match data.open(limit.bytes()).into_string().await {
+            Ok(body) if body.is_complete() => body.into_inner(),
+            Ok(_) => return data::Outcome::Error((Status::PayloadTooLarge, ())),
+            Err(_) => return data::Outcome::Error((Status::BadRequest, ())),
+        };
+        match serde_json::from_str(&body) {
+            Ok(auth_request) => data::Outcome::Success(StartRequest(auth_request)),
+            Err(_) => data::Outcome::Error((Status::UnprocessableEntity, ())),
+        }
+    }
+}
+
+impl Deref for StartRequest {
+    type Target = AuthRequest;
+
+    fn deref(&self) -> &AuthRequest {
+        &self.0
+    }
+}
+
+impl DerefMut for StartRequest {
+    fn deref_mut(&mut self) -> &mut AuthRequest {
+        &mut self.0
+    }
+}
+
+// Parse a url from an authentication request, replacing it by its normalized
+// form so typos are caught before an irma session is started
+fn normalize_url(field: &'static str, url: &mut Option<String>) -> Result<(), Error> {
+    if let Some(raw) = url {
+        let parsed = url::Url::parse(raw).map_err(|e| Error::InvalidUrl(field, e))?;
+        if parsed.cannot_be_a_base() || parsed.host().is_none() {
+            return Err(Error::InvalidUrl(field, url::ParseError::EmptyHost));
+        }
+        *raw = parsed.into();
+    }
+    Ok(())
+}
+
+// Whether results can be sent to the url without crossing the network in
+// cleartext, exempting loopback and the given hosts for development
+fn is_secure_url(url: &str, insecure_hosts: &[String]) -> bool {
+    let url = match url::Url::parse(url) {
+        Ok(url) => url,
+        Err(_) => return false,
+    };
+    let insecure_host = insecure_hosts
+        .iter()
+        .any(|host| url.host_str() == Some(host.as_str()));
+    match (url.scheme(), url.host()) {
+        ("https", Some(_)) => true,
+        ("http", Some(_)) if insecure_host => true,
+        ("http", Some(url::Host::Domain(domain))) => domain.eq_ignore_ascii_case("localhost"),
+        ("http", Some(url::Host::Ipv4(ip))) => ip.is_loopback(),
+        ("http", Some(url::Host::Ipv6(ip))) => ip.is_loopback(),
+        _ => false,
+    }
+}
+
+// start session with out-of-band return of attributes
+async fn start_oob(
+    config: &config::Config,
+    pending: &State<PendingSessions>,
+    restartable: &RestartableSessions,
+    quotas: &quotas::Quotas,
+    audit: &audit::AuditLog,
+    request: &AuthRequest,
+    attr_url: &str,
+) -> Result<Json<StartAuthResponse>, Error> {
+    let session_request = IrmaRequest::Disclosure(IrmaDisclosureRequest {
+        disclose: config
+            .map_attributes_with_values(&request.attributes, &request.attribute_values)?,
+        return_url: request.continuation.clone(),
+        augment_return: false,
+    });
+
+    log::trace!("With attr url");
+
+    let mut callback_url = format!(
+        "{}/session_complete/{}/{}",
+        config.internal_url(),
+        b64::encode_b64(serde_json::to_vec(&request.attributes)?),
+        b64::encode_b64(attr_url)
+    );
+    let mut query = vec![];
+    if let Some(requestor_key) = &request.requestor_key_id {
+        query.push(format!("key={}", b64::encode_b64(requestor_key)));
+    }
+    if let Some(nonce) = &request.nonce {
+        query.push(format!("nonce={}", b64::encode_b64(nonce)));
+    }
+    if !query.is_empty() {
+        callback_url = format!("{}?{}", callback_url, query.join("&"));
+    }
+
+    let session = config
+        .irma_server()
+        .start_with_callback(&session_request, &callback_url)
+        .await?;
+    if config.max_session_age().is_some() {
+        pending
+            .0
+            .insert_with_id(session.token.expose().to_string(), ());
+    }
+    restartable
+        .0
+        .insert_with_id(session.token.expose().to_string(), request.clone());
+    quotas.session_started(request.requestor_key_id.as_deref(), &session.token);
+    usage::session_started(&request.attributes);
+    timings::session_started(&session.token);
+    audit.record(AuditEvent::Started {
+        session: session.token.display_token(),
+        flow: "out_of_band",
+        attributes: &request.attributes,
+    });
+
+    let client_url = match &request.continuation {
+        Some(continuation) => format!(
+            "{}/auth/{}/{}",
+            config.server_url(),
+            b64::encode_b64(session.qr),
+            b64::encode_b64(continuation),
+        ),
+        None => format!(
+            "{}/auth/{}",
+            config.server_url(),
+            b64::encode_b64(session.qr),
+        ),
+    };
+
+    Ok(Json(StartAuthResponse { client_url }))
+}
+
+// start session with in-band return of attributes
+async fn start_ib(
+    config: &config::Config,
+    restartable: &RestartableSessions,
+    quotas: &quotas::Quotas,
+    audit: &audit::AuditLog,
+    request: &AuthRequest,
+    continuation: &str,
+) -> Result<Json<StartAuthResponse>, Error> {
+    let mut continuation_url = format!(
+        "{}/decorated_continue/{}/{}",
+        config.server_url(),
+        b64::encode_b64(serde_json::to_vec(&request.attributes)?),
+        b64::encode_b64(continuation)
+    );
+    let mut query = vec![];
+    if let Some(failure_continuation) = &request.failure_continuation {
+        query.push(format!("failure={}", b64::encode_b64(failure_continuation)));
+    }
+    if let Some(requestor_key) = &request.requestor_key_id {
+        query.push(format!("key={}", b64::encode_b64(requestor_key)));
+    }
+    if let Some(nonce) = &request.nonce {
+        query.push(format!("nonce={}", b64::encode_b64(nonce)));
+    }
+    if !query.is_empty() {
+        continuation_url = format!("{}?{}", continuation_url, query.join("&"));
+    }
+    let token_separator = if continuation_url.contains('?') {
+        '&'
+    } else {
+        '?'
+    };
+
+    log::trace!("Without attr url");
+
+    let session_request = IrmaRequest::Disclosure(IrmaDisclosureRequest {
+        disclose: config
+            .map_attributes_with_values(&request.attributes, &request.attribute_values)?,
+        return_url: Some(continuation_url.clone()),
+        augment_return: match config.augment_return_url() {
+            AugmentReturnUrl::Always => true,
+            AugmentReturnUrl::Never => false,
+            AugmentReturnUrl::WebOnly => request.client == Client::Web,
+        },
+    });
+
+    let session = config.irma_server().start(&session_request).await?;
+    restartable
+        .0
+        .insert_with_id(session.token.expose().to_string(), request.clone());
+    quotas.session_started(request.requestor_key_id.as_deref(), &session.token);
+    usage::session_started(&request.attributes);
+    timings::session_started(&session.token);
+    audit.record(AuditEvent::Started {
+        session: session.token.display_token(),
+        flow: "in_band",
+        attributes: &request.attributes,
+    });
+
+    Ok(Json(StartAuthResponse {
+        client_url: format!(
+            "{}/auth/{}/{}",
+            config.server_url(),
+            b64::encode_b64(&session.qr),
+            b64::encode_b64(format!(
+                "{}{}token={}",
+                continuation_url,
+                token_separator,
+                session.token.expose()
+            )),
+        ),
+    }))
+}
+
+#[post("/start_authentication", data = "<request>")]
+async fn start_authentication(
+    config: CurrentConfig,
+    maintenance: &State<admin::Maintenance>,
+    pending: &State<PendingSessions>,
+    test_sessions: &State<test_mode::TestSessions>,
+    restartable: &State<RestartableSessions>,
+    audit: &State<audit::AuditLog>,
+    quotas: &State<quotas::Quotas>,
+    flights: &State<coalesce::StartFlights>,
+    idempotency_key: coalesce::IdempotencyKey,
+    mut request: StartRequest,
+) -> Result<quotas::WithRemaining<Json<StartAuthResponse>>, Error> {
+    check_maintenance(&config, maintenance)?;
+    if request.attributes.is_empty() && !config.allows_presence_only() {
+        return Err(Error::BadRequest("No attributes requested"));
+    }
+    request.attributes = config.normalize_attributes(&request.attributes)?;
+    normalize_url("attr_url", &mut request.attr_url)?;
+    normalize_url("continuation", &mut request.continuation)?;
+    normalize_url("failure_continuation", &mut request.failure_continuation)?;
+    if request
+        .nonce
+        .as_ref()
+        .is_some_and(|nonce| nonce.len() > MAX_NONCE_LENGTH)
+    {
+        return Err(Error::BadRequest("nonce too long"));
+    }
+    // Keys that are published in a key set may not be fetched yet
+    config
+        .result_encrypter(request.requestor_key_id.as_deref())
+        .map_err(|e| match e {
+            config::Error::KeyUnavailable(_) => {
+                Error::Unavailable("Encryption key unavailable", config.irma_retry_after())
+            }
+            e => e.into(),
+        })?;
+    if !config.allow_insecure_urls() {
+        let urls = [
+            &request.attr_url,
+            &request.continuation,
+            &request.failure_continuation,
+        ];
+        if !urls
+            .iter()
+            .copied()
+            .flatten()
+            .all(|url| is_secure_url(url, config.insecure_url_hosts()))
+        {
+            return Err(Error::BadRequest(
+                "attr_url and continuations must be https urls",
+            ));
+        }
+    }
+    // Without a key id, a requestor would escape its quota
+    if config.has_requestor_quotas() && request.requestor_key_id.is_none() {
+        return Err(Error::BadRequest("requestor_key_id is required"));
+    }
+    let quota = request
+        .requestor_key_id
+        .as_deref()
+        .and_then(|id| Some((id, config.requestor_quota(id)?)));
+    let remaining = match quota {
+        Some((id, quota)) => quotas
+            .check(id, quota)
+            .map_err(|quotas::Exceeded(retry_after)| Error::QuotaExceeded(retry_after))?,
+        None => None,
+    };
+
+    let response = start_counted(
+        &config,
+        pending,
+        test_sessions,
+        restartable,
+        audit,
+        quotas,
+        flights,
+        idempotency_key,
+        &request,
+    )
+    .await;
+    // Only starts that succeed count against the quota
+    if let (Err(_), Some((id, _))) = (&response, quota) {
+        quotas.refund(id);
+    }
+    response.map(|response| quotas::WithRemaining(response, remaining))
+}
+
+// Start a session for a request that passed the quota check, through the
+// flight of its idempotency key when coalescing
+#[allow(clippy::too_many_arguments)]
+async fn start_counted(
+    config: &config::Config,
+    pending: &State<PendingSessions>,
+    test_sessions: &test_mode::TestSessions,
+    restartable: &RestartableSessions,
+    audit: &audit::AuditLog,
+    quotas: &quotas::Quotas,
+    flights: &coalesce::StartFlights,
+    idempotency_key: coalesce::IdempotencyKey,
+    request: &AuthRequest,
+) -> Result<Json<StartAuthResponse>, Error> {
+    if config.test_mode_enabled() {
+        return Ok(Json(test_mode::start(config, test_sessions, request)?));
+    }
+
+    let start = within_deadline(
+        config,
+        "start_authentication",
+        start_session(config, pending, restartable, quotas, audit, request),
+    );
+    let response = match idempotency_key.0.filter(|_| config.coalesce_starts()) {
+        Some(key) => flights
+            .run(&coalesce::flight_key(&key, request)?, async {
+                start.await.map(|Json(response)| response.client_url)
+            })
+            .await
+            .map(|client_url| Json(StartAuthResponse { client_url })),
+        None => start.await,
+    };
+    retry_when_unavailable(config, response)
+}
+
+// Start an irma session for a validated request
+async fn start_session(
+    config: &config::Config,
+    pending: &State<PendingSessions>,
+    restartable: &RestartableSessions,
+    quotas: &quotas::Quotas,
+    audit: &audit::AuditLog,
+    request: &AuthRequest,
+) -> Result<Json<StartAuthResponse>, Error> {
+    match (&request.attr_url, &request.continuation) {
+        (Some(attr_url), _) => {
+            start_oob(
+                config,
+                pending,
+                restartable,
+                quotas,
+                audit,
+                request,
+                attr_url,
+            )
+            .await
+        }
+        (None, Some(continuation)) => {
+            start_ib(config, restartable, quotas, audit, request, continuation).await
+        }
+        (None, None) => Err(Error::BadRequest(
+            "Either a continuation or an attr_url is required",
+        )),
+    }
+}
+
+// Let the core retry later instead of treating an unreachable irma server as
+// a permanent failure
+fn retry_when_unavailable<T>(
+    config: &config::Config,
+    response: Result<T, Error>,
+) -> Result<T, Error> {
+    response.map_err(|e| match e {
+        Error::Irma(e) if e.is_unavailable() => {
+            Error::IrmaUnavailable(e, config.irma_retry_after())
+        }
+        e => e,
+    })
+}
+
+// Replace an unscanned session by a new one for the same request, so the
+// frontend can show a fresh QR code in a single call
+#[post("/restart_authentication/<token>")]
+async fn restart_authentication(
+    config: CurrentConfig,
+    maintenance: &State<admin::Maintenance>,
+    pending: &State<PendingSessions>,
+    restartable: &State<RestartableSessions>,
+    quotas: &State<quotas::Quotas>,
+    audit: &State<audit::AuditLog>,
+    token: String,
+) -> Result<Option<Json<StartAuthResponse>>, Error> {
+    check_maintenance(&config, maintenance)?;
+    if restartable.0.get(&token).is_none() {
+        return Ok(None);
+    }
+
+    let token = irma::SessionToken::new(token);
+    let status = within_deadline(
+        &config,
+        "restart_authentication",
+        config.irma_server().status(&token),
+    )
+    .await;
+    match retry_when_unavailable(&config, status)? {
+        irma::SessionStatus::Initialized => {}
+        _ => return Err(Error::Conflict("Session can no longer be restarted")),
+    }
+    // Another restart of the same session may have beaten us to it
+    let request = match restartable.0.take(token.expose()) {
+        Some(request) => request,
+        None => return Ok(None),
+    };
+
+    quotas.session_ended(&token);
+
+    // Best effort, the old session expires by itself otherwise
+    let cancelled = within_deadline(
+        &config,
+        "restart_authentication",
+        config.irma_server().cancel(&token),
+    )
+    .await;
+    if let Err(e) = cancelled {
+        log::warn!(
+            "Could not cancel restarted session {}: {}",
+            token.display_token(),
+            e
+        );
+    }
+
+    let response = within_deadline(
+        &config,
+        "restart_authentication",
+        start_session(&config, pending, restartable, quotas, audit, &request),
+    )
+    .await;
+    retry_when_unavailable(&config, response).map(Some)
+}
+
+#[derive(Debug, Serialize)]
+struct AttributeInfo {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    irma_ids: Option<Vec<String>>,
+    requirement: config::Requirement,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<config::AttributeFormat>,
+}
+
+// List the attributes this deployment supports, with whether they have to be
+// disclosed and the format their values must have
+#[get("/attributes")]
+async fn attributes(config: CurrentConfig) -> Json<Vec<AttributeInfo>> {
+    let mut attributes: Vec<AttributeInfo> = config
+        .attributes()
+        .map(|(name, ids)| AttributeInfo {
+            name: name.to_string(),
+            irma_ids: (!config.hide_attribute_ids())
+                .then(|| ids.iter().map(|id| id.as_str().to_string()).collect()),
+            requirement: config.requirement(name),
+            format: config.attribute_format(name).cloned(),
+        })
+        .collect();
+    attributes.sort_by(|a, b| a.name.cmp(&b.name));
+    Json(attributes)
+}
+
+// Guard providing the configuration as current at the start of the request,
+// which remains unaffected by reloads during the request
+pub struct CurrentConfig(Arc<config::Config>);
+
+impl Deref for CurrentConfig {
+    type Target = config::Config;
+
+    fn deref(&self) -> &config::Config {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CurrentConfig {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        match request.rocket().state::<config::SharedConfig>() {
+            Some(config) => request::Outcome::Success(CurrentConfig(config.get())),
+            None => request::Outcome::Error((Status::InternalServerError, ())),
+        }
+    }
+}
+
+// Reload the configuration whenever the process receives SIGHUP
+#[cfg(unix)]
+async fn reload_config_on_sighup(config: config::SharedConfig) {
+    use rocket::tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            log::error!("Could not listen for SIGHUP, reloading disabled: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        match config.reload() {
+            Ok(()) => log::info!("Configuration reloaded"),
+            Err(e @ config::Error::RestartRequired(_)) => {
+                log::error!("{}, keeping current configuration", e)
+            }
+            // Drop error value, as it could contain secrets
+            Err(_) => log::error!("Could not reload configuration, keeping current configuration"),
+        }
+    }
+}
+
+/// Build the rocket instance serving this plugin with the given
+/// configuration. When the path of the configuration file is given, the
+/// configuration is reloaded from it on SIGHUP.
+pub fn create_rocket(config: config::Config, config_path: Option<PathBuf>) -> Rocket<Build> {
+    #[allow(unused_mut)]
+    let mut base = rocket::build().mount(
+        "/",
+        panics::with_request_id(routes![
+            start_authentication,
+            restart_authentication,
+            session_status::session_status,
+            jwks::jwks,
+            decorated_continue,
+            session_complete,
+            auth_ui,
+            auth_ui_without_continuation,
+            fetch_result,
+            retained_result,
+            irma_ui_params,
+            verify_ui_params,
+            attributes,
+            admin::set_maintenance,
+            admin::health,
+            admin::ready,
+            admin::delivery_stats,
+            admin::usage_stats,
+            admin::timing_stats
+        ]),
+    );
+    base = base.register("/", catchers![catchers::default]);
+    #[cfg(feature = "sentry")]
+    if let Some(dsn) = config.sentry_dsn() {
+        base = base
+            .attach(verder_helpen_sentry::SentryFairing::new(dsn, "auth-irma"))
+            .attach(AdHoc::on_liftoff("Sentry scrubber", |_| {
+                Box::pin(async {
+                    redact::install_sentry_scrubber();
+                })
+            }));
+    }
+    let results = ResultStore(store::TtlStore::with_max_entries(
+        config.result_reference_ttl(),
+        config.result_reference_max_results(),
+    ));
+    let retained = RetainedResultStore(match config.result_retention() {
+        Some(retention) => {
+            store::TtlStore::with_max_entries(retention.period(), retention.max_results())
+        }
+        None => store::TtlStore::new(Duration::ZERO),
+    });
+    let params = ParamsStore(store::TtlStore::new(IRMA_UI_PARAMS_TTL));
+    let pending = PendingSessions(store::TtlStore::new(
+        config.max_session_age().unwrap_or(Duration::ZERO),
+    ));
+    if config.result_status_spelling() == config::StatusSpelling::Legacy {
+        base = base.attach(AdHoc::on_liftoff("Status spelling deprecation", |_| {
+            Box::pin(async {
+                log::warn!(
+                    "Results use the deprecated \"succes\" status spelling, set \
+                     result_status_spelling to standard once all cores accept \"success\""
+                );
+            })
+        }));
+    }
+    if config.test_mode_enabled() {
+        base = base
+            .mount(
+                "/",
+                panics::with_request_id(routes![test_mode::confirm_page, test_mode::confirm]),
+            )
+            .attach(AdHoc::on_liftoff("Test mode warning", |_| {
+                Box::pin(async {
+                    log::warn!("TEST MODE ENABLED: results contain canned attributes");
+                })
+            }));
+    }
+    let issued = IssuedResults(store::TtlStore::new(config.result_validity()));
+    if config.track_result_ids() {
+        base = base.mount("/", panics::with_request_id(routes![consume_result]));
+    }
+    let maintenance = admin::Maintenance::new(config.maintenance_mode());
+    let audit_log = audit::AuditLog::new(config.audit_log());
+    #[cfg(unix)]
+    {
+        let maintenance = maintenance.clone();
+        base = base.attach(AdHoc::on_liftoff("Maintenance toggle", |_| {
+            Box::pin(async move {
+                rocket::tokio::spawn(admin::toggle_maintenance_on_sigusr1(maintenance));
+            })
+        }));
+    }
+    if !config.remote_key_urls().is_empty() {
+        // Results can't be encrypted without the key sets, so don't start
+        // without them
+        base = base
+            .attach(AdHoc::try_on_ignite("Key sets", |rocket| {
+                Box::pin(async move {
+                    let config = match rocket.state::<config::SharedConfig>() {
+                        Some(config) => config.get(),
+                        None => return Ok(rocket),
+                    };
+                    match remote_keys::refresh_all(&config).await {
+                        Ok(()) => Ok(rocket),
+                        Err(e) => {
+                            log::error!("{}", e);
+                            Err(rocket)
+                        }
+                    }
+                })
+            }))
+            .attach(AdHoc::on_liftoff("Key set refresh", |rocket| {
+                Box::pin(async move {
+                    if let Some(config) = rocket.state::<config::SharedConfig>().cloned() {
+                        rocket::tokio::spawn(remote_keys::refresh_periodically(
+                            config,
+                            rocket.shutdown(),
+                        ));
+                    }
+                })
+            }));
+    }
+    let irma_probe = probe::IrmaProbe::new();
+    if config.irma_probe_interval().is_some() {
+        let irma_probe = irma_probe.clone();
+        base = base.attach(AdHoc::on_liftoff("Irma server probe", |rocket| {
+            Box::pin(async move {
+                if let Some(config) = rocket.state::<config::SharedConfig>().cloned() {
+                    rocket::tokio::spawn(irma_probe.run(config, rocket.shutdown()));
+                }
+            })
+        }));
+    }
+    // Always running, as the self-test can be enabled by a reload
+    let key_probe = probe::KeyProbe::new();
+    {
+        let key_probe = key_probe.clone();
+        base = base.attach(AdHoc::on_liftoff("Key self-test", |rocket| {
+            Box::pin(async move {
+                if let Some(config) = rocket.state::<config::SharedConfig>().cloned() {
+                    rocket::tokio::spawn(key_probe.run(config, rocket.shutdown()));
+                }
+            })
+        }));
+    }
+    if config_path.is_some() {
+        base = base.attach(AdHoc::on_liftoff("Configuration reload", |rocket| {
+            Box::pin(async move {
+                #[cfg(unix)]
+                if let Some(config) = rocket.state::<config::SharedConfig>().cloned() {
+                    rocket::tokio::spawn(reload_config_on_sighup(config));
+                }
+            })
+        }));
+    }
+    base.manage(config::SharedConfig::new(config, config_path))
+        .manage(maintenance)
+        .manage(irma_probe)
+        .manage(key_probe)
+        .manage(audit_log)
+        .manage(test_mode::TestSessions::new())
+        .manage(results)
+        .manage(retained)
+        .manage(issued)
+        .manage(params)
+        .manage(pending)
+        .manage(coalesce::StartFlights::new())
+        .manage(quotas::Quotas::new())
+        .manage(RestartableSessions(store::TtlStore::new(
+            RESTARTABLE_SESSION_TTL,
+        )))
+}
+
+#[cfg(test)]
+mod tests {
+    use askama::Template;
+
+    use super::AuthTemplate;
+
+    const QR: &str = r#"{"u":"https://irma.example.com/irma/session/token","irmaqr":"disclosing"}"#;
+
+    // Session pointer as read back by the script of the page
+    fn embedded_session_ptr(page: &str) -> serde_json::Value {
+        let start = page
+            .find(r#"id="session-ptr">"#)
+            .expect("Missing session pointer")
+            + r#"id="session-ptr">"#.len();
+        let end = start
+            + page[start..]
+                .find("</script>")
+                .expect("Unterminated script");
+        serde_json::from_str(&page[start..end]).expect("Invalid session pointer")
+    }
+
+    #[test]
+    fn auth_template_escapes_continuation() {
+        let continuation = r#"https://core.example.com/"><script>alert(1)</script>"#;
+
+        let page = AuthTemplate::new(QR, continuation)
+            .unwrap()
+            .render()
+            .unwrap();
+
+        assert!(!page.contains("<script>alert(1)"));
+        assert!(page.contains("&quot;&gt;&lt;script&gt;alert(1)"));
+    }
+
+    #[test]
+    fn auth_template_keeps_session_pointer_in_its_script() {
+        let qr = r#"{"u":"https://irma.example.com/</script><script>alert(1)</script>&amp;","irmaqr":"disclosing"}"#;
+
+        let page = AuthTemplate::new(qr, "https://core.example.com/continue")
+            .unwrap()
+            .render()
+            .unwrap();
+
+        // Only the script elements of the template itself are closed
+        assert_eq!(page.matches("</script>").count(), 3);
+        assert!(!page.contains("<script>alert(1)"));
+        assert_eq!(
+            embedded_session_ptr(&page),
+            serde_json::from_str::<serde_json::Value>(qr).unwrap()
+        );
+    }
+
+    #[test]
+    fn auth_template_refuses_hostile_input() {
+        let continuation = "https://core.example.com/continue";
+        assert!(AuthTemplate::new(QR, "javascript:alert(1)").is_err());
+        assert!(AuthTemplate::new(QR, " https://core.example.com/continue").is_err());
+        assert!(AuthTemplate::new("</script><script>alert(1)", continuation).is_err());
+        assert!(AuthTemplate::new(r#""</script>""#, continuation).is_err());
+        assert!(AuthTemplate::new("[]", continuation).is_err());
+    }
+}
@@ -0,0 +1,64 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::irma::ConDisCon;
+
+/// Bounded cache of disclosure requests mapped from lists of requested
+/// attributes, evicting the least recently used mapping when full. Mappings
+/// only depend on the configuration, so the cache lives with the
+/// configuration and is discarded when it is reloaded.
+#[derive(Debug)]
+pub struct MappingCache {
+    max_entries: usize,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    // Incremented on each access, marking entries with their last use
+    clock: u64,
+    // Keyed by the attributes in request order, as the order of the mapped
+    // disclosure request follows it
+    entries: HashMap<Vec<String>, (u64, ConDisCon)>,
+}
+
+impl MappingCache {
+    pub fn new(max_entries: usize) -> Self {
+        MappingCache {
+            max_entries,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    pub fn get(&self, attributes: &[String]) -> Option<ConDisCon> {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+        state
+            .entries
+            .get_mut(attributes)
+            .map(|(last_used, mapped)| {
+                *last_used = clock;
+                mapped.clone()
+            })
+    }
+
+    pub fn insert(&self, attributes: &[String], mapped: ConDisCon) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+        if state.entries.len() >= self.max_entries && !state.entries.contains_key(attributes) {
+            let least_recent = state
+                .entries
+                .iter()
+                .min_by_key(|(_, (last_used, _))| *last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(key) = least_recent {
+                state.entries.remove(&key);
+            }
+        }
+        state.entries.insert(attributes.to_vec(), (clock, mapped));
+    }
+}
@@ -0,0 +1,236 @@
+//! Minimal in-process emulation of the irma server, for local development
+//! and end-to-end testing without an actual irma server.
+//!
+//! Sessions are created by `POST /session`, walk through the `INITIALIZED`,
+//! `CONNECTED` and `DONE` states on consecutive status requests, and always
+//! finish with a valid proof disclosing the first option of every requested
+//! discon. Sessions can be cancelled by `DELETE /session/<token>`, after which
+//! they report `CANCELLED` like the irma server does. When the session was
+//! started with a callback url, the callback is triggered shortly after the
+//! session is created.
+
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use rocket::{
+    delete, fairing::AdHoc, get, http::Status, post, routes, serde::json::Json, Build, Rocket,
+    State,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::irma::SessionStatus;
+
+/// Time after which the callback of a session is triggered
+const CALLBACK_DELAY: Duration = Duration::from_secs(2);
+
+/// Value disclosed for attributes without a configured value
+const DEFAULT_VALUE: &str = "mock value";
+
+#[derive(Debug)]
+struct MockSession {
+    request: Value,
+    polls: usize,
+    cancelled: bool,
+}
+
+#[derive(Debug)]
+struct MockIrmaState {
+    base_url: String,
+    values: HashMap<String, String>,
+    sessions: Mutex<HashMap<String, MockSession>>,
+}
+
+#[derive(Serialize)]
+struct AttributeValue {
+    id: String,
+    rawvalue: String,
+    status: &'static str,
+}
+
+#[post("/session", data = "<request>")]
+async fn start_session(state: &State<MockIrmaState>, request: Json<Value>) -> Json<Value> {
+    let token = crate::store::random_id();
+    let request = request.into_inner();
+
+    // Requests with a callback wrap the actual session request
+    let (request, callback_url) = match request.get("request") {
+        Some(inner) => (
+            inner.clone(),
+            request
+                .get("callbackUrl")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+        ),
+        None => (request, None),
+    };
+
+    state.sessions.lock().unwrap().insert(
+        token.clone(),
+        MockSession {
+            request,
+            polls: 0,
+            cancelled: false,
+        },
+    );
+
+    if let Some(callback_url) = callback_url {
+        let token = token.clone();
+        rocket::tokio::spawn(async move {
+            rocket::tokio::time::sleep(CALLBACK_DELAY).await;
+            let result = reqwest::Client::new()
+                .post(&callback_url)
+                .json(&json!({ "token": token }))
+                .send()
+                .await;
+            if let Err(e) = result {
+                log::error!("Mock irma server failed to call back: {}", e);
+            }
+        });
+    }
+
+    Json(json!({
+        "token": token,
+        "sessionPtr": {
+            "u": format!("{}/irma/session/{}", state.base_url, token),
+            "irmaqr": "disclosing",
+        },
+    }))
+}
+
+#[get("/session/<token>/status")]
+async fn session_status(state: &State<MockIrmaState>, token: String) -> Option<Json<String>> {
+    let mut sessions = state.sessions.lock().unwrap();
+    let session = sessions.get_mut(&token)?;
+    let status = match session.polls {
+        _ if session.cancelled => SessionStatus::Cancelled,
+        0 => SessionStatus::Initialized,
+        1 => SessionStatus::Connected,
+        _ => SessionStatus::Done,
+    };
+    session.polls += 1;
+    Some(Json(status.to_string()))
+}
+
+#[delete("/session/<token>")]
+async fn cancel_session(state: &State<MockIrmaState>, token: String) -> Status {
+    match state.sessions.lock().unwrap().get_mut(&token) {
+        Some(session) if !session.cancelled => {
+            session.cancelled = true;
+            Status::NoContent
+        }
+        _ => Status::BadRequest,
+    }
+}
+
+#[get("/session/<token>/result")]
+async fn session_result(state: &State<MockIrmaState>, token: String) -> Option<Json<Value>> {
+    let sessions = state.sessions.lock().unwrap();
+    let session = sessions.get(&token)?;
+    if session.cancelled {
+        return Some(Json(json!({
+            "token": token,
+            "status": SessionStatus::Cancelled,
+            "type": "disclosing",
+        })));
+    }
+
+    let disclosed: Vec<Vec<AttributeValue>> = session.request["disclose"]
+        .as_array()
+        .map(|discons| {
+            discons
+                .iter()
+                .map(|discon| {
+                    discon[0]
+                        .as_array()
+                        .map(|con| {
+                            con.iter()
+                                .filter_map(|attribute| match attribute {
+                                    Value::String(id) => Some((id.as_str(), None)),
+                                    // Attributes requested with a required value
+                                    Value::Object(attribute) => Some((
+                                        attribute.get("type")?.as_str()?,
+                                        attribute.get("value").and_then(Value::as_str),
+                                    )),
+                                    _ => None,
+                                })
+                                .map(|(id, required)| AttributeValue {
+                                    id: id.to_string(),
+                                    rawvalue: required
+                                        .map(str::to_string)
+                                        .or_else(|| state.values.get(id).cloned())
+                                        .unwrap_or_else(|| DEFAULT_VALUE.to_string()),
+                                    status: "PRESENT",
+                                })
+                                .collect()
+                        })
+                        .unwrap_or_default()
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Some(Json(json!({
+        "token": token,
+        "status": SessionStatus::Done,
+        "type": "disclosing",
+        "proofStatus": "VALID",
+        "disclosed": disclosed,
+    })))
+}
+
+/// Build a mock irma server listening on `port`, disclosing the given values
+/// for the irma attribute ids in `values`
+pub fn create_rocket(port: u16, values: HashMap<String, String>) -> Rocket<Build> {
+    let figment = rocket::Config::figment()
+        .merge(("port", port))
+        .merge(("address", "127.0.0.1"));
+    rocket::custom(figment)
+        .mount(
+            "/",
+            routes![
+                start_session,
+                session_status,
+                cancel_session,
+                session_result
+            ],
+        )
+        .manage(MockIrmaState {
+            base_url: format!("http://127.0.0.1:{port}"),
+            values,
+            sessions: Mutex::new(HashMap::new()),
+        })
+}
+
+/// Launch a mock irma server on `port` in the background, returning once it
+/// accepts requests
+pub async fn spawn(port: u16) {
+    let (listening, ready) = rocket::tokio::sync::oneshot::channel();
+    let mock = create_rocket(port, HashMap::new()).attach(AdHoc::on_liftoff(
+        "Mock irma server ready",
+        move |_| {
+            Box::pin(async move {
+                let _ = listening.send(());
+            })
+        },
+    ));
+    rocket::tokio::spawn(async move {
+        if let Err(e) = mock.launch().await {
+            log::error!("Mock irma server failed: {}", e);
+        }
+    });
+    let _ = ready.await;
+}
+
+/// Fairing launching a mock irma server on `port` alongside the plugin
+pub fn fairing(port: u16) -> AdHoc {
+    AdHoc::on_liftoff("Mock irma server", move |_| {
+        Box::pin(async move {
+            log::warn!("Using mock irma server, disclosures are not real");
+            rocket::tokio::spawn(async move {
+                if let Err(e) = create_rocket(port, HashMap::new()).launch().await {
+                    log::error!("Mock irma server failed: {}", e);
+                }
+            });
+        })
+    })
+}
@@ -0,0 +1,90 @@
+//! Accounting for panics in request handlers. Rocket catches these and
+//! answers with a 500 through the catchers, the hook below makes sure they
+//! are logged with a backtrace and the id of the failing request, and
+//! counted.
+
+use std::{
+    backtrace::Backtrace,
+    panic,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Once,
+    },
+};
+
+use rocket::{
+    route::{Handler, Outcome},
+    tokio, Data, Request, Route,
+};
+
+static PANICS: AtomicU64 = AtomicU64::new(0);
+
+static INSTALL: Once = Once::new();
+
+tokio::task_local! {
+    // X-Request-Id of the request being handled by the current task
+    static REQUEST_ID: Option<String>;
+}
+
+/// Install the panic hook, keeping the previously installed hook (such as
+/// the one reporting panics to Sentry) in place. Installing it more than once
+/// has no effect.
+pub fn install_hook() {
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            PANICS.fetch_add(1, Ordering::Relaxed);
+            let request_id = REQUEST_ID.try_with(Clone::clone).ok().flatten();
+            let context = request_id
+                .as_deref()
+                .map(|id| format!(" (request {})", id))
+                .unwrap_or_default();
+            log::error!("{}{}\n{}", info, context, Backtrace::force_capture());
+
+            #[cfg(feature = "sentry")]
+            if let Some(request_id) = request_id {
+                sentry::with_scope(
+                    |scope| scope.set_tag("request_id", request_id),
+                    || previous(info),
+                );
+                return;
+            }
+            previous(info);
+        }));
+    });
+}
+
+/// Number of panics since the process started
+pub fn count() -> u64 {
+    PANICS.load(Ordering::Relaxed)
+}
+
+// Handler making the request id available to the panic hook while the
+// wrapped handler runs
+#[derive(Clone)]
+struct WithRequestId(Box<dyn Handler>);
+
+#[rocket::async_trait]
+impl Handler for WithRequestId {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> Outcome<'r> {
+        let request_id = request
+            .headers()
+            .get_one("X-Request-Id")
+            .map(str::to_string);
+        REQUEST_ID
+            .scope(request_id, self.0.handle(request, data))
+            .await
+    }
+}
+
+/// Wrap the handlers of routes, so panics in them are reported with the id of
+/// the request
+pub fn with_request_id(routes: Vec<Route>) -> Vec<Route> {
+    routes
+        .into_iter()
+        .map(|mut route| {
+            route.handler = Box::new(WithRequestId(route.handler));
+            route
+        })
+        .collect()
+}
@@ -0,0 +1,140 @@
+//! Background probes of the irma server and of the configured keys, giving
+//! continuous signals that /health and /readyz report without calling the
+//! irma server or signing service themselves.
+
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use rocket::{
+    tokio::{task, time::timeout},
+    Shutdown,
+};
+use serde::Serialize;
+
+use crate::config::SharedConfig;
+
+/// Time to wait before checking again whether probing was enabled, while it
+/// is disabled
+const DISABLED_RECHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Interval between self-tests of the configured keys
+const KEY_SELF_TEST_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Outcome of the latest probe
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ProbeStatus {
+    pub up: bool,
+    latency_ms: u64,
+}
+
+/// Latest outcome of the probe, shared between the probing task and the
+/// health endpoints
+#[derive(Clone, Default)]
+pub struct IrmaProbe(Arc<RwLock<Option<ProbeStatus>>>);
+
+impl IrmaProbe {
+    pub fn new() -> Self {
+        IrmaProbe::default()
+    }
+
+    /// Outcome of the latest probe, if any probe ran
+    pub fn latest(&self) -> Option<ProbeStatus> {
+        *self.0.read().unwrap()
+    }
+
+    fn set(&self, status: Option<ProbeStatus>) {
+        let previous = std::mem::replace(&mut *self.0.write().unwrap(), status);
+        match (
+            previous.map(|status| status.up),
+            status.map(|status| status.up),
+        ) {
+            (Some(true) | None, Some(false)) => log::warn!("Irma server probe failed"),
+            (Some(false), Some(true)) => log::info!("Irma server probe succeeded again"),
+            _ => {}
+        }
+    }
+
+    /// Probe the irma server until the server shuts down. The configuration
+    /// is read before every probe, so a reloaded irma server url or interval
+    /// takes effect with the next probe.
+    pub async fn run(self, config: SharedConfig, shutdown: Shutdown) {
+        loop {
+            let current = config.get();
+            let interval = match current.irma_probe_interval() {
+                Some(interval) => {
+                    let started = Instant::now();
+                    let result = current
+                        .irma_server()
+                        .probe(current.irma_request_timeout())
+                        .await;
+                    if let Err(e) = &result {
+                        log::debug!("Irma server probe failed: {}", e);
+                    }
+                    self.set(Some(ProbeStatus {
+                        up: result.is_ok(),
+                        latency_ms: started.elapsed().as_millis() as u64,
+                    }));
+                    interval
+                }
+                None => {
+                    self.set(None);
+                    DISABLED_RECHECK_INTERVAL
+                }
+            };
+            drop(current);
+
+            if timeout(interval, shutdown.clone()).await.is_ok() {
+                break;
+            }
+        }
+    }
+}
+
+/// Latest outcome of the background self-test of the configured keys, when
+/// enabled
+#[derive(Clone, Default)]
+pub struct KeyProbe(Arc<RwLock<Option<bool>>>);
+
+impl KeyProbe {
+    pub fn new() -> Self {
+        KeyProbe::default()
+    }
+
+    /// Whether the latest self-test passed, if it is enabled and ran
+    pub fn latest(&self) -> Option<bool> {
+        *self.0.read().unwrap()
+    }
+
+    /// Self-test the keys of the current configuration periodically, until
+    /// the server shuts down. Signing may block on a remote signing service,
+    /// so the test runs on a blocking thread.
+    pub async fn run(self, config: SharedConfig, shutdown: Shutdown) {
+        loop {
+            let current = config.get();
+            let interval = if current.health_key_self_test() {
+                let passed = match task::spawn_blocking(move || current.self_test()).await {
+                    Ok(Ok(())) => true,
+                    Ok(Err(e)) => {
+                        log::error!("{}", e);
+                        false
+                    }
+                    Err(e) => {
+                        log::error!("Key self-test panicked: {}", e);
+                        false
+                    }
+                };
+                *self.0.write().unwrap() = Some(passed);
+                KEY_SELF_TEST_INTERVAL
+            } else {
+                *self.0.write().unwrap() = None;
+                DISABLED_RECHECK_INTERVAL
+            };
+
+            if timeout(interval, shutdown.clone()).await.is_ok() {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! Per-requestor quotas on started and concurrent sessions, so a single
+//! tenant of a shared deployment cannot starve the others of the irma server.
+//! Requestors are identified by the requestor key id of their start requests.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use rocket::{
+    response::{self, Responder},
+    Request,
+};
+
+use crate::{config::RequestorQuota, irma::SessionToken};
+
+/// Length of the window in which started sessions are counted
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Time after which a session that was not seen to end no longer counts as
+/// in progress
+const SESSION_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// Time after which a requestor at its limit of concurrent sessions may try
+/// again
+const CONCURRENCY_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// Quota exceeded, with the time after which to try again
+#[derive(Debug)]
+pub struct Exceeded(pub Duration);
+
+struct Window {
+    started: Instant,
+    count: u32,
+}
+
+#[derive(Default)]
+struct Usage {
+    windows: HashMap<String, Window>,
+    /// Requestor and start of sessions in progress, by session token
+    sessions: HashMap<String, (String, Instant)>,
+}
+
+#[derive(Default)]
+pub struct Quotas(Mutex<Usage>);
+
+impl Quotas {
+    pub fn new() -> Self {
+        Quotas::default()
+    }
+
+    /// Count a start for the requestor, refusing it when that exceeds its
+    /// quota. Returns the starts left in the current window, when limited.
+    pub fn check(&self, requestor: &str, quota: &RequestorQuota) -> Result<Option<u32>, Exceeded> {
+        let mut usage = self.0.lock().unwrap();
+        usage
+            .sessions
+            .retain(|_, (_, started)| started.elapsed() < SESSION_TTL);
+
+        if let Some(max_concurrent) = quota.max_concurrent_sessions() {
+            let in_progress = usage
+                .sessions
+                .values()
+                .filter(|(session_requestor, _)| session_requestor == requestor)
+                .count();
+            if in_progress >= max_concurrent as usize {
+                return Err(Exceeded(CONCURRENCY_RETRY_AFTER));
+            }
+        }
+
+        let limit = match quota.sessions_per_minute() {
+            Some(limit) => limit,
+            None => return Ok(None),
+        };
+        let window = usage
+            .windows
+            .entry(requestor.to_string())
+            .or_insert_with(|| Window {
+                started: Instant::now(),
+                count: 0,
+            });
+        if window.started.elapsed() >= WINDOW {
+            *window = Window {
+                started: Instant::now(),
+                count: 0,
+            };
+        }
+        if window.count >= limit {
+            return Err(Exceeded(WINDOW.saturating_sub(window.started.elapsed())));
+        }
+        window.count += 1;
+        Ok(Some(limit - window.count))
+    }
+
+    /// Stop counting a start that failed against the current window of the
+    /// requestor
+    pub fn refund(&self, requestor: &str) {
+        if let Some(window) = self.0.lock().unwrap().windows.get_mut(requestor) {
+            window.count = window.count.saturating_sub(1);
+        }
+    }
+
+    /// Count a session as in progress for the requestor it was started for,
+    /// if any. Sessions are counted whether the requestor has a quota or not,
+    /// so a quota added by a configuration reload applies right away.
+    pub fn session_started(&self, requestor: Option<&str>, token: &SessionToken) {
+        let requestor = match requestor {
+            Some(requestor) => requestor,
+            None => return,
+        };
+        let mut usage = self.0.lock().unwrap();
+        usage
+            .sessions
+            .retain(|_, (_, started)| started.elapsed() < SESSION_TTL);
+        usage.sessions.insert(
+            token.expose().to_string(),
+            (requestor.to_string(), Instant::now()),
+        );
+    }
+
+    /// Stop counting a session as in progress
+    pub fn session_ended(&self, token: &SessionToken) {
+        self.0.lock().unwrap().sessions.remove(token.expose());
+    }
+}
+
+/// Response with the starts left for the requestor in the current window
+pub struct WithRemaining<R>(pub R, pub Option<u32>);
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for WithRemaining<R> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'o> {
+        let mut response = self.0.respond_to(request)?;
+        if let Some(remaining) = self.1 {
+            response.set_raw_header("X-RateLimit-Remaining", remaining.to_string());
+        }
+        Ok(response)
+    }
+}
@@ -0,0 +1,236 @@
+//! Masking of sensitive values, such as BSNs, in everything the plugin emits:
+//! log records pass through a logger redacting their messages, and Sentry
+//! events through a `before_send` hook scrubbing theirs. Both use the
+//! redactor of the active configuration.
+
+use std::{borrow::Cow, io::Write, sync::RwLock};
+
+use log::{LevelFilter, Log, Metadata, Record};
+use regex::Regex;
+
+/// Masks sensitive values, such as BSNs, in text before it is logged or
+/// reported.
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    patterns: Vec<Regex>,
+    replacement: String,
+}
+
+impl Redactor {
+    pub fn new(patterns: &[String], replacement: String) -> Result<Redactor, regex::Error> {
+        Ok(Redactor {
+            patterns: patterns
+                .iter()
+                .map(|pattern| Regex::new(pattern))
+                .collect::<Result<_, _>>()?,
+            replacement,
+        })
+    }
+
+    /// Replace everything matching one of the patterns by the replacement
+    pub fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        for pattern in &self.patterns {
+            let redacted = match pattern.replace_all(&text, regex::NoExpand(&self.replacement)) {
+                Cow::Owned(redacted) => redacted,
+                Cow::Borrowed(_) => continue,
+            };
+            text = Cow::Owned(redacted);
+        }
+        text
+    }
+}
+
+// Redactor of the active configuration, replaced on reloads
+static ACTIVE: RwLock<Option<Redactor>> = RwLock::new(None);
+
+/// Make the redactor of a newly activated configuration the one applied to
+/// logs and Sentry events
+pub fn activate(redactor: &Redactor) {
+    *ACTIVE.write().unwrap() = Some(redactor.clone());
+}
+
+// Redact text with the active redactor, if any
+fn redact_active(text: &str) -> Cow<'_, str> {
+    match ACTIVE.read().unwrap().as_ref() {
+        Some(redactor) => redactor.redact(text),
+        None => Cow::Borrowed(text),
+    }
+}
+
+/// Logger passing records on to another logger with their messages redacted,
+/// so no call site has to redact by itself
+pub struct RedactingLogger<L>(pub L);
+
+impl<L: Log> Log for RedactingLogger<L> {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let message = record.args().to_string();
+        let message = redact_active(&message);
+        self.0.log(
+            &Record::builder()
+                .args(format_args!("{}", message))
+                .metadata(record.metadata().clone())
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .build(),
+        );
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
+// Logger writing records to standard error
+struct StderrLogger(LevelFilter);
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.0
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        let _ = writeln!(
+            std::io::stderr().lock(),
+            "[{}] {}: {}",
+            record.level(),
+            record.target(),
+            record.args()
+        );
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+/// Install the redacting logger, writing records up to the level in the
+/// `RUST_LOG` environment variable (`info` by default) to standard error.
+/// Must run before the rocket is built, or rocket installs its own logger.
+pub fn init_logger() {
+    let level = std::env::var("RUST_LOG")
+        .ok()
+        .and_then(|level| level.parse().ok())
+        .unwrap_or(LevelFilter::Info);
+    if log::set_boxed_logger(Box::new(RedactingLogger(StderrLogger(level)))).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// Sentry `before_send` hook, redacting the messages, exception values and
+/// breadcrumbs of an event
+#[cfg(feature = "sentry")]
+pub fn scrub_event(
+    mut event: sentry::protocol::Event<'static>,
+) -> Option<sentry::protocol::Event<'static>> {
+    let scrub = |text: &mut String| {
+        let redacted = match redact_active(text) {
+            Cow::Owned(redacted) => redacted,
+            Cow::Borrowed(_) => return,
+        };
+        *text = redacted;
+    };
+    if let Some(message) = &mut event.message {
+        scrub(message);
+    }
+    if let Some(logentry) = &mut event.logentry {
+        scrub(&mut logentry.message);
+    }
+    for value in event
+        .exception
+        .values
+        .iter_mut()
+        .filter_map(|exception| exception.value.as_mut())
+    {
+        scrub(value);
+    }
+    for message in event
+        .breadcrumbs
+        .values
+        .iter_mut()
+        .filter_map(|breadcrumb| breadcrumb.message.as_mut())
+    {
+        scrub(message);
+    }
+    Some(event)
+}
+
+/// Add [`scrub_event`] as `before_send` hook to the Sentry client, once the
+/// client is initialized
+#[cfg(feature = "sentry")]
+pub fn install_sentry_scrubber() {
+    let hub = sentry::Hub::main();
+    if let Some(client) = hub.client() {
+        let mut options = client.options().clone();
+        options.before_send = Some(std::sync::Arc::new(scrub_event));
+        hub.bind_client(Some(std::sync::Arc::new(sentry::Client::from(options))));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use log::Level;
+
+    use super::*;
+
+    struct Capture(Mutex<Vec<String>>);
+
+    impl Log for Capture {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record<'_>) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn logged_messages_and_events_are_redacted() {
+        let redactor = Redactor::new(&["[0-9]{9}".to_string()], "[BSN]".to_string()).unwrap();
+        activate(&redactor);
+        let logger = RedactingLogger(Capture(Mutex::new(vec![])));
+
+        logger.log(
+            &Record::builder()
+                .args(format_args!("Invalid result for {}", "123456789"))
+                .level(Level::Error)
+                .build(),
+        );
+
+        let Capture(logged) = &logger.0;
+        assert_eq!(*logged.lock().unwrap(), ["Invalid result for [BSN]"]);
+
+        #[cfg(feature = "sentry")]
+        {
+            use sentry::protocol::{Event, Exception};
+
+            let mut event = Event {
+                message: Some("Panic for 123456789".to_string()),
+                ..Event::default()
+            };
+            event.exception.values.push(Exception {
+                value: Some("Unexpected 123456789".to_string()),
+                ..Exception::default()
+            });
+            let event = scrub_event(event).unwrap();
+            assert_eq!(event.message.as_deref(), Some("Panic for [BSN]"));
+            assert_eq!(
+                event.exception.values[0].value.as_deref(),
+                Some("Unexpected [BSN]")
+            );
+        }
+    }
+}
@@ -0,0 +1,459 @@
+//! Encryption keys of relying parties published at their JWKS urls. The key
+//! sets are fetched when the plugin starts and refreshed periodically, or
+//! sooner when a configured key id is missing from a set. The last good copy
+//! of every set is kept, in memory and optionally on disk, and used whenever
+//! a refresh fails.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use josekit::{
+    jwe::{JweEncrypter, JweHeader, ECDH_ES, RSA_OAEP, RSA_OAEP_256},
+    jwk::{Jwk, JwkSet},
+    jwt::{self, JwtPayload},
+    JoseError,
+};
+use rocket::{tokio::time::timeout, Shutdown};
+use sha2::{Digest, Sha256};
+
+use crate::config::{self, Config, SharedConfig};
+
+/// Minimum time between fetches of a key set triggered by missing key ids
+const MISS_REFETCH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Time a single fetch of a key set may take
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Key published in a JWKS, identified by its url and key id
+#[derive(Debug, Clone)]
+pub struct RemoteKey {
+    pub url: String,
+    pub kid: String,
+}
+
+#[derive(Default)]
+struct CachedSet {
+    encrypters: HashMap<String, Arc<dyn JweEncrypter>>,
+    last_attempt: Option<Instant>,
+}
+
+static CACHE: RwLock<Option<HashMap<String, CachedSet>>> = RwLock::new(None);
+
+// Encrypter for a public key from a key set, with the key management
+// algorithm the key declares, or the default for its key type
+fn encrypter_from_jwk(jwk: &Jwk) -> Result<Arc<dyn JweEncrypter>, Error> {
+    let encrypter: Arc<dyn JweEncrypter> = match (jwk.key_type(), jwk.algorithm()) {
+        ("RSA", None | Some("RSA-OAEP")) => Arc::new(RSA_OAEP.encrypter_from_jwk(jwk)?),
+        ("RSA", Some("RSA-OAEP-256")) => Arc::new(RSA_OAEP_256.encrypter_from_jwk(jwk)?),
+        ("EC" | "OKP", None | Some("ECDH-ES")) => Arc::new(ECDH_ES.encrypter_from_jwk(jwk)?),
+        (key_type, algorithm) => {
+            return Err(Error::UnsupportedKey(
+                key_type.to_string(),
+                algorithm.map(str::to_string),
+            ))
+        }
+    };
+
+    // Catch keys that cannot actually encrypt before they are needed for a
+    // result
+    let mut payload = JwtPayload::new();
+    payload.set_claim("self_test", Some(true.into()))?;
+    jwt::encode_with_encrypter(&payload, &JweHeader::new(), encrypter.as_ref())?;
+    Ok(encrypter)
+}
+
+// Encryption keys in a key set by key id, skipping signing keys and keys that
+// cannot be used
+fn parse_key_set(url: &str, body: &[u8]) -> Result<HashMap<String, Arc<dyn JweEncrypter>>, Error> {
+    let key_set = JwkSet::from_bytes(body)?;
+    Ok(key_set
+        .keys()
+        .into_iter()
+        .filter(|jwk| matches!(jwk.key_use(), None | Some("enc")))
+        .filter_map(|jwk| {
+            let kid = jwk.key_id()?;
+            match encrypter_from_jwk(jwk) {
+                Ok(encrypter) => Some((kid.to_string(), encrypter)),
+                Err(e) => {
+                    log::warn!("Skipping key {} from {}: {}", kid, url, e);
+                    None
+                }
+            }
+        })
+        .collect())
+}
+
+// File caching the key set of a url, named by a digest of the url that stays
+// the same across releases of the plugin and its toolchain
+fn cache_file(cache_dir: &Path, url: &str) -> PathBuf {
+    cache_dir.join(format!("{:x}.json", Sha256::digest(url)))
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Reqwest(reqwest::Error),
+    Jose(JoseError),
+    Io(std::io::Error),
+    UnsupportedKey(String, Option<String>),
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Error {
+        Error::Reqwest(e)
+    }
+}
+
+impl From<JoseError> for Error {
+    fn from(e: JoseError) -> Error {
+        Error::Jose(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Reqwest(e) => e.fmt(f),
+            Error::Jose(e) => e.fmt(f),
+            Error::Io(e) => e.fmt(f),
+            Error::UnsupportedKey(key_type, algorithm) => f.write_fmt(format_args!(
+                "Unsupported key type {} with algorithm {}",
+                key_type,
+                algorithm.as_deref().unwrap_or("none")
+            )),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Reqwest(e) => Some(e),
+            Error::Jose(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::UnsupportedKey(_, _) => None,
+        }
+    }
+}
+
+async fn fetch(url: &str) -> Result<Vec<u8>, Error> {
+    Ok(reqwest::Client::new()
+        .get(url)
+        .timeout(FETCH_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?
+        .to_vec())
+}
+
+fn store(url: &str, encrypters: HashMap<String, Arc<dyn JweEncrypter>>) {
+    let mut cache = CACHE.write().unwrap();
+    let cached = cache
+        .get_or_insert_with(HashMap::new)
+        .entry(url.to_string())
+        .or_default();
+    cached.encrypters = encrypters;
+}
+
+/// Fetch the key set at a url. When the fetch fails and no copy is kept in
+/// memory yet, the copy cached on disk is used instead. Fails when no new
+/// copy could be obtained, in which case any copy in memory stays in use.
+pub async fn refresh(url: &str, cache_dir: Option<&Path>) -> Result<(), Error> {
+    CACHE
+        .write()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .entry(url.to_string())
+        .or_default()
+        .last_attempt = Some(Instant::now());
+
+    let fetched = fetch(url).await;
+    let body = match (fetched, cache_dir) {
+        (Ok(body), cache_dir) => {
+            if let Some(cache_dir) = cache_dir {
+                if let Err(e) = std::fs::write(cache_file(cache_dir, url), &body) {
+                    log::warn!("Could not cache key set of {}: {}", url, e);
+                }
+            }
+            body
+        }
+        (Err(e), Some(cache_dir)) if !is_cached(url) => {
+            log::warn!(
+                "Could not fetch key set of {}, using cached copy: {}",
+                url,
+                e
+            );
+            std::fs::read(cache_file(cache_dir, url))?
+        }
+        (Err(e), _) => return Err(e),
+    };
+    store(url, parse_key_set(url, &body)?);
+    Ok(())
+}
+
+// Whether a copy of the key set at the url is kept in memory
+fn is_cached(url: &str) -> bool {
+    CACHE
+        .read()
+        .unwrap()
+        .as_ref()
+        .and_then(|cache| cache.get(url))
+        .is_some_and(|cached| !cached.encrypters.is_empty())
+}
+
+/// Encrypter for a remote key, from the latest copy of its key set. A missing
+/// key triggers a refresh of the set, after which later results can use it.
+pub fn encrypter(key: &RemoteKey, cache_dir: Option<&Path>) -> Option<Arc<dyn JweEncrypter>> {
+    let cache = CACHE.read().unwrap();
+    let cached = cache.as_ref().and_then(|cache| cache.get(&key.url));
+    if let Some(encrypter) = cached.and_then(|cached| cached.encrypters.get(&key.kid)) {
+        return Some(encrypter.clone());
+    }
+
+    let recently_attempted = cached
+        .and_then(|cached| cached.last_attempt)
+        .is_some_and(|attempt| attempt.elapsed() < MISS_REFETCH_INTERVAL);
+    drop(cache);
+    if !recently_attempted {
+        if let Ok(runtime) = rocket::tokio::runtime::Handle::try_current() {
+            let url = key.url.clone();
+            let cache_dir = cache_dir.map(Path::to_path_buf);
+            runtime.spawn(async move {
+                if let Err(e) = refresh(&url, cache_dir.as_deref()).await {
+                    log::warn!("Could not refresh key set of {}: {}", url, e);
+                }
+            });
+        }
+    }
+    None
+}
+
+/// Fetch the key sets of all remote keys in the configuration
+pub async fn refresh_all(config: &Config) -> Result<(), config::Error> {
+    for url in config.remote_key_urls() {
+        refresh(url, config.jwks_cache_dir())
+            .await
+            .map_err(|e| config::Error::RemoteKey(url.to_string(), e))?;
+    }
+    Ok(())
+}
+
+/// Refresh the key sets of the current configuration periodically, until the
+/// server shuts down
+pub async fn refresh_periodically(config: SharedConfig, shutdown: Shutdown) {
+    loop {
+        let interval = config.get().jwks_refresh_interval();
+        if timeout(interval, shutdown.clone()).await.is_ok() {
+            break;
+        }
+
+        let current = config.get();
+        for url in current.remote_key_urls() {
+            if let Err(e) = refresh(url, current.jwks_cache_dir()).await {
+                log::warn!("Could not refresh key set of {}: {}", url, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, sync::Mutex};
+
+    use josekit::jwk::alg::rsa::RsaKeyPair;
+    use rocket::{fairing::AdHoc, get, http::Status, routes, tokio, State};
+    use serde_json::json;
+
+    use super::*;
+
+    // Key set served by the mock JWKS server, which fails while it is unset
+    #[derive(Clone, Default)]
+    struct ServedSet(Arc<Mutex<Option<String>>>);
+
+    impl ServedSet {
+        fn serve(&self, keys: &[(&str, &RsaKeyPair)]) {
+            let keys: Vec<_> = keys
+                .iter()
+                .map(|(kid, key_pair)| {
+                    let mut jwk = key_pair.to_jwk_public_key();
+                    jwk.set_key_id(*kid);
+                    jwk.set_key_use("enc");
+                    serde_json::Value::Object(jwk.into())
+                })
+                .collect();
+            *self.0.lock().unwrap() = Some(json!({ "keys": keys }).to_string());
+        }
+
+        fn fail(&self) {
+            *self.0.lock().unwrap() = None;
+        }
+    }
+
+    #[get("/jwks.json")]
+    fn jwks(served: &State<ServedSet>) -> Result<String, Status> {
+        served
+            .0
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or(Status::ServiceUnavailable)
+    }
+
+    // Launch a mock JWKS server, returning the url of its key set
+    async fn jwks_server(served: ServedSet) -> String {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+            .port();
+        let figment = rocket::Config::figment()
+            .merge(("port", port))
+            .merge(("address", "127.0.0.1"));
+        let (listening, ready) = tokio::sync::oneshot::channel();
+        let rocket = rocket::custom(figment)
+            .manage(served)
+            .mount("/", routes![jwks])
+            .attach(AdHoc::on_liftoff("JWKS server ready", move |_| {
+                Box::pin(async move {
+                    let _ = listening.send(());
+                })
+            }));
+        tokio::spawn(rocket.launch());
+        ready.await.unwrap();
+        format!("http://127.0.0.1:{port}/jwks.json")
+    }
+
+    fn key_pair() -> RsaKeyPair {
+        RSA_OAEP.generate_key_pair(2048).unwrap()
+    }
+
+    fn key(url: &str, kid: &str) -> RemoteKey {
+        RemoteKey {
+            url: url.to_string(),
+            kid: kid.to_string(),
+        }
+    }
+
+    // Whether a token encrypted with the encrypter decrypts with the key pair
+    fn encrypts_to(encrypter: &dyn JweEncrypter, key_pair: &RsaKeyPair) -> bool {
+        let token =
+            jwt::encode_with_encrypter(&JwtPayload::new(), &JweHeader::new(), encrypter).unwrap();
+        let decrypter = RSA_OAEP
+            .decrypter_from_der(key_pair.to_der_private_key())
+            .unwrap();
+        jwt::decode_with_decrypter(&token, &decrypter).is_ok()
+    }
+
+    // Forget the copy of a key set kept in memory, as after a restart
+    fn forget(url: &str) {
+        CACHE.write().unwrap().as_mut().unwrap().remove(url);
+    }
+
+    #[rocket::async_test]
+    async fn key_is_selected_by_kid() {
+        let (first, second) = (key_pair(), key_pair());
+        let served = ServedSet::default();
+        served.serve(&[("first", &first), ("second", &second)]);
+        let url = jwks_server(served).await;
+
+        refresh(&url, None).await.unwrap();
+
+        let selected = encrypter(&key(&url, "second"), None).unwrap();
+        assert!(encrypts_to(selected.as_ref(), &second));
+        assert!(!encrypts_to(selected.as_ref(), &first));
+        assert!(encrypter(&key(&url, "third"), None).is_none());
+    }
+
+    #[rocket::async_test]
+    async fn refresh_replaces_key_set() {
+        let (old, new) = (key_pair(), key_pair());
+        let served = ServedSet::default();
+        served.serve(&[("old", &old)]);
+        let url = jwks_server(served.clone()).await;
+        refresh(&url, None).await.unwrap();
+
+        served.serve(&[("new", &new)]);
+        refresh(&url, None).await.unwrap();
+
+        assert!(encrypter(&key(&url, "old"), None).is_none());
+        let encrypter = encrypter(&key(&url, "new"), None).unwrap();
+        assert!(encrypts_to(encrypter.as_ref(), &new));
+    }
+
+    #[rocket::async_test]
+    async fn missing_kid_triggers_fetch() {
+        let key_pair = key_pair();
+        let served = ServedSet::default();
+        served.serve(&[("rp", &key_pair)]);
+        let url = jwks_server(served).await;
+
+        // Never fetched before, so the miss fetches the set in the background
+        assert!(encrypter(&key(&url, "rp"), None).is_none());
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let encrypter = loop {
+            if let Some(encrypter) = encrypter(&key(&url, "rp"), None) {
+                break encrypter;
+            }
+            assert!(Instant::now() < deadline, "Key set was not fetched");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        };
+        assert!(encrypts_to(encrypter.as_ref(), &key_pair));
+    }
+
+    #[rocket::async_test]
+    async fn failed_refresh_keeps_copy_in_memory() {
+        let key_pair = key_pair();
+        let served = ServedSet::default();
+        served.serve(&[("rp", &key_pair)]);
+        let url = jwks_server(served.clone()).await;
+        refresh(&url, None).await.unwrap();
+
+        served.fail();
+
+        assert!(refresh(&url, None).await.is_err());
+        let encrypter = encrypter(&key(&url, "rp"), None).unwrap();
+        assert!(encrypts_to(encrypter.as_ref(), &key_pair));
+    }
+
+    #[rocket::async_test]
+    async fn failed_fetch_falls_back_to_copy_on_disk() {
+        let key_pair = key_pair();
+        let served = ServedSet::default();
+        served.serve(&[("rp", &key_pair)]);
+        let url = jwks_server(served.clone()).await;
+        let cache_dir =
+            std::env::temp_dir().join(format!("auth-irma-jwks-{:x}", Sha256::digest(&url)));
+        std::fs::create_dir_all(&cache_dir).unwrap();
+        refresh(&url, Some(&cache_dir)).await.unwrap();
+
+        served.fail();
+        forget(&url);
+
+        refresh(&url, Some(&cache_dir)).await.unwrap();
+        let encrypter = encrypter(&key(&url, "rp"), Some(&cache_dir)).unwrap();
+        assert!(encrypts_to(encrypter.as_ref(), &key_pair));
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn failed_fetch_without_copy_fails() {
+        let served = ServedSet::default();
+        let url = jwks_server(served).await;
+        let cache_dir = std::env::temp_dir();
+
+        assert!(refresh(&url, None).await.is_err());
+        // A cache directory without a copy of this set does not help either
+        assert!(refresh(&url, Some(&cache_dir)).await.is_err());
+        assert!(encrypter(&key(&url, "rp"), None).is_none());
+    }
+}
@@ -0,0 +1,262 @@
+//! Signing delegated to an external signing service, such as a proxy in front
+//! of a KMS, so the private signing key does not have to be in the
+//! configuration file.
+//!
+//! The service receives `{"alg": ..., "kid": ..., "message": ...}` with the
+//! message base64url encoded, and answers with `{"signature": ...}`, also
+//! base64url encoded. Every signature is verified against the configured
+//! public key before it is used.
+
+use std::{sync::Arc, time::Duration};
+
+use base64::URL_SAFE_NO_PAD;
+use josekit::{
+    jwk::Jwk,
+    jws::{JwsAlgorithm, JwsSigner, JwsVerifier},
+    JoseError,
+};
+use rocket::tokio::{
+    self,
+    runtime::{Handle, RuntimeFlavor},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Time after which clients may retry requests that failed because the
+/// signing service was unavailable
+pub const RETRY_AFTER: Duration = Duration::from_secs(5);
+
+fn default_timeout() -> u64 {
+    5
+}
+
+fn default_attempts() -> u32 {
+    3
+}
+
+/// Private parameters of RSA, EC and OKP keys, and the secret of symmetric
+/// keys, none of which may end up in the published public key
+const PRIVATE_PARAMETERS: [&str; 8] = ["d", "p", "q", "dp", "dq", "qi", "oth", "k"];
+
+#[derive(Debug, Deserialize)]
+pub struct RemoteSignerConfig {
+    pub url: String,
+    api_key: Option<String>,
+    algorithm: String,
+    key_id: Option<String>,
+    /// Public key as JWK, to verify signatures and to publish
+    public_key: Map<String, Value>,
+    /// Seconds a single signing request may take
+    #[serde(default = "default_timeout")]
+    timeout: u64,
+    #[serde(default = "default_attempts")]
+    attempts: u32,
+}
+
+impl RemoteSignerConfig {
+    /// The configured public key, refusing keys with private parameters as
+    /// the key is published as is
+    pub fn public_key(&self) -> Result<Jwk, JoseError> {
+        if let Some(parameter) = PRIVATE_PARAMETERS
+            .iter()
+            .find(|parameter| self.public_key.contains_key(**parameter))
+        {
+            return Err(JoseError::InvalidKeyFormat(anyhow::anyhow!(
+                "The public_key of the remote signer contains private parameter {}",
+                parameter
+            )));
+        }
+        Jwk::from_map(self.public_key.clone())
+    }
+}
+
+/// The signing service could not be reached, or failed, in every attempt
+#[derive(Debug)]
+struct Unavailable(String);
+
+impl std::fmt::Display for Unavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Signing service unavailable: {}", self.0)
+    }
+}
+
+impl std::error::Error for Unavailable {}
+
+/// Whether signing failed because the signing service was unavailable, as
+/// opposed to a misconfiguration or invalid signature
+pub fn is_unavailable(e: &JoseError) -> bool {
+    match e {
+        JoseError::InvalidSignature(e) => e.is::<Unavailable>(),
+        _ => false,
+    }
+}
+
+#[derive(Serialize)]
+struct SignRequest<'a> {
+    alg: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kid: Option<&'a str>,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct SignResponse {
+    signature: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct RemoteSigner {
+    client: reqwest::Client,
+    url: String,
+    api_key: Option<String>,
+    key_id: Option<String>,
+    verifier: Arc<dyn JwsVerifier>,
+    signature_len: usize,
+    timeout: Duration,
+    attempts: u32,
+}
+
+impl TryFrom<RemoteSignerConfig> for RemoteSigner {
+    type Error = JoseError;
+
+    fn try_from(config: RemoteSignerConfig) -> Result<RemoteSigner, JoseError> {
+        let jwk = config.public_key()?;
+        let verifier: Arc<dyn JwsVerifier> =
+            crate::jwks::verifier_from_jwk(&config.algorithm, &jwk)?.into();
+        let signature_len = match config.algorithm.as_str() {
+            "ES256" | "EdDSA" => 64,
+            "ES384" => 96,
+            "ES512" => 132,
+            // RSA signatures are as long as the modulus
+            _ => jwk
+                .parameter("n")
+                .and_then(Value::as_str)
+                .and_then(|n| base64::decode_config(n, URL_SAFE_NO_PAD).ok())
+                .map_or(0, |n| n.len()),
+        };
+
+        Ok(RemoteSigner {
+            client: reqwest::Client::new(),
+            url: config.url,
+            api_key: config.api_key,
+            key_id: config.key_id.or_else(|| jwk.key_id().map(str::to_string)),
+            verifier,
+            signature_len,
+            timeout: Duration::from_secs(config.timeout),
+            attempts: config.attempts.max(1),
+        })
+    }
+}
+
+impl RemoteSigner {
+    // Single attempt to have the message signed. Returns whether a failure
+    // is worth retrying.
+    async fn attempt(
+        &self,
+        client: &reqwest::Client,
+        message: &[u8],
+    ) -> Result<Vec<u8>, (bool, String)> {
+        let mut request = client
+            .post(&self.url)
+            .timeout(self.timeout)
+            .json(&SignRequest {
+                alg: self.algorithm().name(),
+                kid: self.key_id.as_deref(),
+                message: base64::encode_config(message, URL_SAFE_NO_PAD),
+            });
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        let response = request.send().await.map_err(|e| (true, e.to_string()))?;
+        let status = response.status();
+        if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            return Err((true, format!("status {}", status)));
+        }
+        let response: SignResponse = response
+            .error_for_status()
+            .map_err(|e| (false, e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| (false, e.to_string()))?;
+        base64::decode_config(response.signature, URL_SAFE_NO_PAD)
+            .map_err(|e| (false, e.to_string()))
+    }
+
+    async fn sign_remotely(
+        &self,
+        client: &reqwest::Client,
+        message: &[u8],
+    ) -> Result<Vec<u8>, JoseError> {
+        let mut attempt = 1;
+        loop {
+            match self.attempt(client, message).await {
+                Ok(signature) => return Ok(signature),
+                Err((true, e)) if attempt < self.attempts => {
+                    log::warn!("Signing attempt {} failed: {}", attempt, e);
+                    tokio::time::sleep(Duration::from_millis(100 * u64::from(attempt))).await;
+                    attempt += 1;
+                }
+                Err((true, e)) => {
+                    return Err(JoseError::InvalidSignature(Unavailable(e).into()));
+                }
+                Err((false, e)) => {
+                    return Err(JoseError::InvalidSignature(anyhow::anyhow!(
+                        "Signing service refused to sign: {}",
+                        e
+                    )));
+                }
+            }
+        }
+    }
+
+    // Sign on a fresh runtime, with a client of its own, as connections of
+    // the shared client belong to the runtime they were opened on
+    fn sign_on_own_runtime(&self, message: &[u8]) -> Result<Vec<u8>, JoseError> {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| JoseError::InvalidSignature(Unavailable(e.to_string()).into()))?
+            .block_on(self.sign_remotely(&reqwest::Client::new(), message))
+    }
+}
+
+impl JwsSigner for RemoteSigner {
+    fn algorithm(&self) -> &dyn JwsAlgorithm {
+        self.verifier.algorithm()
+    }
+
+    fn key_id(&self) -> Option<&str> {
+        self.key_id.as_deref()
+    }
+
+    fn signature_len(&self) -> usize {
+        self.signature_len
+    }
+
+    // Signing is synchronous in josekit, so the request to the signing
+    // service blocks the current thread. On a multi-threaded runtime this
+    // does not stall other tasks of the runtime. Elsewhere, including on a
+    // single-threaded runtime that can't block in place, the request runs
+    // on a runtime of its own in a separate thread.
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, JoseError> {
+        let signature = match Handle::try_current() {
+            Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                tokio::task::block_in_place(|| {
+                    handle.block_on(self.sign_remotely(&self.client, message))
+                })
+            }
+            _ => std::thread::scope(|scope| {
+                scope
+                    .spawn(|| self.sign_on_own_runtime(message))
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e))
+            }),
+        }?;
+        self.verifier.verify(message, &signature)?;
+        Ok(signature)
+    }
+
+    fn box_clone(&self) -> Box<dyn JwsSigner> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,124 @@
+//! Sentry events for failed requests, tagged with the context of the request.
+//! Only identifiers and codes are attached, never attribute values or
+//! continuation urls.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rocket::{http::Status, Request};
+use sentry::{protocol::Level, Scope};
+
+use crate::{irma, Error};
+
+// Marks requests for which an event was already captured, so the catcher
+// answering them does not capture a second one
+struct Captured(AtomicBool);
+
+// Tags describing a failed request
+struct Context {
+    route: Option<String>,
+    flow: Option<&'static str>,
+    session: Option<String>,
+    request_id: Option<String>,
+    session_status: Option<&'static str>,
+    error_code: String,
+    client_error: bool,
+}
+
+impl Context {
+    fn new(request: &Request<'_>, error_code: String, client_error: bool) -> Context {
+        let route = request
+            .route()
+            .and_then(|route| route.name.as_deref())
+            .map(str::to_string);
+        let flow = match route.as_deref() {
+            Some("decorated_continue") => Some("in_band"),
+            Some("session_complete") => Some("out_of_band"),
+            _ => None,
+        };
+        let session = request
+            .query_value::<String>("token")
+            .and_then(Result::ok)
+            .map(|token| irma::SessionToken::new(token).display_token());
+        let request_id = request
+            .headers()
+            .get_one("X-Request-Id")
+            .map(str::to_string);
+        Context {
+            route,
+            flow,
+            session,
+            request_id,
+            session_status: None,
+            error_code,
+            client_error,
+        }
+    }
+
+    fn apply(self, scope: &mut Scope) {
+        if let Some(route) = self.route {
+            scope.set_tag("route", route);
+        }
+        if let Some(flow) = self.flow {
+            scope.set_tag("flow", flow);
+        }
+        if let Some(session) = self.session {
+            scope.set_tag("session", session);
+        }
+        if let Some(request_id) = self.request_id {
+            scope.set_tag("request_id", request_id);
+        }
+        if let Some(session_status) = self.session_status {
+            scope.set_tag("irma_session_status", session_status);
+        }
+        scope.set_tag("error_code", self.error_code);
+        // Allows filtering errors caused by clients from alerting
+        scope.set_tag("client_error", self.client_error);
+    }
+
+    // Capture an event in a scope of its own, so the tags do not stick to
+    // events of later requests
+    fn capture(self, request: &Request<'_>) {
+        request
+            .local_cache(|| Captured(AtomicBool::new(false)))
+            .0
+            .store(true, Ordering::Relaxed);
+        let level = if self.client_error {
+            Level::Warning
+        } else {
+            Level::Error
+        };
+        let message = format!("Request failed with {}", self.error_code);
+        sentry::with_scope(
+            |scope| self.apply(scope),
+            || sentry::capture_message(&message, level),
+        );
+    }
+}
+
+/// Capture an event for a request that failed with `error`
+pub fn capture_error(request: &Request<'_>, error: &Error) {
+    let mut context = Context::new(request, error.code().to_string(), error.is_client_error());
+    context.session_status = match error {
+        Error::Irma(irma::Error::Cancelled()) => Some("CANCELLED"),
+        Error::Irma(irma::Error::Timeout()) => Some("TIMEOUT"),
+        Error::Irma(irma::Error::Incomplete()) => Some("INCOMPLETE"),
+        _ => None,
+    };
+    context.capture(request);
+}
+
+/// Capture an event for a request answered by a catcher, unless the error
+/// causing it was captured already. Internal server errors are left out, those
+/// are captured by the error responder or reported as panics.
+pub fn capture_status(request: &Request<'_>, status: Status) {
+    let captured = request
+        .local_cache(|| Captured(AtomicBool::new(false)))
+        .0
+        .load(Ordering::Relaxed);
+    if captured || status == Status::InternalServerError {
+        return;
+    }
+    let reason = status.reason().unwrap_or("error");
+    let error_code = reason.to_ascii_lowercase().replace(' ', "_");
+    Context::new(request, error_code, status.class().is_client_error()).capture(request);
+}
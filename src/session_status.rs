@@ -0,0 +1,91 @@
+//! Status of a session for the waiting page, which unlike the status the irma
+//! server reports tells why a session failed, so the page can show a specific
+//! message.
+
+use rocket::{get, serde::json::Json};
+use serde::Serialize;
+
+use crate::{b64, config, failure::FailureReason, irma, within_deadline, CurrentConfig, Error};
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum SessionProgress {
+    Initialized,
+    Pairing,
+    Connected,
+    Done,
+    /// Terminal failure, with the reason as also reported to the requestor
+    Failed {
+        reason: &'static str,
+    },
+}
+
+impl SessionProgress {
+    fn failed(reason: FailureReason) -> Self {
+        SessionProgress::Failed {
+            reason: reason.as_str(),
+        }
+    }
+}
+
+// Progress of a finished session, which failed when its proof was not
+// acceptable or, when the requested attributes are known, when the disclosed
+// attributes do not map to them like they would on finalization
+async fn outcome(
+    config: &config::Config,
+    token: &irma::SessionToken,
+    attributes: Option<&[String]>,
+) -> Result<SessionProgress, Error> {
+    let result = within_deadline(
+        config,
+        "session_status",
+        config.irma_server().get_result(token),
+    )
+    .await
+    .and_then(|session_result| match attributes {
+        Some(attributes) => config
+            .map_response(attributes, session_result)
+            .map(drop)
+            .map_err(Error::from),
+        None => Ok(()),
+    });
+    match result {
+        Ok(()) => Ok(SessionProgress::Done),
+        Err(e) => match FailureReason::of(&e) {
+            Some(reason) => Ok(SessionProgress::failed(reason)),
+            None => Err(e),
+        },
+    }
+}
+
+// The attributes are encoded like in the continuation of the session, as
+// base64 json list
+#[get("/session_status/<token>?<attributes>")]
+pub async fn session_status(
+    config: CurrentConfig,
+    token: String,
+    attributes: Option<String>,
+) -> Result<Option<Json<SessionProgress>>, Error> {
+    let token = irma::SessionToken::new(token);
+    let attributes = attributes
+        .as_deref()
+        .map(b64::decode_b64_json::<Vec<String>>)
+        .transpose()?;
+    let status = within_deadline(
+        &config,
+        "session_status",
+        config.irma_server().status(&token),
+    )
+    .await;
+    let progress = match status {
+        Ok(irma::SessionStatus::Initialized) => SessionProgress::Initialized,
+        Ok(irma::SessionStatus::Pairing) => SessionProgress::Pairing,
+        Ok(irma::SessionStatus::Connected) => SessionProgress::Connected,
+        Ok(irma::SessionStatus::Done) => outcome(&config, &token, attributes.as_deref()).await?,
+        Ok(irma::SessionStatus::Cancelled) => SessionProgress::failed(FailureReason::Cancelled),
+        Ok(irma::SessionStatus::Timeout) => SessionProgress::failed(FailureReason::Timeout),
+        Err(Error::Irma(irma::Error::UnknownSession())) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    Ok(Some(Json(progress)))
+}
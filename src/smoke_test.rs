@@ -0,0 +1,194 @@
+//! The `smoke-test` subcommand, which runs a complete disclosure session
+//! against the configured irma server, to verify a deployment without going
+//! through a core. The session is shown as universal link to open or scan on
+//! a phone, and its result is mapped like results of regular sessions.
+
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use rocket::tokio::{self, runtime::Handle};
+
+use crate::{
+    config::Config,
+    irma::{IrmaDisclosureRequest, IrmaRequest, SessionStatus, SessionToken},
+};
+
+/// The arguments could not be parsed, or the attributes could not be mapped
+pub const EXIT_USAGE: i32 = 1;
+/// The session could not be started, did not complete, or its result could
+/// not be mapped
+pub const EXIT_FAILED: i32 = 2;
+/// The session did not complete before the timeout
+pub const EXIT_TIMEOUT: i32 = 3;
+/// The smoke test was interrupted
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+const USAGE: &str = "\
+Usage: smoke-test [--timeout <seconds>] <attribute>...
+Starts a session disclosing the given attributes and waits for it to complete.";
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Time between status requests while waiting for the session
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Base url of links opening a session in the irma app
+const UNIVERSAL_LINK_BASE: &str = "https://irma.app/-/session#";
+
+struct Options {
+    attributes: Vec<String>,
+    timeout: Duration,
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Option<Options> {
+    let mut attributes = vec![];
+    let mut timeout = DEFAULT_TIMEOUT;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--timeout" => timeout = Duration::from_secs(args.next()?.parse().ok()?),
+            _ if !arg.starts_with("--") => attributes.push(arg),
+            _ => return None,
+        }
+    }
+    Some(Options {
+        attributes,
+        timeout,
+    })
+}
+
+/// Run a future to completion from synchronous code, also when called from
+/// within the runtime of the server
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    match Handle::try_current() {
+        Ok(handle) => tokio::task::block_in_place(|| handle.block_on(future)),
+        Err(_) => tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Could not start runtime")
+            .block_on(future),
+    }
+}
+
+// Poll the status of a session until it no longer waits for the app
+async fn wait_for_session(config: &Config, token: &SessionToken) -> SessionStatus {
+    let mut previous = None;
+    loop {
+        match config.irma_server().status(token).await {
+            Ok(
+                status @ (SessionStatus::Done | SessionStatus::Cancelled | SessionStatus::Timeout),
+            ) => return status,
+            Ok(status) => {
+                let description = status.to_string();
+                if previous.as_ref() != Some(&description) {
+                    eprintln!("Session {}", description.to_lowercase());
+                    previous = Some(description);
+                }
+            }
+            Err(e) => eprintln!("Could not get session status: {}", e),
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+async fn cancel(config: &Config, token: &SessionToken) {
+    if let Err(e) = config.irma_server().cancel(token).await {
+        eprintln!("Could not cancel session: {}", e);
+    }
+}
+
+async fn smoke_test(config: &Config, options: Options) -> i32 {
+    let attributes = match config.normalize_attributes(&options.attributes) {
+        Ok(attributes) => attributes,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_USAGE;
+        }
+    };
+    let request = match config.map_attributes(&attributes) {
+        Ok(disclose) => IrmaRequest::Disclosure(IrmaDisclosureRequest {
+            disclose,
+            return_url: None,
+            augment_return: false,
+        }),
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_USAGE;
+        }
+    };
+
+    let started = Instant::now();
+    let session = match config.irma_server().start(&request).await {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("Could not start session: {}", e);
+            return EXIT_FAILED;
+        }
+    };
+    eprintln!(
+        "Session {} started in {} ms",
+        session.token.display_token(),
+        started.elapsed().as_millis()
+    );
+    println!("Session pointer: {}", session.qr);
+    println!(
+        "Universal link: {}{}",
+        UNIVERSAL_LINK_BASE,
+        url::form_urlencoded::byte_serialize(session.qr.as_bytes()).collect::<String>()
+    );
+
+    let status = tokio::select! {
+        status = wait_for_session(config, &session.token) => status,
+        _ = tokio::time::sleep(options.timeout) => {
+            eprintln!("Session did not complete within {} seconds", options.timeout.as_secs());
+            cancel(config, &session.token).await;
+            return EXIT_TIMEOUT;
+        }
+        _ = tokio::signal::ctrl_c() => {
+            eprintln!("Interrupted");
+            cancel(config, &session.token).await;
+            return EXIT_INTERRUPTED;
+        }
+    };
+    if status != SessionStatus::Done {
+        eprintln!("Session ended with status {}", status);
+        return EXIT_FAILED;
+    }
+
+    let mapped = match config.irma_server().get_result(&session.token).await {
+        Ok(result) => config.map_response(&attributes, result),
+        Err(e) => {
+            eprintln!("Could not get session result: {}", e);
+            return EXIT_FAILED;
+        }
+    };
+    match mapped {
+        Ok(mapped) => {
+            let mut disclosed: Vec<&str> = mapped.values.keys().map(String::as_str).collect();
+            disclosed.sort_unstable();
+            println!(
+                "Success in {} ms, disclosed: {}",
+                started.elapsed().as_millis(),
+                disclosed.join(", ")
+            );
+            0
+        }
+        Err(e) => {
+            eprintln!("Could not map session result: {}", e);
+            EXIT_FAILED
+        }
+    }
+}
+
+/// Run the subcommand with the arguments following its name, returning the
+/// exit code
+pub fn run(config: &Config, args: impl Iterator<Item = String>) -> i32 {
+    match parse_options(args) {
+        Some(options) => block_on(smoke_test(config, options)),
+        None => {
+            eprintln!("{}", USAGE);
+            EXIT_USAGE
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::{
+    num::NonZeroUsize,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use base64::URL_SAFE_NO_PAD;
+use lru::LruCache;
+use rand::Rng;
+
+/// Generate a random, unguessable identifier
+pub fn random_id() -> String {
+    base64::encode_config(rand::thread_rng().gen::<[u8; 32]>(), URL_SAFE_NO_PAD)
+}
+
+/// In-memory store handing out random references to values that expire after
+/// a fixed time to live. Values are kept in order of use, so expiry and
+/// eviction take constant time.
+pub struct TtlStore<V> {
+    ttl: Duration,
+    entries: Mutex<LruCache<String, (Instant, V)>>,
+}
+
+impl<V> TtlStore<V> {
+    pub fn new(ttl: Duration) -> Self {
+        TtlStore {
+            ttl,
+            entries: Mutex::new(LruCache::unbounded()),
+        }
+    }
+
+    /// Create a store holding at most `max_entries` values, evicting the
+    /// least recently used value when full
+    pub fn with_max_entries(ttl: Duration, max_entries: usize) -> Self {
+        let max_entries = NonZeroUsize::new(max_entries).unwrap_or(NonZeroUsize::MIN);
+        TtlStore {
+            ttl,
+            entries: Mutex::new(LruCache::new(max_entries)),
+        }
+    }
+
+    /// Store a value, returning the reference under which it can be retrieved
+    pub fn insert(&self, value: V) -> String {
+        let id = random_id();
+        self.insert_with_id(id.clone(), value);
+        id
+    }
+
+    /// Store a value under a reference previously obtained from [`random_id`]
+    pub fn insert_with_id(&self, id: String, value: V) {
+        let now = Instant::now();
+
+        let mut entries = self.entries.lock().unwrap();
+        // Expired entries are purged from the least recently used end on
+        // insertion. An entry used after others were created can hold back
+        // their purge until it expires itself, which bounds memory use to the
+        // entries created within two times the time to live.
+        while entries
+            .peek_lru()
+            .is_some_and(|(_, (created, _))| now.duration_since(*created) >= self.ttl)
+        {
+            entries.pop_lru();
+        }
+        // Evicts the least recently used entry when the store is full
+        entries.push(id, (now, value));
+    }
+
+    /// Remove and return the value stored under `id`, if it has not expired
+    pub fn take(&self, id: &str) -> Option<V> {
+        let (created, value) = self.entries.lock().unwrap().pop(id)?;
+        if created.elapsed() < self.ttl {
+            Some(value)
+        } else {
+            None
+        }
+    }
+}
+
+impl<V: Clone> TtlStore<V> {
+    /// Get the value stored under `id` without removing it, if it has not
+    /// expired, marking it as recently used
+    pub fn get(&self, id: &str) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+        let (created, value) = entries.get(id)?;
+        if created.elapsed() < self.ttl {
+            Some(value.clone())
+        } else {
+            None
+        }
+    }
+}
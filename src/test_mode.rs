@@ -0,0 +1,184 @@
+//! Test mode, in which sessions complete with canned attribute values after a
+//! confirmation in the browser, without involving an irma server. This is only
+//! available when the configuration explicitly enables insecure development
+//! mode.
+
+use std::time::{Duration, SystemTime};
+
+use askama::Template;
+use rocket::{
+    get, post,
+    response::{content::RawHtml, Redirect},
+    Either, State,
+};
+use verder_helpen_proto::{AuthResult, AuthStatus, StartAuthResponse};
+
+use crate::{
+    config, continuation_redirect, deliver_result,
+    events::{self, CompletionEvent},
+    sign_auth_result, store, AuthRequest, CurrentConfig, Error, IssuedResults, ResultRecipient,
+    ResultStore, RetainedResultStore,
+};
+
+/// Time a test session can be confirmed after it was started
+const TEST_SESSION_TTL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone)]
+struct TestSession {
+    attributes: Vec<String>,
+    continuation: Option<String>,
+    attr_url: Option<String>,
+    requestor_key_id: Option<String>,
+    nonce: Option<String>,
+}
+
+pub struct TestSessions(store::TtlStore<TestSession>);
+
+impl TestSessions {
+    pub fn new() -> Self {
+        TestSessions(store::TtlStore::new(TEST_SESSION_TTL))
+    }
+}
+
+#[derive(Template)]
+#[template(path = "test_confirm.html")]
+struct ConfirmTemplate<'a> {
+    attributes: Vec<(&'a str, &'a str)>,
+    action: &'a str,
+}
+
+// Values for the requested attributes, failing when any of them has no canned
+// value configured
+fn canned_attributes<'a>(
+    config: &'a config::Config,
+    attributes: &'a [String],
+) -> Result<Vec<(&'a str, &'a str)>, Error> {
+    attributes
+        .iter()
+        .map(|attribute| {
+            config
+                .test_mode_value(attribute)
+                .map(|value| (attribute.as_str(), value))
+                .ok_or_else(|| Error::Config(config::Error::UnknownAttribute(attribute.clone())))
+        })
+        .collect()
+}
+
+/// Start a test session instead of an irma session
+pub(crate) fn start(
+    config: &config::Config,
+    sessions: &TestSessions,
+    request: &AuthRequest,
+) -> Result<StartAuthResponse, Error> {
+    canned_attributes(config, &request.attributes)?;
+    log::warn!("Starting test mode session, no actual authentication takes place");
+
+    let id = sessions.0.insert(TestSession {
+        attributes: request.attributes.clone(),
+        continuation: request.continuation.clone(),
+        attr_url: request.attr_url.clone(),
+        requestor_key_id: request.requestor_key_id.clone(),
+        nonce: request.nonce.clone(),
+    });
+    Ok(StartAuthResponse {
+        client_url: format!("{}/test_confirm/{}", config.server_url(), id),
+    })
+}
+
+#[get("/test_confirm/<id>")]
+pub async fn confirm_page(
+    config: CurrentConfig,
+    sessions: &State<TestSessions>,
+    id: String,
+) -> Result<Option<RawHtml<String>>, Error> {
+    let session = match sessions.0.get(&id) {
+        Some(session) => session,
+        None => return Ok(None),
+    };
+
+    let action = format!("{}/test_confirm/{}", config.server_url(), id);
+    let page = ConfirmTemplate {
+        attributes: canned_attributes(&config, &session.attributes)?,
+        action: &action,
+    }
+    .render()?;
+    Ok(Some(RawHtml(page)))
+}
+
+#[post("/test_confirm/<id>")]
+pub async fn confirm(
+    config: CurrentConfig,
+    sessions: &State<TestSessions>,
+    results: &State<ResultStore>,
+    retained: &State<RetainedResultStore>,
+    issued: &State<IssuedResults>,
+    id: String,
+) -> Result<Option<Either<Redirect, &'static str>>, Error> {
+    let session = match sessions.0.take(&id) {
+        Some(session) => session,
+        None => return Ok(None),
+    };
+
+    let attributes = canned_attributes(&config, &session.attributes)?
+        .into_iter()
+        .map(|(name, value)| (config.claim_name(name).to_string(), value.to_string()))
+        .collect();
+    let auth_result = AuthResult {
+        status: AuthStatus::Success,
+        attributes: Some(attributes),
+        session_url: None,
+    };
+    let (auth_result, jti) = sign_auth_result(
+        &config,
+        retained,
+        issued,
+        &session.attributes,
+        auth_result,
+        None,
+        SystemTime::now(),
+        ResultRecipient {
+            requestor_key: session.requestor_key_id.as_deref(),
+            nonce: session.nonce.as_deref(),
+            destination: session
+                .attr_url
+                .as_deref()
+                .or(session.continuation.as_deref())
+                .unwrap_or_default(),
+        },
+    )?;
+
+    let (response, delivered) = match (session.attr_url, session.continuation) {
+        (Some(attr_url), continuation) => {
+            let delivered = deliver_result(&config, &attr_url, auth_result).await?;
+            let response = match continuation {
+                Some(continuation) => Either::Left(Redirect::to(continuation)),
+                None => Either::Right("Test session completed"),
+            };
+            (response, delivered)
+        }
+        (None, Some(continuation)) => (
+            Either::Left(continuation_redirect(
+                &config,
+                results,
+                &continuation,
+                auth_result,
+            )),
+            true,
+        ),
+        (None, None) => {
+            return Err(Error::BadRequest(
+                "Either a continuation or an attr_url is required",
+            ))
+        }
+    };
+    let requestor = session.requestor_key_id.as_deref();
+    events::notify(
+        &config,
+        if delivered {
+            CompletionEvent::success(requestor, jti)
+        } else {
+            CompletionEvent::failure(requestor, "undelivered")
+        },
+    );
+    Ok(Some(response))
+}
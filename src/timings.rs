@@ -0,0 +1,75 @@
+//! Funnel timings of the authentication flow: how long users take from the
+//! start of a session until it completes.
+
+use std::{
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::{irma::SessionToken, store::TtlStore};
+
+/// Time after which a session that did not complete is forgotten
+const SESSION_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// Upper bounds in seconds of the histogram buckets, the last bucket counting
+/// everything slower
+const BUCKETS_SECONDS: [u64; 9] = [5, 10, 20, 30, 60, 120, 180, 300, 600];
+
+static STARTED: OnceLock<TtlStore<Instant>> = OnceLock::new();
+
+static TIME_TO_COMPLETE: Mutex<Histogram> = Mutex::new(Histogram {
+    buckets: [0; BUCKETS_SECONDS.len() + 1],
+    sum_seconds: 0,
+});
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Histogram {
+    /// Sessions per bucket, matching the bounds in `bounds_seconds` of the
+    /// report
+    buckets: [u64; BUCKETS_SECONDS.len() + 1],
+    sum_seconds: u64,
+}
+
+impl Histogram {
+    fn record(&mut self, elapsed: Duration) {
+        let seconds = elapsed.as_secs();
+        let bucket = BUCKETS_SECONDS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(BUCKETS_SECONDS.len());
+        self.buckets[bucket] += 1;
+        self.sum_seconds += seconds;
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Timings {
+    bounds_seconds: &'static [u64],
+    time_to_complete_seconds: Histogram,
+}
+
+fn started() -> &'static TtlStore<Instant> {
+    STARTED.get_or_init(|| TtlStore::new(SESSION_TTL))
+}
+
+/// Note the start of an irma session
+pub fn session_started(token: &SessionToken) {
+    started().insert_with_id(token.expose().to_string(), Instant::now());
+}
+
+/// Record the time an irma session took to complete since it was started
+pub fn session_completed(token: &SessionToken) {
+    if let Some(started) = started().take(token.expose()) {
+        TIME_TO_COMPLETE.lock().unwrap().record(started.elapsed());
+    }
+}
+
+/// Timings of sessions since the process started
+pub fn snapshot() -> Timings {
+    Timings {
+        bounds_seconds: &BUCKETS_SECONDS,
+        time_to_complete_seconds: TIME_TO_COMPLETE.lock().unwrap().clone(),
+    }
+}
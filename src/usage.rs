@@ -0,0 +1,60 @@
+//! Usage accounting per requested attribute set, so the costs of a deployment
+//! serving several relying parties can be allocated without parsing logs.
+//!
+//! Sessions are labelled with their sorted attribute names. The attributes
+//! travel along with the session in the continuation and callback urls, so
+//! completions carry the same label as the start. Those urls pass through
+//! the browser, so the number of distinct labels is capped, lumping any
+//! further attribute sets into [`OTHER`].
+
+use std::{collections::BTreeMap, sync::Mutex};
+
+use serde::Serialize;
+
+/// Maximum number of distinct labels tracked
+const MAX_LABELS: usize = 64;
+
+/// Label for attribute sets beyond the first `MAX_LABELS`
+pub const OTHER: &str = "other";
+
+static USAGE: Mutex<BTreeMap<String, Usage>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Usage {
+    started: u64,
+    completed: u64,
+}
+
+/// Label identifying a set of requested attributes independent of their order
+pub fn label(attributes: &[String]) -> String {
+    let mut attributes: Vec<&str> = attributes.iter().map(String::as_str).collect();
+    attributes.sort_unstable();
+    attributes.dedup();
+    attributes.join(",")
+}
+
+fn record(attributes: &[String], count: impl FnOnce(&mut Usage)) {
+    let label = label(attributes);
+    let mut usage = USAGE.lock().unwrap();
+    let label = if usage.contains_key(&label) || usage.len() < MAX_LABELS {
+        label
+    } else {
+        OTHER.to_string()
+    };
+    count(usage.entry(label).or_default());
+}
+
+/// Count a started session for the requested attributes
+pub fn session_started(attributes: &[String]) {
+    record(attributes, |usage| usage.started += 1);
+}
+
+/// Count a session that completed with the requested attributes disclosed
+pub fn session_completed(attributes: &[String]) {
+    record(attributes, |usage| usage.completed += 1);
+}
+
+/// Sessions per label since the process started
+pub fn snapshot() -> BTreeMap<String, Usage> {
+    USAGE.lock().unwrap().clone()
+}
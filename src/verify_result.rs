@@ -0,0 +1,218 @@
+//! The `verify-result` subcommand, which decrypts and verifies an auth result
+//! offline, for debugging results a core refuses. It prints the claims of the
+//! result, with attribute values masked unless asked otherwise, and exits
+//! with a code telling which check failed.
+
+use std::{io::Read, path::PathBuf, time::SystemTime};
+
+use base64::URL_SAFE_NO_PAD;
+use josekit::{
+    jwe::{
+        JweDecrypter, ECDH_ES, ECDH_ES_A128KW, ECDH_ES_A192KW, ECDH_ES_A256KW, RSA_OAEP,
+        RSA_OAEP_256, RSA_OAEP_384, RSA_OAEP_512,
+    },
+    jws::{EdDSA, JwsVerifier, ES256, ES384, ES512, PS256, PS384, PS512, RS256, RS384, RS512},
+    JoseError,
+};
+use serde_json::Value;
+
+use crate::jwe;
+
+/// The arguments could not be parsed, or the token or a key could not be read
+pub const EXIT_USAGE: i32 = 1;
+/// The result could not be decrypted with the decryption key
+pub const EXIT_DECRYPTION: i32 = 2;
+/// The signature of the result does not verify with the verification key
+pub const EXIT_SIGNATURE: i32 = 3;
+/// The result is expired, or not valid yet
+pub const EXIT_EXPIRED: i32 = 4;
+
+const USAGE: &str = "\
+Usage: verify-result --decryption-key <pem file> --verification-key <pem file>
+                     [--show-values] [token]
+Reads the token from standard input when not given.";
+
+/// Placeholder for masked attribute values
+const MASK: &str = "***";
+
+struct Options {
+    token: Option<String>,
+    decryption_key: PathBuf,
+    verification_key: PathBuf,
+    show_values: bool,
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Option<Options> {
+    let mut token = None;
+    let mut decryption_key = None;
+    let mut verification_key = None;
+    let mut show_values = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--decryption-key" => decryption_key = Some(PathBuf::from(args.next()?)),
+            "--verification-key" => verification_key = Some(PathBuf::from(args.next()?)),
+            "--show-values" => show_values = true,
+            _ if token.is_none() && !arg.starts_with("--") => token = Some(arg),
+            _ => return None,
+        }
+    }
+    Some(Options {
+        token,
+        decryption_key: decryption_key?,
+        verification_key: verification_key?,
+        show_values,
+    })
+}
+
+// The alg header of a compact jwe or jws, read without verifying anything
+fn header_algorithm(token: &str) -> Result<String, JoseError> {
+    let header = token.split('.').next().unwrap_or_default();
+    let header = base64::decode_config(header, URL_SAFE_NO_PAD)
+        .map_err(|e| JoseError::InvalidJwtFormat(e.into()))?;
+    let header: Value =
+        serde_json::from_slice(&header).map_err(|e| JoseError::InvalidJson(e.into()))?;
+    header
+        .get("alg")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| JoseError::InvalidJwtFormat(anyhow::anyhow!("Missing alg header")))
+}
+
+fn decrypter(algorithm: &str, pem: &[u8]) -> Result<Box<dyn JweDecrypter>, JoseError> {
+    Ok(match algorithm {
+        "RSA-OAEP" => Box::new(RSA_OAEP.decrypter_from_pem(pem)?),
+        "RSA-OAEP-256" => Box::new(RSA_OAEP_256.decrypter_from_pem(pem)?),
+        "RSA-OAEP-384" => Box::new(RSA_OAEP_384.decrypter_from_pem(pem)?),
+        "RSA-OAEP-512" => Box::new(RSA_OAEP_512.decrypter_from_pem(pem)?),
+        "ECDH-ES" => Box::new(ECDH_ES.decrypter_from_pem(pem)?),
+        "ECDH-ES+A128KW" => Box::new(ECDH_ES_A128KW.decrypter_from_pem(pem)?),
+        "ECDH-ES+A192KW" => Box::new(ECDH_ES_A192KW.decrypter_from_pem(pem)?),
+        "ECDH-ES+A256KW" => Box::new(ECDH_ES_A256KW.decrypter_from_pem(pem)?),
+        algorithm => {
+            return Err(JoseError::InvalidJweFormat(anyhow::anyhow!(
+                "Unsupported key management algorithm {}",
+                algorithm
+            )))
+        }
+    })
+}
+
+fn verifier(algorithm: &str, pem: &[u8]) -> Result<Box<dyn JwsVerifier>, JoseError> {
+    Ok(match algorithm {
+        "RS256" => Box::new(RS256.verifier_from_pem(pem)?),
+        "RS384" => Box::new(RS384.verifier_from_pem(pem)?),
+        "RS512" => Box::new(RS512.verifier_from_pem(pem)?),
+        "PS256" => Box::new(PS256.verifier_from_pem(pem)?),
+        "PS384" => Box::new(PS384.verifier_from_pem(pem)?),
+        "PS512" => Box::new(PS512.verifier_from_pem(pem)?),
+        "ES256" => Box::new(ES256.verifier_from_pem(pem)?),
+        "ES384" => Box::new(ES384.verifier_from_pem(pem)?),
+        "ES512" => Box::new(ES512.verifier_from_pem(pem)?),
+        "EdDSA" => Box::new(EdDSA.verifier_from_pem(pem)?),
+        algorithm => {
+            return Err(JoseError::UnsupportedSignatureAlgorithm(anyhow::anyhow!(
+                "Unsupported signing algorithm {}",
+                algorithm
+            )))
+        }
+    })
+}
+
+// Replace all string values in a claim by the mask, keeping its structure
+fn mask(value: &mut Value) {
+    match value {
+        Value::String(value) => *value = MASK.to_string(),
+        Value::Object(values) => values.values_mut().for_each(mask),
+        Value::Array(values) => values.iter_mut().for_each(mask),
+        _ => {}
+    }
+}
+
+fn read_input(options: &Options) -> Result<(String, Vec<u8>, Vec<u8>), std::io::Error> {
+    let token = match &options.token {
+        Some(token) => token.clone(),
+        None => {
+            let mut token = String::new();
+            std::io::stdin().read_to_string(&mut token)?;
+            token
+        }
+    };
+    Ok((
+        token.trim().to_string(),
+        std::fs::read(&options.decryption_key)?,
+        std::fs::read(&options.verification_key)?,
+    ))
+}
+
+/// Run the subcommand with the arguments following its name, returning the
+/// exit code
+pub fn run(args: impl Iterator<Item = String>) -> i32 {
+    let options = match parse_options(args) {
+        Some(options) => options,
+        None => {
+            eprintln!("{}", USAGE);
+            return EXIT_USAGE;
+        }
+    };
+    let (token, decryption_key, verification_key) = match read_input(&options) {
+        Ok(input) => input,
+        Err(e) => {
+            eprintln!("Could not read input: {}", e);
+            return EXIT_USAGE;
+        }
+    };
+
+    let njwt = match header_algorithm(&token)
+        .and_then(|algorithm| decrypter(&algorithm, &decryption_key))
+        .and_then(|decrypter| jwe::decrypt_result(&token, decrypter.as_ref()))
+    {
+        Ok(njwt) => njwt,
+        Err(e) => {
+            eprintln!("Decryption failed: {}", e);
+            return EXIT_DECRYPTION;
+        }
+    };
+    let payload = match header_algorithm(&njwt)
+        .and_then(|algorithm| verifier(&algorithm, &verification_key))
+        .and_then(|verifier| jwe::verify_result(&njwt, verifier.as_ref()))
+    {
+        Ok(payload) => payload,
+        Err(e) => {
+            eprintln!("Signature verification failed: {}", e);
+            return EXIT_SIGNATURE;
+        }
+    };
+
+    let mut claims = payload.claims_set().clone();
+    if !options.show_values {
+        for claim in ["attributes", "credentials"] {
+            if let Some(value) = claims.get_mut(claim) {
+                mask(value);
+            }
+        }
+    }
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&claims).unwrap_or_default()
+    );
+
+    let now = SystemTime::now();
+    if let Some(not_before) = payload.not_before() {
+        if let Ok(until) = not_before.duration_since(now) {
+            eprintln!("Not valid until {} seconds from now", until.as_secs());
+            return EXIT_EXPIRED;
+        }
+    }
+    match payload
+        .expires_at()
+        .map(|expires| expires.duration_since(now))
+    {
+        Some(Ok(left)) => eprintln!("Expires in {} seconds", left.as_secs()),
+        Some(Err(e)) => {
+            eprintln!("Expired {} seconds ago", e.duration().as_secs());
+            return EXIT_EXPIRED;
+        }
+        None => eprintln!("Does not expire"),
+    }
+    0
+}
@@ -0,0 +1,101 @@
+//! Auth results as W3C verifiable presentations, for consumers that verify
+//! standard verifiable credentials rather than the nested jwt format. The
+//! presentation is a signed jwt with a `vp` claim, holding the disclosed
+//! attributes as a single credential, itself a signed jwt with a `vc` claim,
+//! following the jwt encoding of the verifiable credentials data model. The
+//! signed presentation is encrypted to the result encryption key as nested
+//! jwt, with content type `JWT`.
+
+use std::time::SystemTime;
+
+use josekit::{
+    jwe::{self, JweEncrypter, JweHeader},
+    jws::{JwsHeader, JwsSigner},
+    jwt::{self, JwtPayload},
+    JoseError,
+};
+use serde_json::json;
+use verder_helpen_proto::AuthResult;
+
+use crate::jwe::{set_result_claims, to_value, ResultClaims};
+
+/// Base context of verifiable credentials and presentations, preceding any
+/// configured contexts
+const BASE_CONTEXT: &str = "https://www.w3.org/2018/credentials/v1";
+
+fn header(key_id: Option<&str>) -> JwsHeader {
+    let mut header = JwsHeader::new();
+    header.set_token_type("JWT");
+    if let Some(kid) = key_id {
+        header.set_key_id(kid);
+    }
+    header
+}
+
+/// Sign an auth result as a verifiable presentation and encrypt it. The
+/// disclosed attributes make up the credential subject of the credential in
+/// it, which carries the configured contexts and types on top of the base
+/// ones. Results without attributes yield a presentation without credentials.
+/// The other result claims are claims of the presentation jwt.
+#[allow(clippy::too_many_arguments)]
+pub fn sign_and_encrypt_auth_result(
+    auth_result: &AuthResult,
+    claims: &ResultClaims,
+    signer: &dyn JwsSigner,
+    key_id: Option<&str>,
+    encrypter: &dyn JweEncrypter,
+    encryption_key_id: Option<&str>,
+    contexts: &[String],
+    types: &[String],
+) -> Result<String, JoseError> {
+    let now = SystemTime::now();
+    let mut context = vec![BASE_CONTEXT];
+    context.extend(contexts.iter().map(String::as_str));
+
+    let mut credentials = vec![];
+    if let Some(attributes) = &auth_result.attributes {
+        let mut credential_type = vec!["VerifiableCredential"];
+        credential_type.extend(types.iter().map(String::as_str));
+
+        let mut vc_payload = JwtPayload::new();
+        vc_payload.set_issuer(&claims.issuer);
+        vc_payload.set_jwt_id(format!("{}-vc", claims.jti));
+        vc_payload.set_issued_at(&now);
+        if claims.not_before {
+            vc_payload.set_not_before(&now);
+        }
+        vc_payload.set_expires_at(&(now + claims.validity));
+        vc_payload.set_claim(
+            "vc",
+            Some(json!({
+                "@context": context,
+                "type": credential_type,
+                "credentialSubject": to_value(attributes)?,
+            })),
+        )?;
+        credentials.push(jwt::encode_with_signer(
+            &vc_payload,
+            &header(key_id),
+            signer,
+        )?);
+    }
+
+    let mut vp_payload = JwtPayload::new();
+    set_result_claims(&mut vp_payload, auth_result, claims, now)?;
+    vp_payload.set_claim(
+        "vp",
+        Some(json!({
+            "@context": context,
+            "type": ["VerifiablePresentation"],
+            "verifiableCredential": credentials,
+        })),
+    )?;
+    let presentation = jwt::encode_with_signer(&vp_payload, &header(key_id), signer)?;
+
+    let mut enc_header = JweHeader::new();
+    enc_header.set_content_type("JWT");
+    if let Some(kid) = encryption_key_id {
+        enc_header.set_key_id(kid);
+    }
+    jwe::serialize_compact(presentation.as_bytes(), &enc_header, encrypter)
+}
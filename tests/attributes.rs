@@ -0,0 +1,58 @@
+//! Listing of the attributes a deployment supports.
+
+mod common;
+
+use rocket::http::Status;
+use serde_json::{json, Value};
+
+async fn listing(overrides: Value) -> Value {
+    let client = common::client(common::config("http://127.0.0.1:1", overrides)).await;
+    let response = client.get("/attributes").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    response.into_json().await.unwrap()
+}
+
+#[rocket::async_test]
+async fn attributes_are_listed_with_their_metadata() {
+    let listing = listing(json!({
+        "attribute_formats": {
+            "email": { "type": "email" },
+            "fullname": { "type": "regex", "pattern": "\\S.*" },
+        },
+        "attribute_requirements": { "city": "optional", "email": "preferred" },
+    }))
+    .await;
+
+    assert_eq!(
+        listing,
+        json!([
+            {
+                "name": "city",
+                "irma_ids": ["irma-demo.gemeente.address.city"],
+                "requirement": "optional",
+            },
+            {
+                "name": "email",
+                "irma_ids": ["pbdf.pbdf.email.email", "pbdf.sidn-pbdf.email.email"],
+                "requirement": "preferred",
+                "format": { "type": "email" },
+            },
+            {
+                "name": "fullname",
+                "irma_ids": ["irma-demo.gemeente.personalData.fullname"],
+                "requirement": "required",
+                "format": { "type": "regex", "pattern": "\\S.*" },
+            },
+        ])
+    );
+}
+
+#[rocket::async_test]
+async fn hidden_attribute_ids_are_left_out() {
+    let listing = listing(json!({ "hide_attribute_ids": true })).await;
+
+    assert_eq!(
+        listing[0],
+        json!({ "name": "city", "requirement": "required" })
+    );
+}
@@ -0,0 +1,334 @@
+//! Helpers shared by the integration tests: configurations around a test key
+//! pair, a mock irma server and the plugin itself on free ports, a server
+//! recording the requests it receives, and decryption of results.
+
+#![allow(dead_code)]
+
+use std::{
+    collections::HashMap,
+    net::TcpListener,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use base64::URL_SAFE_NO_PAD;
+use josekit::{
+    jwe::{JweDecrypter, RSA_OAEP, RSA_OAEP_256},
+    jwt::JwtPayload,
+    JoseError,
+};
+use rocket::{
+    data::ToByteUnit,
+    fairing::AdHoc,
+    http::Method,
+    local::asynchronous::Client,
+    route::{Handler, Outcome},
+    tokio, Build, Data, Request, Rocket, Route,
+};
+use serde_json::{json, Value};
+#[cfg(feature = "mock-irma")]
+use verder_helpen_auth_irma::mock_irma;
+use verder_helpen_auth_irma::{
+    config::{self, Config},
+    create_rocket, jwe,
+};
+
+pub const SERVER_URL: &str = "https://auth-irma.example.com";
+pub const UI_IRMA_URL: &str = "https://irma-ui.example.com/index.html";
+pub const CONTINUATION: &str = "https://core.example.com/continue";
+
+/// Key pair results are both signed with and encrypted to
+pub const PRIVATE_KEY: &str = include_str!("../keys/rsa.pem");
+pub const PUBLIC_KEY: &str = include_str!("../keys/rsa.pub.pem");
+
+/// Port nothing listens on at the moment
+pub fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("No free port")
+        .port()
+}
+
+/// Launch a mock irma server, returning its url
+#[cfg(feature = "mock-irma")]
+pub async fn mock_irma_server() -> String {
+    let port = free_port();
+    mock_irma::spawn(port).await;
+    format!("http://127.0.0.1:{port}")
+}
+
+/// Launch a rocket on `port` in the background, returning once it accepts
+/// requests
+pub async fn launch(rocket: Rocket<Build>, port: u16) {
+    let figment = rocket::Config::figment()
+        .merge(("port", port))
+        .merge(("address", "127.0.0.1"));
+    let (listening, ready) = tokio::sync::oneshot::channel();
+    let rocket =
+        rocket
+            .configure(figment)
+            .attach(AdHoc::on_liftoff("Test server ready", move |_| {
+                Box::pin(async move {
+                    let _ = listening.send(());
+                })
+            }));
+    tokio::spawn(async move {
+        if let Err(e) = rocket.launch().await {
+            panic!("Test server failed: {}", e);
+        }
+    });
+    ready.await.expect("Test server did not start");
+}
+
+/// Launch the plugin with the configuration of [`config`], returning its url.
+/// The plugin is its own internal url, so the irma server can call back.
+pub async fn plugin_server(irma_url: &str, mut overrides: Value) -> String {
+    let port = free_port();
+    let url = format!("http://127.0.0.1:{port}");
+    overrides["internal_url"] = Value::String(url.clone());
+    launch(create_rocket(config(irma_url, overrides), None), port).await;
+    url
+}
+
+/// Request received by a [`Recorder`]
+#[derive(Clone, Debug)]
+pub struct Recorded {
+    pub method: Method,
+    /// Path and query of the request
+    pub uri: String,
+    pub content_type: Option<String>,
+    pub body: String,
+}
+
+/// Server answering every request with an empty 200 response, recording the
+/// requests for inspection by the test
+#[derive(Clone, Default)]
+pub struct Recorder {
+    requests: Arc<Mutex<Vec<Recorded>>>,
+    /// Time to wait before answering, to emulate a slow server
+    delay: Duration,
+}
+
+#[rocket::async_trait]
+impl Handler for Recorder {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> Outcome<'r> {
+        let body = data
+            .open(1.mebibytes())
+            .into_string()
+            .await
+            .map(|body| body.into_inner())
+            .unwrap_or_default();
+        self.requests.lock().unwrap().push(Recorded {
+            method: request.method(),
+            uri: request.uri().to_string(),
+            content_type: request
+                .headers()
+                .get_one("Content-Type")
+                .map(str::to_string),
+            body,
+        });
+        tokio::time::sleep(self.delay).await;
+        Outcome::from(request, ())
+    }
+}
+
+impl Recorder {
+    /// Launch a recorder, returning its url
+    pub async fn spawn() -> (String, Recorder) {
+        Recorder::spawn_slow(Duration::ZERO).await
+    }
+
+    /// Launch a recorder answering only after `delay`, returning its url
+    pub async fn spawn_slow(delay: Duration) -> (String, Recorder) {
+        let recorder = Recorder {
+            delay,
+            ..Recorder::default()
+        };
+        let routes: Vec<Route> = [Method::Get, Method::Post, Method::Put, Method::Delete]
+            .into_iter()
+            .map(|method| Route::new(method, "/<path..>", recorder.clone()))
+            .collect();
+        let port = free_port();
+        launch(rocket::build().mount("/", routes), port).await;
+        (format!("http://127.0.0.1:{port}"), recorder)
+    }
+
+    pub fn requests(&self) -> Vec<Recorded> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Wait until at least `count` requests were recorded, returning them
+    pub async fn wait_for(&self, count: usize) -> Vec<Recorded> {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let requests = self.requests();
+            if requests.len() >= count {
+                return requests;
+            }
+            assert!(
+                Instant::now() < deadline,
+                "Received {} of {} requests",
+                requests.len(),
+                count
+            );
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
+
+/// Path of a file for the audit log of a single test
+pub fn audit_log_path() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!(
+        "auth-irma-audit-{}-{}.jsonl",
+        std::process::id(),
+        free_port()
+    ))
+}
+
+/// Wait until the audit log holds at least `count` lines, returning them
+/// parsed
+pub async fn audit_lines(path: &std::path::Path, count: usize) -> Vec<Value> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let lines: Vec<Value> = std::fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("Audit line is not json"))
+            .collect();
+        if lines.len() >= count {
+            return lines;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "Audit log has {} of {} lines",
+            lines.len(),
+            count
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// Configuration using the given irma server, with the keys above and the
+/// attributes of the sample configuration. Settings in `overrides` replace
+/// or add to the defaults.
+pub fn config(irma_url: &str, overrides: Value) -> Config {
+    try_config(irma_url, overrides).expect("Invalid test configuration")
+}
+
+pub fn try_config(irma_url: &str, overrides: Value) -> Result<Config, config::Error> {
+    let mut config = json!({
+        "server_url": SERVER_URL,
+        "internal_url": "http://127.0.0.1:1",
+        "ui_irma_url": UI_IRMA_URL,
+        "irma_probe_interval": 0,
+        "irma_server": { "url": irma_url },
+        "attributes": {
+            "email": ["pbdf.pbdf.email.email", "pbdf.sidn-pbdf.email.email"],
+            "fullname": ["irma-demo.gemeente.personalData.fullname"],
+            "city": ["irma-demo.gemeente.address.city"],
+        },
+        "encryption_pubkey": { "type": "RSA", "key": PUBLIC_KEY },
+        "signing_privkey": { "type": "RSA", "key": PRIVATE_KEY },
+    });
+    if let (Value::Object(config), Value::Object(overrides)) = (&mut config, overrides) {
+        config.extend(overrides);
+    }
+    Config::from_string(&config.to_string())
+}
+
+/// Local client of the plugin with the given configuration
+pub async fn client(config: Config) -> Client {
+    Client::tracked(create_rocket(config, None))
+        .await
+        .expect("Invalid rocket instance")
+}
+
+pub fn decode_b64(encoded: &str) -> String {
+    String::from_utf8(base64::decode_config(encoded, URL_SAFE_NO_PAD).expect("Invalid base64"))
+        .expect("Invalid utf-8")
+}
+
+/// Path of a url pointing at the plugin, for the local client
+pub fn local_path(url: &str) -> &str {
+    url.strip_prefix(SERVER_URL)
+        .unwrap_or_else(|| panic!("{} does not point at the plugin", url))
+}
+
+/// Path of the irma ui page and the continuation the client url of a start
+/// response points to. The continuation is absent for out-of-band sessions
+/// started without one.
+pub fn split_client_url(client_url: &str) -> (&str, Option<String>) {
+    let path = local_path(client_url);
+    let continuation = path
+        .strip_prefix("/auth/")
+        .and_then(|params| params.split_once('/'))
+        .map(|(_, continuation)| decode_b64(continuation));
+    (path, continuation)
+}
+
+/// Value of a query parameter of a url
+pub fn query_param(url: &str, name: &str) -> Option<String> {
+    url::Url::parse(url)
+        .expect("Invalid url")
+        .query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.into_owned())
+}
+
+/// Message of a json error response, or an empty string when it has none
+pub fn error_message(body: &str) -> String {
+    serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|envelope| envelope["message"].as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// Protected header of a compact jws or jwe, read without verifying or
+/// decrypting anything
+pub fn header(token: &str) -> Value {
+    let header = token.split('.').next().unwrap_or_default();
+    serde_json::from_slice(
+        &base64::decode_config(header, URL_SAFE_NO_PAD).expect("Invalid header encoding"),
+    )
+    .expect("Invalid header")
+}
+
+fn decrypter(token: &str) -> Box<dyn JweDecrypter> {
+    match header(token)["alg"].as_str().expect("Missing alg header") {
+        "RSA-OAEP" => Box::new(RSA_OAEP.decrypter_from_pem(PRIVATE_KEY).unwrap()),
+        "RSA-OAEP-256" => Box::new(RSA_OAEP_256.decrypter_from_pem(PRIVATE_KEY).unwrap()),
+        algorithm => panic!("Unexpected key management algorithm {}", algorithm),
+    }
+}
+
+// Configuration providing the verifier for the test signing key, with its
+// algorithm as used by the plugin
+fn verifying_config() -> Config {
+    config("http://127.0.0.1:1", json!({}))
+}
+
+/// Decrypt and verify a result produced with the test keys, returning its
+/// attributes
+pub fn try_result_attributes(token: &str) -> Result<HashMap<String, String>, JoseError> {
+    let config = verifying_config();
+    let verifier = config.signing_verifier().expect("No signing verifier");
+    jwe::decrypt_and_verify_attributes(token, decrypter(token).as_ref(), verifier)
+}
+
+pub fn result_attributes(token: &str) -> HashMap<String, String> {
+    try_result_attributes(token).expect("Invalid result")
+}
+
+/// Decrypt a result produced with the test keys, returning the signed jwt
+/// nested in it
+pub fn decrypt_result(token: &str) -> String {
+    jwe::decrypt_result(token, decrypter(token).as_ref()).expect("Undecryptable result")
+}
+
+/// Decrypt and verify a result produced with the test keys, returning all of
+/// its claims
+pub fn result_claims(token: &str) -> JwtPayload {
+    let config = verifying_config();
+    let verifier = config.signing_verifier().expect("No signing verifier");
+    jwe::verify_result(&decrypt_result(token), verifier).expect("Invalid signature")
+}
@@ -0,0 +1,95 @@
+//! Results in the cose format: signed COSE_Sign1 structures encrypted to the
+//! result key.
+
+mod common;
+
+use common::CONTINUATION;
+use coset::{CborSerializable, CoseSign1, TaggedCborSerializable};
+use josekit::{jwe::RSA_OAEP, jws::ES256};
+use rocket::http::{ContentType, Status};
+use serde_json::{json, Value};
+use verder_helpen_auth_irma::cose;
+
+fn cose_config(overrides: Value) -> Value {
+    let mut config = json!({
+        "insecure_dev_mode": true,
+        "test_mode": { "attributes": { "email": "test@example.com" } },
+        "result_format": "cose",
+    });
+    if let (Value::Object(config), Value::Object(overrides)) = (&mut config, overrides) {
+        config.extend(overrides);
+    }
+    config
+}
+
+// Complete a test mode session, returning the result it redirects with
+async fn result() -> String {
+    let client = common::client(common::config("http://127.0.0.1:1", cose_config(json!({})))).await;
+    let response = client
+        .post("/start_authentication")
+        .header(ContentType::JSON)
+        .body(json!({ "attributes": ["email"], "continuation": CONTINUATION }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let started: Value = response.into_json().await.unwrap();
+    let response = client
+        .post(common::local_path(started["client_url"].as_str().unwrap()))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::SeeOther);
+    let location = response.headers().get_one("Location").unwrap();
+    common::query_param(location, "result").expect("Missing result")
+}
+
+#[rocket::async_test]
+async fn result_is_signed_and_encrypted() {
+    let result = result().await;
+    let decrypter = RSA_OAEP.decrypter_from_pem(common::PRIVATE_KEY).unwrap();
+
+    let sign1 = cose::decrypt_result(&result, &decrypter).expect("Undecryptable result");
+
+    let sign1 = CoseSign1::from_tagged_slice(&sign1).expect("Invalid COSE_Sign1");
+    let config = common::config("http://127.0.0.1:1", json!({}));
+    let verifier = config.signing_verifier().unwrap();
+    sign1
+        .verify_signature(&[], |signature, data| verifier.verify(data, signature))
+        .expect("Invalid signature");
+    let claims: Value = ciborium::de::from_reader(sign1.payload.unwrap().as_slice()).unwrap();
+    assert_eq!(claims["attributes"], json!({ "email": "test@example.com" }));
+    assert!(claims["jti"].is_string());
+}
+
+#[rocket::async_test]
+async fn result_is_not_readable_without_the_key() {
+    let result = result().await;
+    let other_key = RSA_OAEP.generate_key_pair(2048).unwrap();
+    let decrypter = RSA_OAEP
+        .decrypter_from_der(other_key.to_der_private_key())
+        .unwrap();
+
+    assert!(cose::decrypt_result(&result, &decrypter).is_err());
+    // The signed structure is not in the clear either
+    let encoded = base64::decode_config(&result, base64::URL_SAFE_NO_PAD).unwrap();
+    assert!(CoseSign1::from_slice(&encoded).is_err());
+    assert!(CoseSign1::from_tagged_slice(&encoded).is_err());
+}
+
+#[test]
+fn keys_without_key_wrapping_are_refused() {
+    let ec_key = ES256.generate_key_pair().unwrap().to_pem_public_key();
+
+    let error = common::try_config(
+        "http://127.0.0.1:1",
+        cose_config(json!({
+            "encryption_pubkey": { "type": "EC", "key": String::from_utf8(ec_key).unwrap() },
+        })),
+    )
+    .unwrap_err();
+
+    assert!(
+        error.to_string().contains("ECDH-ES"),
+        "Unexpected error {}",
+        error
+    );
+}
@@ -0,0 +1,130 @@
+//! Statistics of result deliveries to attr_urls, driven by test mode sessions
+//! delivering to servers answering in different ways. The statistics are
+//! kept per process and per host, so this binary holds a single test.
+
+mod common;
+
+use common::Recorder;
+use rocket::{
+    http::{ContentType, Header, Status},
+    local::asynchronous::Client,
+    post, routes,
+};
+use serde_json::{json, Value};
+
+const ADMIN_KEY: &str = "admin-key";
+
+#[post("/status/<code>")]
+fn respond_with(code: u16) -> Status {
+    Status::from_code(code).unwrap()
+}
+
+// Complete a test mode session delivering its result to `attr_url`
+async fn deliver(client: &Client, attr_url: &str) {
+    let response = client
+        .post("/start_authentication")
+        .header(ContentType::JSON)
+        .body(json!({ "attributes": ["email"], "attr_url": attr_url }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let started: Value = response.into_json().await.unwrap();
+    let response = client
+        .post(common::local_path(started["client_url"].as_str().unwrap()))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+}
+
+// Statistics of deliveries to the loopback host, which all servers below
+// listen on
+async fn stats(client: &Client) -> Value {
+    let response = client
+        .get("/admin/deliveries")
+        .header(Header::new(
+            "Authorization",
+            format!("Bearer {}", ADMIN_KEY),
+        ))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let stats: Value = response.into_json().await.unwrap();
+    stats["127.0.0.1"].clone()
+}
+
+// Increase of a counter between two snapshots
+fn increase(before: &Value, after: &Value, counter: &str) -> u64 {
+    let count = |stats: &Value| {
+        stats
+            .pointer(counter)
+            .and_then(Value::as_u64)
+            .unwrap_or_default()
+    };
+    count(after) - count(before)
+}
+
+#[rocket::async_test]
+async fn deliveries_are_counted_by_outcome() {
+    let client = common::client(common::config(
+        "http://127.0.0.1:1",
+        json!({
+            "insecure_dev_mode": true,
+            "allow_insecure_urls": true,
+            "admin_api_key": ADMIN_KEY,
+            "test_mode": { "attributes": { "email": "test@example.com" } },
+        }),
+    ))
+    .await;
+    let (receiver_url, _) = Recorder::spawn().await;
+    let port = common::free_port();
+    common::launch(rocket::build().mount("/", routes![respond_with]), port).await;
+    let status_url = |code: u16| format!("http://127.0.0.1:{}/status/{}", port, code);
+
+    let cases = [
+        (format!("{}/attributes", receiver_url), "/successes"),
+        (status_url(404), "/failures/client_error"),
+        (status_url(503), "/failures/server_error"),
+        // Nothing listens on a free port
+        (
+            format!("http://127.0.0.1:{}/attributes", common::free_port()),
+            "/failures/connect",
+        ),
+    ];
+    for (attr_url, counter) in cases {
+        let before = stats(&client).await;
+        deliver(&client, &attr_url).await;
+        let after = stats(&client).await;
+
+        assert_eq!(increase(&before, &after, "/attempts"), 1, "{}", counter);
+        assert_eq!(increase(&before, &after, counter), 1, "{}", counter);
+        let outcomes = [
+            "/successes",
+            "/failures/connect",
+            "/failures/timeout",
+            "/failures/client_error",
+            "/failures/server_error",
+            "/failures/other",
+        ];
+        let counted: u64 = outcomes
+            .iter()
+            .map(|outcome| increase(&before, &after, outcome))
+            .sum();
+        assert_eq!(counted, 1, "{} counted as another outcome too", counter);
+    }
+
+    // Every attempt lands in one latency bucket
+    let stats = stats(&client).await;
+    let buckets = stats["latency"]["buckets"].as_array().unwrap();
+    assert_eq!(
+        buckets.len(),
+        stats["latency"]["bounds_ms"].as_array().unwrap().len() + 1
+    );
+    assert_eq!(
+        buckets.iter().filter_map(Value::as_u64).sum::<u64>(),
+        stats["attempts"].as_u64().unwrap()
+    );
+
+    // The statistics are for operators only
+    let response = client.get("/admin/deliveries").dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+}
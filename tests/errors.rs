@@ -0,0 +1,253 @@
+//! Bodies of error responses, which only carry details of the error when
+//! explicitly enabled.
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use base64::URL_SAFE_NO_PAD;
+use common::{Recorder, CONTINUATION};
+use rocket::{
+    http::{Accept, ContentType, Header, Status},
+    local::asynchronous::Client,
+};
+use serde_json::{json, Value};
+
+const TOKEN: &str = "secret-session-token";
+
+// Irma server answering every request with an empty body, which fails to
+// parse with an error mentioning the url of the request
+async fn broken_irma_server() -> String {
+    let (url, _) = Recorder::spawn().await;
+    url
+}
+
+async fn start(client: &Client) -> (Status, String) {
+    let response = client
+        .post("/start_authentication")
+        .header(ContentType::JSON)
+        .body(json!({ "attributes": ["email"], "continuation": CONTINUATION }).to_string())
+        .dispatch()
+        .await;
+    (
+        response.status(),
+        response.into_string().await.unwrap_or_default(),
+    )
+}
+
+async fn finalize(client: &Client) -> (Status, String) {
+    let path = format!(
+        "/decorated_continue/{}/{}?token={}",
+        base64::encode_config(r#"["email"]"#, URL_SAFE_NO_PAD),
+        base64::encode_config(CONTINUATION, URL_SAFE_NO_PAD),
+        TOKEN
+    );
+    let response = client.get(path).dispatch().await;
+    (
+        response.status(),
+        response.into_string().await.unwrap_or_default(),
+    )
+}
+
+fn assert_no_details(body: &str, irma_url: &str) {
+    for detail in [irma_url, "127.0.0.1", "http", TOKEN] {
+        assert!(!body.contains(detail), "{} found in {}", detail, body);
+    }
+    let envelope: Value = serde_json::from_str(body).expect("Error body is not json");
+    assert_eq!(envelope["error"], "internal_server_error");
+}
+
+#[rocket::async_test]
+async fn start_errors_have_no_details() {
+    let irma_url = broken_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    let (status, body) = start(&client).await;
+
+    assert_eq!(status, Status::InternalServerError);
+    assert_no_details(&body, &irma_url);
+}
+
+#[rocket::async_test]
+async fn result_errors_have_no_details() {
+    let irma_url = broken_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    let (status, body) = finalize(&client).await;
+
+    assert_eq!(status, Status::InternalServerError);
+    assert_no_details(&body, &irma_url);
+}
+
+#[rocket::async_test]
+async fn details_are_shown_when_enabled() {
+    let irma_url = broken_irma_server().await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "debug_errors": true, "insecure_dev_mode": true }),
+    ))
+    .await;
+
+    let (status, body) = start(&client).await;
+
+    assert_eq!(status, Status::InternalServerError);
+    assert!(serde_json::from_str::<Value>(&body).is_err());
+    assert!(!body.is_empty());
+}
+
+#[rocket::async_test]
+async fn unreachable_irma_server_is_unavailable() {
+    // Nothing listens on the port, so connecting is refused
+    let irma_url = format!("http://127.0.0.1:{}", common::free_port());
+
+    for (overrides, retry_after) in [(json!({}), "5"), (json!({ "irma_retry_after": 30 }), "30")] {
+        let client = common::client(common::config(&irma_url, overrides)).await;
+        let response = client
+            .post("/start_authentication")
+            .header(ContentType::JSON)
+            .body(json!({ "attributes": ["email"], "continuation": CONTINUATION }).to_string())
+            .dispatch()
+            .await;
+
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+        assert_eq!(response.headers().get_one("Retry-After"), Some(retry_after));
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        let body: Value = response.into_json().await.expect("Error body is not json");
+        assert_eq!(body, json!({ "error": "irma_unavailable" }));
+    }
+}
+
+#[rocket::async_test]
+async fn unknown_route_is_json_not_found() {
+    let client = common::client(common::config("http://127.0.0.1:1", json!({}))).await;
+
+    let response = client
+        .get("/no/such/route")
+        .header(Header::new("X-Request-Id", "request-1"))
+        .dispatch()
+        .await;
+
+    assert_eq!(response.status(), Status::NotFound);
+    assert_eq!(response.content_type(), Some(ContentType::JSON));
+    let body: Value = response.into_json().await.unwrap();
+    assert_eq!(
+        body,
+        json!({ "error": "not_found", "request_id": "request-1" })
+    );
+}
+
+#[rocket::async_test]
+async fn route_errors_are_json() {
+    let client = common::client(common::config("http://127.0.0.1:1", json!({}))).await;
+
+    let response = client
+        .get("/decorated_continue/e30/e30")
+        .header(Header::new("X-Request-Id", "request-3"))
+        .dispatch()
+        .await;
+
+    assert_eq!(response.status(), Status::BadRequest);
+    assert_eq!(response.content_type(), Some(ContentType::JSON));
+    let body: Value = response.into_json().await.unwrap();
+    assert_eq!(
+        body,
+        json!({
+            "error": "bad_request",
+            "message": "The token query parameter identifying the irma session is required",
+            "request_id": "request-3",
+        })
+    );
+}
+
+#[rocket::async_test]
+async fn browsers_get_html_route_errors() {
+    let client = common::client(common::config("http://127.0.0.1:1", json!({}))).await;
+
+    let response = client
+        .get("/decorated_continue/e30/e30")
+        .header(Accept::HTML)
+        .dispatch()
+        .await;
+
+    assert_eq!(response.status(), Status::BadRequest);
+    assert_eq!(response.content_type(), Some(ContentType::HTML));
+    let body = response.into_string().await.unwrap();
+    assert!(body.contains("400 Bad Request"));
+    assert!(body.contains("The token query parameter identifying the irma session is required"));
+}
+
+#[rocket::async_test]
+async fn missing_field_is_json_unprocessable() {
+    let client = common::client(common::config("http://127.0.0.1:1", json!({}))).await;
+
+    let response = client
+        .post("/start_authentication")
+        .header(ContentType::JSON)
+        .body(json!({ "continuation": CONTINUATION }).to_string())
+        .dispatch()
+        .await;
+
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let body: Value = response.into_json().await.unwrap();
+    assert_eq!(body, json!({ "error": "unprocessable_entity" }));
+}
+
+#[rocket::async_test]
+async fn internal_error_is_json() {
+    let irma_url = broken_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    let response = client
+        .post("/start_authentication")
+        .header(ContentType::JSON)
+        .header(Header::new("X-Request-Id", "request-2"))
+        .body(json!({ "attributes": ["email"], "continuation": CONTINUATION }).to_string())
+        .dispatch()
+        .await;
+
+    assert_eq!(response.status(), Status::InternalServerError);
+    let body: Value = response.into_json().await.unwrap();
+    assert_eq!(
+        body,
+        json!({ "error": "internal_server_error", "request_id": "request-2" })
+    );
+}
+
+#[rocket::async_test]
+async fn browsers_get_html_errors() {
+    let client = common::client(common::config("http://127.0.0.1:1", json!({}))).await;
+
+    let response = client
+        .get("/no/such/route")
+        .header(Accept::HTML)
+        .dispatch()
+        .await;
+
+    assert_eq!(response.status(), Status::NotFound);
+    assert_eq!(response.content_type(), Some(ContentType::HTML));
+    assert!(response
+        .into_string()
+        .await
+        .unwrap()
+        .contains("404 Not Found"));
+}
+
+#[rocket::async_test]
+async fn slow_irma_server_exceeds_deadline() {
+    let (irma_url, irma) = Recorder::spawn_slow(Duration::from_secs(5)).await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "irma_request_timeout": 1 }),
+    ))
+    .await;
+
+    let started = Instant::now();
+    let (status, body) = start(&client).await;
+
+    assert_eq!(status, Status::GatewayTimeout);
+    assert!(started.elapsed() < Duration::from_secs(4));
+    let body: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body, json!({ "error": "gateway_timeout" }));
+    // The irma server was asked, but did not answer in time
+    assert_eq!(irma.requests().len(), 1);
+}
@@ -0,0 +1,809 @@
+//! End-to-end tests of the in-band flow against the mock irma server: start a
+//! session, follow the client url to the irma ui, and finalize the session
+//! through the continuation the irma app returns to.
+
+#![cfg(feature = "mock-irma")]
+
+mod common;
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::URL_SAFE_NO_PAD;
+use common::{Recorder, CONTINUATION};
+use rocket::{
+    http::{ContentType, Header, Status},
+    local::asynchronous::Client,
+};
+use serde_json::{json, Value};
+
+// Start an in-band session for the given attributes and follow the client
+// url to the irma ui, returning the continuation the irma app opens once the
+// attributes are disclosed
+async fn start_in_band(client: &Client, attributes: &[&str]) -> String {
+    start_in_band_with(
+        client,
+        json!({ "attributes": attributes, "continuation": CONTINUATION }),
+    )
+    .await
+}
+
+// Like start_in_band, for a start request with other fields
+async fn start_in_band_with(client: &Client, request: Value) -> String {
+    let response = client
+        .post("/start_authentication")
+        .header(ContentType::JSON)
+        .body(request.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let started: Value = response.into_json().await.expect("Invalid start response");
+    let client_url = started["client_url"].as_str().expect("Missing client_url");
+
+    let (ui_path, continuation) = common::split_client_url(client_url);
+    let response = client.get(ui_path).dispatch().await;
+    assert_eq!(response.status(), Status::SeeOther);
+    let ui_url = response
+        .headers()
+        .get_one("Location")
+        .expect("No redirect to the irma ui");
+    assert!(ui_url.starts_with(common::UI_IRMA_URL));
+    assert!(common::query_param(ui_url, "token").is_some());
+
+    continuation.expect("Missing continuation")
+}
+
+// Open the continuation of an in-band session like the irma app does,
+// returning the status of the response and where it redirects to
+async fn finalize(client: &Client, continuation: &str) -> (Status, Option<String>) {
+    let response = client
+        .get(common::local_path(continuation))
+        .dispatch()
+        .await;
+    (
+        response.status(),
+        response.headers().get_one("Location").map(str::to_string),
+    )
+}
+
+async fn cancel(irma_url: &str, continuation: &str) {
+    let token = common::query_param(continuation, "token").expect("Missing session token");
+    reqwest::Client::new()
+        .delete(format!("{}/session/{}", irma_url, token))
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .expect("Could not cancel session");
+}
+
+#[rocket::async_test]
+async fn completed_session_redirects_with_result() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    let continuation = start_in_band(&client, &["email", "city"]).await;
+    let (status, location) = finalize(&client, &continuation).await;
+
+    assert_eq!(status, Status::SeeOther);
+    let location = location.expect("No redirect to the continuation");
+    assert!(location.starts_with(CONTINUATION));
+    let result = common::query_param(&location, "result").expect("Missing result");
+    let attributes = common::result_attributes(&result);
+    assert_eq!(attributes.len(), 2);
+    assert_eq!(attributes["email"], "mock value");
+    assert_eq!(attributes["city"], "mock value");
+}
+
+#[rocket::async_test]
+async fn nonce_is_echoed_in_result() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    let continuation = start_in_band_with(
+        &client,
+        json!({
+            "attributes": ["email"],
+            "continuation": CONTINUATION,
+            "nonce": "n-0S6_WzA2Mj/+?&=",
+        }),
+    )
+    .await;
+    let (_, location) = finalize(&client, &continuation).await;
+
+    let result = common::query_param(&location.unwrap(), "result").unwrap();
+    let claims = common::result_claims(&result);
+    assert_eq!(claims.claim("nonce"), Some(&json!("n-0S6_WzA2Mj/+?&=")));
+}
+
+#[rocket::async_test]
+async fn result_has_no_nonce_unless_supplied() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    let continuation = start_in_band(&client, &["email"]).await;
+    let (_, location) = finalize(&client, &continuation).await;
+
+    let result = common::query_param(&location.unwrap(), "result").unwrap();
+    assert_eq!(common::result_claims(&result).claim("nonce"), None);
+}
+
+#[rocket::async_test]
+async fn presence_only_session_succeeds_without_attributes() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "presence_only_attribute": "pbdf.sidn-pbdf.mobilenumber.mobilenumber" }),
+    ))
+    .await;
+
+    let continuation = start_in_band(&client, &[]).await;
+    let (status, location) = finalize(&client, &continuation).await;
+
+    assert_eq!(status, Status::SeeOther);
+    let result = common::query_param(&location.unwrap(), "result").unwrap();
+    let claims = common::result_claims(&result);
+    assert_eq!(claims.claim("status"), Some(&json!("succes")));
+    // An empty map rather than no attributes at all
+    assert_eq!(claims.claim("attributes"), Some(&json!({})));
+}
+
+#[rocket::async_test]
+async fn result_validity_is_configurable() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "result_validity": 60, "result_not_before": true }),
+    ))
+    .await;
+
+    let continuation = start_in_band(&client, &["email"]).await;
+    let (_, location) = finalize(&client, &continuation).await;
+    let result = common::query_param(&location.unwrap(), "result").unwrap();
+    let claims = common::result_claims(&result);
+
+    let issued_at = claims.issued_at().expect("Missing iat");
+    let expires_at = claims.expires_at().expect("Missing exp");
+    assert_eq!(
+        expires_at.duration_since(issued_at).unwrap(),
+        Duration::from_secs(60)
+    );
+    assert_eq!(claims.not_before(), Some(issued_at));
+}
+
+#[rocket::async_test]
+async fn result_has_configured_issuer_and_audience() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "jwt_issuer": "auth-irma", "jwt_audience": "verder-helpen-core" }),
+    ))
+    .await;
+
+    let continuation = start_in_band(&client, &["email"]).await;
+    let (_, location) = finalize(&client, &continuation).await;
+    let result = common::query_param(&location.unwrap(), "result").unwrap();
+    let claims = common::result_claims(&result);
+
+    assert_eq!(claims.issuer(), Some("auth-irma"));
+    assert_eq!(claims.audience(), Some(vec!["verder-helpen-core"]));
+}
+
+#[rocket::async_test]
+async fn result_headers_carry_configured_key_ids() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "signing_key_id": "auth-irma-2024", "encryption_key_id": "core-2024" }),
+    ))
+    .await;
+
+    let continuation = start_in_band(&client, &["email"]).await;
+    let (_, location) = finalize(&client, &continuation).await;
+    let result = common::query_param(&location.unwrap(), "result").unwrap();
+
+    assert_eq!(common::header(&result)["kid"], "core-2024");
+    let signed = common::decrypt_result(&result);
+    assert_eq!(common::header(&signed)["kid"], "auth-irma-2024");
+}
+
+#[rocket::async_test]
+async fn tampered_result_does_not_decrypt() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    let continuation = start_in_band(&client, &["email"]).await;
+    let (_, location) = finalize(&client, &continuation).await;
+    let result = common::query_param(&location.unwrap(), "result").unwrap();
+
+    let mut parts: Vec<String> = result.split('.').map(str::to_string).collect();
+    let ciphertext = &mut parts[3];
+    let flipped = if ciphertext.starts_with('A') {
+        "B"
+    } else {
+        "A"
+    };
+    ciphertext.replace_range(..1, flipped);
+    assert!(common::try_result_attributes(&parts.join(".")).is_err());
+}
+
+#[rocket::async_test]
+async fn cancelled_session_yields_no_result() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    let continuation = start_in_band(&client, &["email"]).await;
+    cancel(&irma_url, &continuation).await;
+    let (status, location) = finalize(&client, &continuation).await;
+
+    assert_eq!(status, Status::InternalServerError);
+    assert_eq!(location, None);
+}
+
+#[rocket::async_test]
+async fn failure_is_audited() {
+    let irma_url = common::mock_irma_server().await;
+    let audit_log = common::audit_log_path();
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "audit_log": audit_log.to_str().unwrap() }),
+    ))
+    .await;
+
+    let continuation = start_in_band(&client, &["email"]).await;
+    cancel(&irma_url, &continuation).await;
+    finalize(&client, &continuation).await;
+
+    let lines = common::audit_lines(&audit_log, 3).await;
+    assert_eq!(lines[0]["event"], "started");
+    assert_eq!(lines[0]["flow"], "in_band");
+    assert_eq!(lines[1]["event"], "qr_shown");
+    assert_eq!(lines[1]["session"], lines[0]["session"]);
+    assert_eq!(lines[2]["event"], "failed");
+    assert_eq!(lines[2]["reason"], "cancelled");
+    assert_eq!(lines[2]["session"], lines[0]["session"]);
+
+    // Sessions are identified by a prefix of their token only
+    let token = common::query_param(&continuation, "token").unwrap();
+    let log = std::fs::read_to_string(&audit_log).unwrap();
+    assert!(!log.contains(&token));
+    std::fs::remove_file(&audit_log).unwrap();
+}
+
+#[rocket::async_test]
+async fn completion_event_is_posted_after_redirect() {
+    let irma_url = common::mock_irma_server().await;
+    let (webhook_url, webhook) = Recorder::spawn().await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({
+            "event_webhook_url": format!("{}/events", webhook_url),
+            "requestor_keys": { "tenant-a": { "type": "RSA", "key": common::PUBLIC_KEY } },
+        }),
+    ))
+    .await;
+
+    let continuation = start_in_band_with(
+        &client,
+        json!({
+            "attributes": ["email"],
+            "continuation": CONTINUATION,
+            "requestor_key_id": "tenant-a",
+        }),
+    )
+    .await;
+    let (_, location) = finalize(&client, &continuation).await;
+
+    let result = common::query_param(&location.unwrap(), "result").unwrap();
+    let events = webhook.wait_for(1).await;
+    assert_eq!(events[0].uri, "/events");
+    assert!(!events[0].body.contains("mock value"));
+    let event: Value = serde_json::from_str(&events[0].body).unwrap();
+    assert_eq!(event["requestor"], "tenant-a");
+    assert_eq!(event["status"], "success");
+    assert_eq!(event.get("reason"), None);
+    assert!(event["timestamp"].as_u64().is_some());
+    assert_eq!(
+        event["correlation_id"].as_str(),
+        common::result_claims(&result).jwt_id()
+    );
+}
+
+#[rocket::async_test]
+async fn failure_event_is_posted_for_failed_session() {
+    let irma_url = common::mock_irma_server().await;
+    let (webhook_url, webhook) = Recorder::spawn().await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "event_webhook_url": format!("{}/events", webhook_url) }),
+    ))
+    .await;
+
+    let continuation = start_in_band(&client, &["email"]).await;
+    cancel(&irma_url, &continuation).await;
+    let (status, _) = finalize(&client, &continuation).await;
+
+    assert_eq!(status, Status::InternalServerError);
+    let events = webhook.wait_for(1).await;
+    let event: Value = serde_json::from_str(&events[0].body).unwrap();
+    assert_eq!(event["requestor"], Value::Null);
+    assert_eq!(event["status"], "failure");
+    assert_eq!(event["reason"], "cancelled");
+    assert_eq!(event["correlation_id"], Value::Null);
+}
+
+#[rocket::async_test]
+async fn cancelled_session_is_reported_when_enabled() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "report_failures": true }),
+    ))
+    .await;
+
+    let continuation = start_in_band(&client, &["email"]).await;
+    cancel(&irma_url, &continuation).await;
+    let (status, location) = finalize(&client, &continuation).await;
+
+    assert_eq!(status, Status::SeeOther);
+    let location = location.expect("No redirect to the continuation");
+    assert!(location.starts_with(CONTINUATION));
+    assert_eq!(
+        common::query_param(&location, "error").as_deref(),
+        Some("cancelled")
+    );
+    assert_eq!(common::query_param(&location, "result"), None);
+}
+
+#[rocket::async_test]
+async fn tampered_attributes_are_refused() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    let continuation = start_in_band(&client, &["email"]).await;
+    // Claim the session disclosed the city instead of the email address
+    let requested = base64::encode_config(r#"["email"]"#, URL_SAFE_NO_PAD);
+    let tampered = base64::encode_config(r#"["city"]"#, URL_SAFE_NO_PAD);
+    assert!(continuation.contains(&requested));
+    let continuation = continuation.replacen(&requested, &tampered, 1);
+    let (status, location) = finalize(&client, &continuation).await;
+
+    assert_eq!(status, Status::InternalServerError);
+    assert_eq!(location, None);
+}
+
+#[rocket::async_test]
+async fn tampered_attributes_are_reported_when_enabled() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "report_failures": true }),
+    ))
+    .await;
+
+    let continuation = start_in_band(&client, &["email"]).await;
+    let requested = base64::encode_config(r#"["email"]"#, URL_SAFE_NO_PAD);
+    let tampered = base64::encode_config(r#"["city"]"#, URL_SAFE_NO_PAD);
+    let continuation = continuation.replacen(&requested, &tampered, 1);
+    let (status, location) = finalize(&client, &continuation).await;
+
+    assert_eq!(status, Status::SeeOther);
+    let location = location.unwrap();
+    assert_eq!(
+        common::query_param(&location, "error").as_deref(),
+        Some("invalid_proof")
+    );
+    // The reason is all that is reported, no values of any attribute
+    assert!(!location.contains("mock"));
+}
+
+#[rocket::async_test]
+async fn tampered_token_is_refused() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    let continuation = start_in_band(&client, &["email"]).await;
+    let token = common::query_param(&continuation, "token").unwrap();
+    let continuation = continuation.replace(&token, "tampered");
+    let (status, location) = finalize(&client, &continuation).await;
+
+    assert_eq!(status, Status::Gone);
+    assert_eq!(location, None);
+}
+
+#[rocket::async_test]
+async fn duplicate_attributes_are_deduplicated() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "duplicate_attributes": "deduplicate" }),
+    ))
+    .await;
+
+    let continuation = start_in_band(&client, &["email", "city", "email"]).await;
+    // The continuation lists each attribute once, in request order, so the
+    // disclosure maps onto it
+    let deduplicated = base64::encode_config(r#"["email","city"]"#, URL_SAFE_NO_PAD);
+    assert!(continuation.contains(&format!("/decorated_continue/{}/", deduplicated)));
+    let (status, location) = finalize(&client, &continuation).await;
+
+    assert_eq!(status, Status::SeeOther);
+    let result = common::query_param(&location.unwrap(), "result").unwrap();
+    let attributes = common::result_attributes(&result);
+    assert_eq!(attributes.len(), 2);
+    assert_eq!(attributes["email"], "mock value");
+    assert_eq!(attributes["city"], "mock value");
+}
+
+#[rocket::async_test]
+async fn duplicate_attributes_are_rejected() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "duplicate_attributes": "reject" }),
+    ))
+    .await;
+
+    let response = client
+        .post("/start_authentication")
+        .header(ContentType::JSON)
+        .body(
+            json!({
+                "attributes": ["email", "city", "email", "city", "fullname"],
+                "continuation": CONTINUATION,
+            })
+            .to_string(),
+        )
+        .dispatch()
+        .await;
+
+    assert_eq!(response.status(), Status::BadRequest);
+    assert_eq!(
+        common::error_message(&response.into_string().await.unwrap()),
+        "Duplicate attributes email, city"
+    );
+}
+
+fn binding_config(irma_url: &str) -> verder_helpen_auth_irma::config::Config {
+    common::config(
+        irma_url,
+        json!({ "session_binding_secret": "secret binding browsers to their sessions" }),
+    )
+}
+
+#[rocket::async_test]
+async fn bound_browser_finalizes_session() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(binding_config(&irma_url)).await;
+
+    // Opening the client url binds the browser of the client to the session
+    let continuation = start_in_band(&client, &["email"]).await;
+    let (status, location) = finalize(&client, &continuation).await;
+
+    assert_eq!(status, Status::SeeOther);
+    assert!(common::query_param(&location.unwrap(), "result").is_some());
+}
+
+#[rocket::async_test]
+async fn unbound_browser_is_refused() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(binding_config(&irma_url)).await;
+    let other_browser = common::client(binding_config(&irma_url)).await;
+
+    let continuation = start_in_band(&client, &["email"]).await;
+    let (status, location) = finalize(&other_browser, &continuation).await;
+
+    assert_eq!(status, Status::Forbidden);
+    assert_eq!(location, None);
+}
+
+#[rocket::async_test]
+async fn browser_bound_to_other_session_is_refused() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(binding_config(&irma_url)).await;
+
+    let first = start_in_band(&client, &["email"]).await;
+    // Binds the browser to the second session instead
+    let second = start_in_band(&client, &["email"]).await;
+
+    let (status, _) = finalize(&client, &first).await;
+    assert_eq!(status, Status::Forbidden);
+    let (status, _) = finalize(&client, &second).await;
+    assert_eq!(status, Status::SeeOther);
+}
+
+// Finalize an in-band session of a client redirecting with result references,
+// returning the reference
+async fn result_ref(client: &Client) -> String {
+    let continuation = start_in_band(client, &["email"]).await;
+    let (status, location) = finalize(client, &continuation).await;
+    assert_eq!(status, Status::SeeOther);
+    let location = location.expect("No redirect to the continuation");
+    assert_eq!(common::query_param(&location, "result"), None);
+    common::query_param(&location, "result_ref").expect("Missing result_ref")
+}
+
+#[rocket::async_test]
+async fn result_is_fetched_once_by_reference() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "result_by_reference": true }),
+    ))
+    .await;
+    let result_ref = result_ref(&client).await;
+
+    let response = client
+        .get(format!("/result/{}", result_ref))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        response.content_type(),
+        Some(ContentType::new("application", "jwt"))
+    );
+    let result = response.into_string().await.unwrap();
+    assert_eq!(common::result_attributes(&result)["email"], "mock value");
+
+    let response = client
+        .get(format!("/result/{}", result_ref))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Gone);
+}
+
+#[rocket::async_test]
+async fn expired_result_reference_is_gone() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "result_by_reference": true, "result_reference_ttl": 1 }),
+    ))
+    .await;
+    let result_ref = result_ref(&client).await;
+
+    rocket::tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    let response = client
+        .get(format!("/result/{}", result_ref))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Gone);
+}
+
+#[rocket::async_test]
+async fn unknown_result_reference_is_gone() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "result_by_reference": true }),
+    ))
+    .await;
+    // A reference issued by another instance is unknown to this one
+    let other_client = common::client(common::config(
+        &irma_url,
+        json!({ "result_by_reference": true }),
+    ))
+    .await;
+    let result_ref = result_ref(&other_client).await;
+
+    for path in [
+        format!("/result/{}", result_ref),
+        "/result/unknown".to_string(),
+    ] {
+        let response = client.get(path).dispatch().await;
+        assert_eq!(response.status(), Status::Gone);
+    }
+}
+
+fn retention_config(irma_url: &str, period: u64) -> verder_helpen_auth_irma::config::Config {
+    common::config(
+        irma_url,
+        json!({ "result_retention": { "period": period, "api_key": "core-key" } }),
+    )
+}
+
+// Finalize an in-band session, returning the result and the path of its
+// session_url
+async fn retained_session(client: &Client) -> (String, String) {
+    let continuation = start_in_band(client, &["email"]).await;
+    let (_, location) = finalize(client, &continuation).await;
+    let result = common::query_param(&location.unwrap(), "result").unwrap();
+    let claims = common::result_claims(&result);
+    let session_url = claims
+        .claim("session_url")
+        .and_then(Value::as_str)
+        .expect("Missing session_url");
+    assert!(session_url.starts_with(&format!("{}/retained_result/", common::SERVER_URL)));
+    let path = common::local_path(session_url).to_string();
+    (result, path)
+}
+
+#[rocket::async_test]
+async fn retained_result_is_retrievable_from_session_url() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(retention_config(&irma_url, 60)).await;
+    let (result, path) = retained_session(&client).await;
+
+    // Unlike results fetched by reference, retained results can be fetched
+    // repeatedly
+    for _ in 0..2 {
+        let response = client
+            .get(path.clone())
+            .header(Header::new("Authorization", "Bearer core-key"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.content_type(),
+            Some(ContentType::new("application", "jwt"))
+        );
+        assert_eq!(response.into_string().await.unwrap(), result);
+    }
+}
+
+#[rocket::async_test]
+async fn retained_result_requires_the_core_key() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(retention_config(&irma_url, 60)).await;
+    let (_, path) = retained_session(&client).await;
+
+    let response = client.get(path.clone()).dispatch().await;
+    assert_eq!(response.status(), Status::Unauthorized);
+    for authorization in ["Bearer other-key", "core-key", "Basic core-key"] {
+        let response = client
+            .get(path.clone())
+            .header(Header::new("Authorization", authorization))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized, "{}", authorization);
+    }
+}
+
+#[rocket::async_test]
+async fn retained_result_expires() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(retention_config(&irma_url, 1)).await;
+    let (_, path) = retained_session(&client).await;
+
+    rocket::tokio::time::sleep(Duration::from_millis(1100)).await;
+
+    let response = client
+        .get(path)
+        .header(Header::new("Authorization", "Bearer core-key"))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn results_are_not_retained_unless_configured() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    let continuation = start_in_band(&client, &["email"]).await;
+    let (_, location) = finalize(&client, &continuation).await;
+    let result = common::query_param(&location.unwrap(), "result").unwrap();
+    assert_eq!(common::result_claims(&result).claim("session_url"), None);
+
+    // Without an api key to match, the route refuses everyone
+    let response = client
+        .get("/retained_result/unknown")
+        .header(Header::new("Authorization", "Bearer "))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+async fn result_is_added_to_single_page_application_route() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "result_in_fragment": true }),
+    ))
+    .await;
+    let continuation = format!("{}#/done?step=2", CONTINUATION);
+
+    let continuation = start_in_band_with(
+        &client,
+        json!({ "attributes": ["email"], "continuation": continuation }),
+    )
+    .await;
+    let (status, location) = finalize(&client, &continuation).await;
+
+    assert_eq!(status, Status::SeeOther);
+    let location = location.unwrap();
+    let route = location
+        .strip_prefix(&format!("{}#/done?step=2&result=", CONTINUATION))
+        .expect("Result not in the route query");
+    assert_eq!(common::result_attributes(route)["email"], "mock value");
+}
+
+#[rocket::async_test]
+async fn result_carries_moment_of_disclosure() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "include_auth_time": true }),
+    ))
+    .await;
+    let started_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    let continuation = start_in_band(&client, &["email"]).await;
+    let (_, location) = finalize(&client, &continuation).await;
+    let result = common::query_param(&location.unwrap(), "result").unwrap();
+    let claims = common::result_claims(&result);
+
+    let auth_time = claims
+        .claim("auth_time")
+        .and_then(Value::as_u64)
+        .expect("Missing auth_time");
+    let issued_at = claims
+        .issued_at()
+        .expect("Missing iat")
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    assert!(
+        started_at <= auth_time,
+        "auth_time before the session started"
+    );
+    assert!(
+        auth_time <= issued_at,
+        "auth_time after the result was issued"
+    );
+}
+
+#[rocket::async_test]
+async fn result_has_no_moment_of_disclosure_unless_configured() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    let continuation = start_in_band(&client, &["email"]).await;
+    let (_, location) = finalize(&client, &continuation).await;
+    let result = common::query_param(&location.unwrap(), "result").unwrap();
+
+    assert_eq!(common::result_claims(&result).claim("auth_time"), None);
+}
+
+#[rocket::async_test]
+async fn result_is_bound_to_normalized_continuation() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "bind_destination": true }),
+    ))
+    .await;
+
+    let continuation = start_in_band_with(
+        &client,
+        json!({
+            "attributes": ["email"],
+            "continuation": "HTTPS://Core.Example.com:443/continue",
+        }),
+    )
+    .await;
+    let (_, location) = finalize(&client, &continuation).await;
+    let location = location.unwrap();
+    assert!(location.starts_with(CONTINUATION));
+    let result = common::query_param(&location, "result").unwrap();
+
+    assert_eq!(
+        common::result_claims(&result).claim("destination"),
+        Some(&json!(CONTINUATION))
+    );
+}
+
+#[rocket::async_test]
+async fn result_is_not_bound_to_continuation_unless_configured() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    let continuation = start_in_band(&client, &["email"]).await;
+    let (_, location) = finalize(&client, &continuation).await;
+    let result = common::query_param(&location.unwrap(), "result").unwrap();
+
+    assert_eq!(common::result_claims(&result).claim("destination"), None);
+}
@@ -0,0 +1,78 @@
+//! Logging of calls to the irma server, through a logger capturing the records.
+//! The logger is global, so this binary holds a single test.
+
+mod common;
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::{LevelFilter, Log, Metadata, Record};
+use rocket::{get, routes, serde::json::Json, tokio};
+use verder_helpen_auth_irma::irma::{Discovery, IrmaServer, SessionStatus, SessionToken};
+
+const VARIABLE: &str = "IRMA_LOGGING_TEST_SERVER_URL";
+
+// Logger keeping the messages of records about irma server calls
+struct Capture(Arc<Mutex<Vec<String>>>);
+
+impl Log for Capture {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if record.target() == "irma" {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[get("/session/<_token>/status")]
+fn done_status(_token: String) -> Json<&'static str> {
+    Json("DONE")
+}
+
+#[rocket::async_test]
+async fn calls_are_logged_with_the_host_called() {
+    let logged = Arc::new(Mutex::new(vec![]));
+    log::set_boxed_logger(Box::new(Capture(logged.clone()))).unwrap();
+    log::set_max_level(LevelFilter::Debug);
+    let port = common::free_port();
+    common::launch(rocket::build().mount("/", routes![done_status]), port).await;
+    std::env::set_var(VARIABLE, format!("http://127.0.0.1:{}", port));
+    // Resolve the url on every call
+    let irma = IrmaServer::new("http://fallback.invalid").with_discovery(
+        Discovery::Env {
+            variable: VARIABLE.to_string(),
+        },
+        Duration::ZERO,
+    );
+    let token = SessionToken::new("token".to_string());
+
+    // Discovery moves on to another server while the call is in flight
+    let (status, ()) = tokio::join!(irma.status(&token), async {
+        std::env::set_var(VARIABLE, "http://moved.invalid");
+    });
+    assert!(matches!(status, Ok(SessionStatus::Done)));
+    let status = irma.status(&token).await;
+    assert!(status.is_err());
+
+    let logged = logged.lock().unwrap().clone();
+    assert_eq!(logged.len(), 2, "{:?}", logged);
+    assert!(
+        logged[0].starts_with("status on 127.0.0.1 succeeded in "),
+        "{}",
+        logged[0]
+    );
+    assert!(
+        logged[1].starts_with("status on moved.invalid failed in "),
+        "{}",
+        logged[1]
+    );
+    // Session tokens stay out of the log
+    assert!(!logged.iter().any(|message| message.contains("token")));
+}
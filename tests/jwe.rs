@@ -0,0 +1,409 @@
+//! Results signed and encrypted by the jwe module: their claims and headers,
+//! and their compatibility with those of `verder_helpen_jwt`, which produced
+//! results before the module replaced it.
+
+mod common;
+
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+
+use josekit::{
+    jwe::{JweHeader, RSA_OAEP},
+    jws::{JwsHeader, RS256},
+    jwt::{self, JwtPayload},
+    JoseError,
+};
+use serde_json::{json, Value};
+use verder_helpen_auth_irma::{
+    config::StatusSpelling,
+    jwe::{self, ResultClaims},
+};
+use verder_helpen_proto::{AuthResult, AuthStatus};
+
+fn auth_result() -> AuthResult {
+    AuthResult {
+        status: AuthStatus::Success,
+        attributes: Some(HashMap::from([
+            ("email".to_string(), "user@example.com".to_string()),
+            ("city".to_string(), "Nijmegen".to_string()),
+        ])),
+        session_url: None,
+    }
+}
+
+fn claims() -> ResultClaims {
+    ResultClaims {
+        validity: Duration::from_secs(300),
+        not_before: false,
+        issuer: common::SERVER_URL.to_string(),
+        audience: None,
+        auth_time: None,
+        disclosed_keys: None,
+        status_spelling: StatusSpelling::Legacy,
+        jti: "result-id".to_string(),
+        credentials: None,
+        nonce: None,
+        destination: None,
+    }
+}
+
+// Result produced by the shared crate
+fn shared_result() -> String {
+    let signer = RS256.signer_from_pem(common::PRIVATE_KEY).unwrap();
+    let encrypter = RSA_OAEP.encrypter_from_pem(common::PUBLIC_KEY).unwrap();
+    verder_helpen_jwt::sign_and_encrypt_auth_result(&auth_result(), &signer, &encrypter)
+        .expect("Could not sign result with the shared crate")
+}
+
+// Result produced by the jwe module
+fn sign(auth_result: &AuthResult, claims: &ResultClaims, compress: bool) -> String {
+    let signer = RS256.signer_from_pem(common::PRIVATE_KEY).unwrap();
+    let encrypter = RSA_OAEP.encrypter_from_pem(common::PUBLIC_KEY).unwrap();
+    jwe::sign_and_encrypt_auth_result(
+        auth_result,
+        claims,
+        &signer,
+        None,
+        &encrypter,
+        None,
+        compress,
+    )
+    .expect("Could not sign result")
+}
+
+fn local_result() -> String {
+    sign(&auth_result(), &claims(), false)
+}
+
+fn decrypt(token: &str) -> String {
+    let decrypter = RSA_OAEP.decrypter_from_pem(common::PRIVATE_KEY).unwrap();
+    jwe::decrypt_result(token, &decrypter).expect("Undecryptable result")
+}
+
+fn verify(token: &str) -> JwtPayload {
+    let verifier = RS256.verifier_from_pem(common::PUBLIC_KEY).unwrap();
+    jwe::verify_result(&decrypt(token), &verifier).expect("Invalid signature")
+}
+
+#[test]
+fn shared_results_decrypt_and_verify() {
+    let decrypter = RSA_OAEP.decrypter_from_pem(common::PRIVATE_KEY).unwrap();
+    let verifier = RS256.verifier_from_pem(common::PUBLIC_KEY).unwrap();
+
+    let attributes = jwe::decrypt_and_verify_attributes(&shared_result(), &decrypter, &verifier)
+        .expect("Shared result does not verify");
+
+    assert_eq!(Some(attributes), auth_result().attributes);
+}
+
+#[test]
+fn local_results_decrypt_and_verify() {
+    let decrypter = RSA_OAEP.decrypter_from_pem(common::PRIVATE_KEY).unwrap();
+    let verifier = RS256.verifier_from_pem(common::PUBLIC_KEY).unwrap();
+
+    let attributes = jwe::decrypt_and_verify_attributes(&local_result(), &decrypter, &verifier)
+        .expect("Result does not verify");
+
+    assert_eq!(Some(attributes), auth_result().attributes);
+}
+
+#[test]
+fn results_have_shared_headers() {
+    let shared = shared_result();
+    let local = local_result();
+
+    assert_eq!(common::header(&local), common::header(&shared));
+    assert_eq!(
+        common::header(&decrypt(&local)),
+        common::header(&decrypt(&shared))
+    );
+}
+
+#[test]
+fn results_have_shared_claims() {
+    let shared = verify(&shared_result());
+    let local = verify(&local_result());
+
+    // Every claim of the shared crate is kept with the same value, apart from
+    // the times, which differ between the two tokens
+    for (name, value) in shared.claims_set() {
+        if !["iat", "nbf", "exp"].contains(&name.as_str()) {
+            assert_eq!(local.claim(name), Some(value), "Claim {} differs", name);
+        }
+    }
+    assert_eq!(
+        local.claim("status").and_then(Value::as_str),
+        Some("succes")
+    );
+    // The expiry is added on top of the claims of the shared crate
+    assert!(local.expires_at().is_some());
+}
+
+#[test]
+fn result_expires_after_validity() {
+    let claims = ResultClaims {
+        validity: Duration::from_secs(120),
+        ..claims()
+    };
+
+    let payload = verify(&sign(&auth_result(), &claims, false));
+
+    let issued_at = payload.issued_at().expect("Missing iat");
+    let expires_at = payload.expires_at().expect("Missing exp");
+    assert_eq!(
+        expires_at.duration_since(issued_at).unwrap(),
+        Duration::from_secs(120)
+    );
+    let age = SystemTime::now().duration_since(issued_at).unwrap();
+    assert!(age < Duration::from_secs(10));
+    assert_eq!(payload.not_before(), None);
+}
+
+#[test]
+fn result_is_not_valid_before_issuing() {
+    let claims = ResultClaims {
+        not_before: true,
+        ..claims()
+    };
+
+    let payload = verify(&sign(&auth_result(), &claims, false));
+
+    assert_eq!(payload.not_before(), payload.issued_at());
+    assert!(payload.not_before().is_some());
+}
+
+#[test]
+fn issuer_and_audience_survive_nesting() {
+    let claims = ResultClaims {
+        issuer: "auth-irma".to_string(),
+        audience: Some("verder-helpen-core".to_string()),
+        ..claims()
+    };
+
+    let payload = verify(&sign(&auth_result(), &claims, false));
+
+    assert_eq!(payload.issuer(), Some("auth-irma"));
+    assert_eq!(payload.audience(), Some(vec!["verder-helpen-core"]));
+}
+
+#[test]
+fn result_headers_carry_key_ids() {
+    let signer = RS256.signer_from_pem(common::PRIVATE_KEY).unwrap();
+    let encrypter = RSA_OAEP.encrypter_from_pem(common::PUBLIC_KEY).unwrap();
+
+    let token = jwe::sign_and_encrypt_auth_result(
+        &auth_result(),
+        &claims(),
+        &signer,
+        Some("auth-irma-2024"),
+        &encrypter,
+        Some("core-2024"),
+        false,
+    )
+    .unwrap();
+
+    assert_eq!(common::header(&token)["kid"], "core-2024");
+    assert_eq!(common::header(&decrypt(&token))["kid"], "auth-irma-2024");
+}
+
+#[test]
+fn result_headers_lack_unknown_key_ids() {
+    let token = local_result();
+
+    assert_eq!(common::header(&token).get("kid"), None);
+    assert_eq!(common::header(&decrypt(&token)).get("kid"), None);
+}
+
+#[test]
+fn irma_params_header_carries_key_id() {
+    let signer = RS256.signer_from_pem(common::PRIVATE_KEY).unwrap();
+
+    let token = jwe::sign_irma_params(
+        Some(common::CONTINUATION),
+        r#"{"u":"https://irma.example.com/irma/session/token","irmaqr":"disclosing"}"#,
+        Duration::from_secs(60),
+        &signer,
+        Some("auth-irma-2024"),
+    )
+    .unwrap();
+
+    let header = common::header(&token);
+    assert_eq!(header["kid"], "auth-irma-2024");
+    assert_eq!(header["typ"], "JWT");
+}
+
+// Encrypt a payload to the test key like the jwe module does
+fn encrypt(payload: &JwtPayload) -> String {
+    let encrypter = RSA_OAEP.encrypter_from_pem(common::PUBLIC_KEY).unwrap();
+    jwt::encode_with_encrypter(payload, &JweHeader::new(), &encrypter).unwrap()
+}
+
+// Signed payload of a result with the given status, issued at the given time
+fn signed_payload(status: &str, issued_at: SystemTime) -> String {
+    let signer = RS256.signer_from_pem(common::PRIVATE_KEY).unwrap();
+    let mut payload = JwtPayload::new();
+    payload.set_issued_at(&issued_at);
+    payload.set_expires_at(&(issued_at + Duration::from_secs(300)));
+    payload.set_claim("status", Some(json!(status))).unwrap();
+    payload
+        .set_claim("attributes", Some(json!({ "email": "user@example.com" })))
+        .unwrap();
+    jwt::encode_with_signer(&payload, &JwsHeader::new(), &signer).unwrap()
+}
+
+fn nested(njwt: String) -> String {
+    let mut payload = JwtPayload::new();
+    payload.set_claim("njwt", Some(json!(njwt))).unwrap();
+    encrypt(&payload)
+}
+
+fn decrypt_and_verify(token: &str) -> Result<HashMap<String, String>, JoseError> {
+    let decrypter = RSA_OAEP.decrypter_from_pem(common::PRIVATE_KEY).unwrap();
+    let verifier = RS256.verifier_from_pem(common::PUBLIC_KEY).unwrap();
+    jwe::decrypt_and_verify_attributes(token, &decrypter, &verifier)
+}
+
+#[test]
+fn hand_made_result_verifies() {
+    let attributes =
+        decrypt_and_verify(&nested(signed_payload("succes", SystemTime::now()))).unwrap();
+
+    assert_eq!(attributes["email"], "user@example.com");
+}
+
+#[test]
+fn result_for_other_key_does_not_decrypt() {
+    let other_key = RSA_OAEP.generate_key_pair(2048).unwrap();
+    let decrypter = RSA_OAEP
+        .decrypter_from_der(other_key.to_der_private_key())
+        .unwrap();
+    let verifier = RS256.verifier_from_pem(common::PUBLIC_KEY).unwrap();
+
+    let result = jwe::decrypt_and_verify_attributes(&local_result(), &decrypter, &verifier);
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn result_signed_by_other_key_does_not_verify() {
+    let other_key = RS256.generate_key_pair(2048).unwrap();
+    let decrypter = RSA_OAEP.decrypter_from_pem(common::PRIVATE_KEY).unwrap();
+    let verifier = RS256
+        .verifier_from_der(other_key.to_der_public_key())
+        .unwrap();
+
+    let result = jwe::decrypt_and_verify_attributes(&local_result(), &decrypter, &verifier);
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn expired_result_does_not_verify() {
+    let issued_at = SystemTime::now() - Duration::from_secs(600);
+
+    let result = decrypt_and_verify(&nested(signed_payload("succes", issued_at)));
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn result_without_nesting_does_not_verify() {
+    // The signed payload encrypted as is, rather than in an njwt claim
+    let mut payload = JwtPayload::new();
+    payload.set_claim("status", Some(json!("succes"))).unwrap();
+    payload
+        .set_claim("attributes", Some(json!({ "email": "user@example.com" })))
+        .unwrap();
+
+    let result = decrypt_and_verify(&encrypt(&payload));
+
+    assert!(result.is_err());
+}
+
+fn large_auth_result() -> AuthResult {
+    AuthResult {
+        status: AuthStatus::Success,
+        attributes: Some(
+            (0..50)
+                .map(|i| {
+                    (
+                        format!("attribute-{}", i),
+                        format!("value of attribute {}", i),
+                    )
+                })
+                .collect(),
+        ),
+        session_url: None,
+    }
+}
+
+#[test]
+fn compression_shrinks_large_results() {
+    let uncompressed = sign(&large_auth_result(), &claims(), false);
+    let compressed = sign(&large_auth_result(), &claims(), true);
+
+    assert!(
+        compressed.len() < uncompressed.len(),
+        "Compressed result of {} bytes is not smaller than {} bytes",
+        compressed.len(),
+        uncompressed.len()
+    );
+    assert_eq!(common::header(&compressed)["zip"], "DEF");
+    assert_eq!(common::header(&uncompressed).get("zip"), None);
+}
+
+#[test]
+fn results_round_trip_with_and_without_compression() {
+    for compress in [false, true] {
+        let token = sign(&large_auth_result(), &claims(), compress);
+
+        let attributes = decrypt_and_verify(&token).unwrap();
+
+        assert_eq!(Some(attributes), large_auth_result().attributes);
+    }
+}
+
+#[test]
+fn status_spelling_is_configured() {
+    for (config, spelling) in [
+        (json!("legacy"), StatusSpelling::Legacy),
+        (json!("standard"), StatusSpelling::Standard),
+    ] {
+        assert_eq!(
+            serde_json::from_value::<StatusSpelling>(config).unwrap(),
+            spelling
+        );
+    }
+    assert_eq!(StatusSpelling::default(), StatusSpelling::Legacy);
+}
+
+#[test]
+fn results_use_configured_status_spelling() {
+    for (spelling, status) in [
+        (StatusSpelling::Legacy, "succes"),
+        (StatusSpelling::Standard, "success"),
+    ] {
+        let claims = ResultClaims {
+            status_spelling: spelling,
+            ..claims()
+        };
+
+        let token = sign(&auth_result(), &claims, false);
+
+        assert_eq!(verify(&token).claim("status"), Some(&json!(status)));
+        assert!(decrypt_and_verify(&token).is_ok());
+    }
+}
+
+#[test]
+fn results_with_either_status_spelling_verify() {
+    for status in ["succes", "success"] {
+        let token = nested(signed_payload(status, SystemTime::now()));
+
+        assert!(decrypt_and_verify(&token).is_ok(), "{} is refused", status);
+    }
+    let token = nested(signed_payload("failed", SystemTime::now()));
+    assert!(decrypt_and_verify(&token).is_err());
+}
@@ -0,0 +1,119 @@
+//! The public signing key as JWK Set at `/.well-known/jwks.json`.
+
+mod common;
+
+use josekit::{
+    jwk::Jwk,
+    jws::{JwsHeader, ES256, RS256},
+    jwt::{self, JwtPayload},
+};
+use rocket::http::{Header, Status};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+/// Private parameters of RSA and EC keys
+const PRIVATE_PARAMETERS: [&str; 7] = ["d", "p", "q", "dp", "dq", "qi", "oth"];
+
+// Keys published by a plugin with the given configuration overrides
+async fn published_keys(overrides: Value) -> Vec<Map<String, Value>> {
+    let client = common::client(common::config("http://127.0.0.1:1", overrides)).await;
+    let response = client.get("/.well-known/jwks.json").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let jwks: Value = response.into_json().await.expect("Invalid key set");
+    jwks["keys"]
+        .as_array()
+        .expect("Missing keys")
+        .iter()
+        .map(|key| key.as_object().expect("Invalid key").clone())
+        .collect()
+}
+
+fn assert_public(key: &Map<String, Value>) {
+    for parameter in PRIVATE_PARAMETERS {
+        assert!(
+            !key.contains_key(parameter),
+            "Private parameter {} published",
+            parameter
+        );
+    }
+}
+
+#[rocket::async_test]
+async fn rsa_key_is_published_without_private_parameters() {
+    let keys = published_keys(json!({ "signing_key_id": "sig-1" })).await;
+
+    assert_eq!(keys.len(), 1);
+    let key = &keys[0];
+    assert_public(key);
+    assert_eq!(key["kty"], "RSA");
+    assert_eq!(key["use"], "sig");
+    assert_eq!(key["alg"], "RS256");
+    assert_eq!(key["kid"], "sig-1");
+
+    // The published key verifies what the configured key signs
+    let verifier = RS256
+        .verifier_from_jwk(&Jwk::from_map(key.clone()).unwrap())
+        .unwrap();
+    let signer = RS256.signer_from_pem(common::PRIVATE_KEY).unwrap();
+    let token = jwt::encode_with_signer(&JwtPayload::new(), &JwsHeader::new(), &signer).unwrap();
+    assert!(jwt::decode_with_verifier(&token, &verifier).is_ok());
+}
+
+#[rocket::async_test]
+async fn ec_key_is_published_without_private_parameters() {
+    let private_key = ES256.generate_key_pair().unwrap().to_pem_private_key();
+    let keys = published_keys(json!({
+        "signing_privkey": { "type": "EC", "key": String::from_utf8(private_key).unwrap() },
+    }))
+    .await;
+
+    assert_eq!(keys.len(), 1);
+    let key = &keys[0];
+    assert_public(key);
+    assert_eq!(key["kty"], "EC");
+    assert_eq!(key["use"], "sig");
+    assert_eq!(key["alg"], "ES256");
+}
+
+#[rocket::async_test]
+async fn key_set_is_cacheable() {
+    let client = common::client(common::config("http://127.0.0.1:1", json!({}))).await;
+
+    let response = client.get("/.well-known/jwks.json").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        response.headers().get_one("Cache-Control"),
+        Some("public, max-age=300")
+    );
+    let etag = response
+        .headers()
+        .get_one("ETag")
+        .expect("Missing ETag")
+        .to_string();
+
+    let response = client
+        .get("/.well-known/jwks.json")
+        .header(Header::new("If-None-Match", etag.clone()))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::NotModified);
+    assert_eq!(response.headers().get_one("ETag"), Some(etag.as_str()));
+
+    let response = client
+        .get("/.well-known/jwks.json")
+        .header(Header::new("If-None-Match", "\"stale\""))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+async fn etag_is_digest_of_key_set() {
+    let client = common::client(common::config("http://127.0.0.1:1", json!({}))).await;
+
+    let response = client.get("/.well-known/jwks.json").dispatch().await;
+    let etag = response.headers().get_one("ETag").unwrap().to_string();
+    let body = response.into_string().await.unwrap();
+
+    assert_eq!(etag, format!("\"{:x}\"", Sha256::digest(&body)));
+}
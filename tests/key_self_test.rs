@@ -0,0 +1,115 @@
+//! Self-test of the configured keys when loading the configuration: keys that
+//! don't match their configured type or public key fail loading instead of
+//! the first result after a completed session.
+
+mod common;
+
+use base64::URL_SAFE_NO_PAD;
+use josekit::jws::{ES256, RS256};
+use rocket::{post, routes, serde::json::Json, State};
+use serde_json::{json, Value};
+
+// Private key, in DER, the signing service signs with
+struct ServiceKey(Vec<u8>);
+
+#[post("/", data = "<request>")]
+fn sign(key: &State<ServiceKey>, request: Json<Value>) -> Json<Value> {
+    let message = base64::decode_config(request["message"].as_str().unwrap(), URL_SAFE_NO_PAD)
+        .expect("Invalid message encoding");
+    let signature = RS256
+        .signer_from_der(&key.0)
+        .unwrap()
+        .sign(&message)
+        .unwrap();
+    Json(json!({ "signature": base64::encode_config(signature, URL_SAFE_NO_PAD) }))
+}
+
+// Launch a signing service signing with the given key, returning its url
+async fn signing_service(private_key_der: Vec<u8>) -> String {
+    let port = common::free_port();
+    let rocket = rocket::build()
+        .manage(ServiceKey(private_key_der))
+        .mount("/", routes![sign]);
+    common::launch(rocket, port).await;
+    format!("http://127.0.0.1:{port}")
+}
+
+// Signing key configuration delegating to the signing service at `url`,
+// publishing the test public key
+fn remote_signing_key(url: &str) -> Value {
+    let public_key = RS256
+        .key_pair_from_pem(common::PRIVATE_KEY)
+        .unwrap()
+        .to_jwk_public_key();
+    json!({
+        "remote": {
+            "url": url,
+            "algorithm": "RS256",
+            "public_key": Value::Object(public_key.as_ref().clone()),
+        }
+    })
+}
+
+fn insecure_overrides(signing_privkey: Value) -> Value {
+    json!({
+        "insecure_dev_mode": true,
+        "allow_insecure_urls": true,
+        "signing_privkey": signing_privkey,
+    })
+}
+
+#[rocket::async_test]
+async fn signing_service_with_configured_key_passes() {
+    let private_key = RS256.key_pair_from_pem(common::PRIVATE_KEY).unwrap();
+    let url = signing_service(private_key.to_der_private_key()).await;
+
+    let config = common::try_config(
+        "http://127.0.0.1:1",
+        insecure_overrides(remote_signing_key(&url)),
+    );
+
+    assert!(config.is_ok(), "{:?}", config.err());
+}
+
+#[rocket::async_test]
+async fn signing_service_with_other_key_fails_self_test() {
+    let other_key = RS256.generate_key_pair(2048).unwrap();
+    let url = signing_service(other_key.to_der_private_key()).await;
+
+    let error = common::try_config(
+        "http://127.0.0.1:1",
+        insecure_overrides(remote_signing_key(&url)),
+    )
+    .err()
+    .expect("Signatures of another key passed the self-test");
+
+    assert!(
+        error
+            .to_string()
+            .starts_with("Self-test of key signing_privkey failed"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn rsa_signing_key_of_other_type_fails() {
+    let ec_key = ES256.generate_key_pair().unwrap().to_pem_private_key();
+
+    let config = common::try_config(
+        "http://127.0.0.1:1",
+        json!({ "signing_privkey": { "type": "RSA", "key": String::from_utf8(ec_key).unwrap() } }),
+    );
+
+    assert!(config.is_err());
+}
+
+#[test]
+fn ec_encryption_key_of_other_type_fails() {
+    let config = common::try_config(
+        "http://127.0.0.1:1",
+        json!({ "encryption_pubkey": { "type": "EC", "key": common::PUBLIC_KEY } }),
+    );
+
+    assert!(config.is_err());
+}
@@ -0,0 +1,140 @@
+//! Maintenance mode, in which new sessions are refused while sessions in
+//! flight still complete.
+
+#![cfg(feature = "mock-irma")]
+
+mod common;
+
+use common::CONTINUATION;
+use rocket::{
+    http::{ContentType, Header, Status},
+    local::asynchronous::Client,
+};
+use serde_json::{json, Value};
+
+const ADMIN_KEY: &str = "admin-key";
+
+async fn set_maintenance(client: &Client, enabled: bool) {
+    let response = client
+        .post("/admin/maintenance")
+        .header(ContentType::JSON)
+        .header(Header::new(
+            "Authorization",
+            format!("Bearer {}", ADMIN_KEY),
+        ))
+        .body(json!({ "enabled": enabled }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let status: Value = response.into_json().await.unwrap();
+    assert_eq!(status, json!({ "enabled": enabled }));
+}
+
+// Status of a start request and the client url when it started
+async fn start(client: &Client) -> (Status, Option<String>) {
+    let response = client
+        .post("/start_authentication")
+        .header(ContentType::JSON)
+        .body(json!({ "attributes": ["email"], "continuation": CONTINUATION }).to_string())
+        .dispatch()
+        .await;
+    let status = response.status();
+    let started: Option<Value> = response.into_json().await;
+    let client_url = started.and_then(|started| Some(started["client_url"].as_str()?.to_string()));
+    (status, client_url)
+}
+
+async fn readiness(client: &Client) -> (Status, Value) {
+    let response = client.get("/readyz").dispatch().await;
+    (response.status(), response.into_json().await.unwrap())
+}
+
+#[rocket::async_test]
+async fn maintenance_refuses_new_sessions_only() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "admin_api_key": ADMIN_KEY }),
+    ))
+    .await;
+
+    let (_, in_flight) = start(&client).await;
+    let in_flight = in_flight.unwrap();
+    let (_, continuation) = common::split_client_url(&in_flight);
+    let continuation = continuation.unwrap();
+    assert_eq!(readiness(&client).await.0, Status::Ok);
+
+    set_maintenance(&client, true).await;
+
+    let (status, body) = readiness(&client).await;
+    assert_eq!(status, Status::ServiceUnavailable);
+    assert_eq!(body["status"], "maintenance");
+    assert_eq!(body["maintenance"], true);
+
+    let response = client
+        .post("/start_authentication")
+        .header(ContentType::JSON)
+        .body(json!({ "attributes": ["email"], "continuation": CONTINUATION }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::ServiceUnavailable);
+    assert_eq!(response.headers().get_one("Retry-After"), Some("300"));
+
+    let (ui_path, _) = common::split_client_url(&in_flight);
+    let response = client.get(ui_path).dispatch().await;
+    assert_eq!(response.status(), Status::ServiceUnavailable);
+
+    // The session started before maintenance still completes
+    let response = client
+        .get(common::local_path(&continuation))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::SeeOther);
+    let location = response.headers().get_one("Location").unwrap();
+    assert!(common::query_param(location, "result").is_some());
+
+    set_maintenance(&client, false).await;
+
+    assert_eq!(readiness(&client).await.0, Status::Ok);
+    assert_eq!(start(&client).await.0, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn maintenance_can_be_configured() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "admin_api_key": ADMIN_KEY, "maintenance_mode": true }),
+    ))
+    .await;
+
+    assert_eq!(readiness(&client).await.0, Status::ServiceUnavailable);
+    assert_eq!(start(&client).await, (Status::ServiceUnavailable, None));
+
+    set_maintenance(&client, false).await;
+
+    assert_eq!(start(&client).await.0, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn maintenance_toggle_requires_admin_key() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "admin_api_key": ADMIN_KEY }),
+    ))
+    .await;
+
+    for authorization in [None, Some("Bearer wrong-key"), Some(ADMIN_KEY)] {
+        let mut request = client
+            .post("/admin/maintenance")
+            .header(ContentType::JSON)
+            .body(json!({ "enabled": true }).to_string());
+        if let Some(authorization) = authorization {
+            request = request.header(Header::new("Authorization", authorization));
+        }
+        assert_eq!(request.dispatch().await.status(), Status::Unauthorized);
+    }
+
+    assert_eq!(readiness(&client).await.0, Status::Ok);
+}
@@ -0,0 +1,253 @@
+//! End-to-end test of the out-of-band flow: the plugin runs as a server the
+//! mock irma server calls back to once the session completes, and delivers
+//! the result to the attr_url of the session.
+
+#![cfg(feature = "mock-irma")]
+
+mod common;
+
+use common::Recorder;
+use rocket::http::Method;
+use serde_json::{json, Value};
+
+// Start an out-of-band session delivering to the given receiver, returning
+// the client url
+async fn start(plugin_url: &str, receiver_url: &str, attributes: &[&str]) -> String {
+    start_with(
+        plugin_url,
+        json!({
+            "attributes": attributes,
+            "attr_url": format!("{}/attributes", receiver_url),
+        }),
+    )
+    .await
+}
+
+// Like start, for a start request with other fields
+async fn start_with(plugin_url: &str, request: Value) -> String {
+    let response = reqwest::Client::new()
+        .post(format!("{}/start_authentication", plugin_url))
+        .json(&request)
+        .send()
+        .await
+        .expect("Could not start session");
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let started: Value = response.json().await.expect("Invalid start response");
+    started["client_url"]
+        .as_str()
+        .expect("Missing client_url")
+        .to_string()
+}
+
+#[rocket::async_test]
+async fn result_is_delivered_to_attr_url() {
+    let irma_url = common::mock_irma_server().await;
+    let plugin_url = common::plugin_server(&irma_url, json!({})).await;
+    let (receiver_url, receiver) = Recorder::spawn().await;
+
+    let client_url = start(&plugin_url, &receiver_url, &["email", "fullname"]).await;
+    assert!(client_url.starts_with(&format!("{}/auth/", common::SERVER_URL)));
+    // Nothing is delivered before the irma server calls back
+    assert!(receiver.requests().is_empty());
+
+    let delivered = receiver.wait_for(1).await;
+    assert_eq!(delivered.len(), 1);
+    let delivered = &delivered[0];
+    assert_eq!(delivered.method, Method::Post);
+    assert_eq!(delivered.uri, "/attributes");
+    assert_eq!(delivered.content_type.as_deref(), Some("application/jwt"));
+    let attributes = common::result_attributes(&delivered.body);
+    assert_eq!(attributes.len(), 2);
+    assert_eq!(attributes["email"], "mock value");
+    assert_eq!(attributes["fullname"], "mock value");
+}
+
+#[rocket::async_test]
+async fn result_is_delivered_with_configured_content_type() {
+    let irma_url = common::mock_irma_server().await;
+    let plugin_url = common::plugin_server(
+        &irma_url,
+        json!({ "callback_content_type": "application/jose" }),
+    )
+    .await;
+    let (receiver_url, receiver) = Recorder::spawn().await;
+
+    start(&plugin_url, &receiver_url, &["email"]).await;
+
+    let delivered = receiver.wait_for(1).await;
+    assert_eq!(
+        delivered[0].content_type.as_deref(),
+        Some("application/jose")
+    );
+    common::result_attributes(&delivered[0].body);
+}
+
+#[rocket::async_test]
+async fn nonce_is_echoed_in_delivered_result() {
+    let irma_url = common::mock_irma_server().await;
+    let plugin_url = common::plugin_server(&irma_url, json!({})).await;
+    let (receiver_url, receiver) = Recorder::spawn().await;
+
+    start_with(
+        &plugin_url,
+        json!({
+            "attributes": ["email"],
+            "attr_url": format!("{}/attributes", receiver_url),
+            "nonce": "n-0S6_WzA2Mj/+?&=",
+        }),
+    )
+    .await;
+
+    let delivered = receiver.wait_for(1).await;
+    let claims = common::result_claims(&delivered[0].body);
+    assert_eq!(claims.claim("nonce"), Some(&json!("n-0S6_WzA2Mj/+?&=")));
+}
+
+#[rocket::async_test]
+async fn delivered_result_has_no_nonce_unless_supplied() {
+    let irma_url = common::mock_irma_server().await;
+    let plugin_url = common::plugin_server(&irma_url, json!({})).await;
+    let (receiver_url, receiver) = Recorder::spawn().await;
+
+    start(&plugin_url, &receiver_url, &["email"]).await;
+
+    let delivered = receiver.wait_for(1).await;
+    assert_eq!(
+        common::result_claims(&delivered[0].body).claim("nonce"),
+        None
+    );
+}
+
+#[rocket::async_test]
+async fn lifecycle_is_audited_without_values() {
+    let irma_url = common::mock_irma_server().await;
+    let audit_log = common::audit_log_path();
+    let plugin_url = common::plugin_server(
+        &irma_url,
+        json!({ "audit_log": audit_log.to_str().unwrap() }),
+    )
+    .await;
+    let (receiver_url, receiver) = Recorder::spawn().await;
+
+    let client_url = start(&plugin_url, &receiver_url, &["email", "fullname"]).await;
+    let (ui_path, _) = common::split_client_url(&client_url);
+    let response = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(format!("{}{}", plugin_url, ui_path))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_redirection());
+    receiver.wait_for(1).await;
+
+    let lines = common::audit_lines(&audit_log, 4).await;
+    let events: Vec<&str> = lines
+        .iter()
+        .map(|line| line["event"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        events,
+        ["started", "qr_shown", "completed", "result_delivered"]
+    );
+    assert_eq!(lines[0]["flow"], "out_of_band");
+    assert_eq!(lines[0]["attributes"], json!(["email", "fullname"]));
+    assert_eq!(lines[2]["attributes"], json!(["email", "fullname"]));
+    let session = &lines[0]["session"];
+    assert!(session.is_string());
+    assert_eq!(&lines[2]["session"], session);
+    assert_eq!(&lines[3]["session"], session);
+    for line in &lines {
+        assert!(line["timestamp"].as_u64().is_some());
+    }
+
+    // Disclosed values are never logged
+    let log = std::fs::read_to_string(&audit_log).unwrap();
+    assert!(!log.contains("mock value"));
+    std::fs::remove_file(&audit_log).unwrap();
+}
+
+#[rocket::async_test]
+async fn completion_event_is_posted_after_delivery() {
+    let irma_url = common::mock_irma_server().await;
+    let (webhook_url, webhook) = Recorder::spawn().await;
+    let plugin_url = common::plugin_server(
+        &irma_url,
+        json!({ "event_webhook_url": format!("{}/events", webhook_url) }),
+    )
+    .await;
+    let (receiver_url, receiver) = Recorder::spawn().await;
+
+    start(&plugin_url, &receiver_url, &["email"]).await;
+
+    let delivered = receiver.wait_for(1).await;
+    let events = webhook.wait_for(1).await;
+    let event: Value = serde_json::from_str(&events[0].body).unwrap();
+    assert_eq!(event["status"], "success");
+    assert_eq!(
+        event["correlation_id"].as_str(),
+        common::result_claims(&delivered[0].body).jwt_id()
+    );
+}
+
+#[rocket::async_test]
+async fn failure_event_is_posted_for_undelivered_result() {
+    let irma_url = common::mock_irma_server().await;
+    let (webhook_url, webhook) = Recorder::spawn().await;
+    let plugin_url = common::plugin_server(
+        &irma_url,
+        json!({ "event_webhook_url": format!("{}/events", webhook_url) }),
+    )
+    .await;
+    // Nothing listens on the attr_url
+    let receiver_url = format!("http://127.0.0.1:{}", common::free_port());
+
+    start(&plugin_url, &receiver_url, &["email"]).await;
+
+    let events = webhook.wait_for(1).await;
+    let event: Value = serde_json::from_str(&events[0].body).unwrap();
+    assert_eq!(event["status"], "failure");
+    assert_eq!(event["reason"], "undelivered");
+    assert_eq!(event["correlation_id"], Value::Null);
+}
+
+#[rocket::async_test]
+async fn result_is_bound_to_normalized_attr_url() {
+    let irma_url = common::mock_irma_server().await;
+    let plugin_url = common::plugin_server(&irma_url, json!({ "bind_destination": true })).await;
+    let (receiver_url, receiver) = Recorder::spawn().await;
+    let port = receiver_url.rsplit(':').next().unwrap();
+
+    start_with(
+        &plugin_url,
+        json!({
+            "attributes": ["email"],
+            "attr_url": format!("HTTP://127.0.0.1:{}/results/../attributes", port),
+        }),
+    )
+    .await;
+
+    let delivered = receiver.wait_for(1).await;
+    assert_eq!(delivered[0].uri, "/attributes");
+    let claims = common::result_claims(&delivered[0].body);
+    assert_eq!(
+        claims.claim("destination"),
+        Some(&json!(format!("{}/attributes", receiver_url)))
+    );
+}
+
+#[rocket::async_test]
+async fn result_is_not_bound_unless_configured() {
+    let irma_url = common::mock_irma_server().await;
+    let plugin_url = common::plugin_server(&irma_url, json!({})).await;
+    let (receiver_url, receiver) = Recorder::spawn().await;
+
+    start(&plugin_url, &receiver_url, &["email"]).await;
+
+    let delivered = receiver.wait_for(1).await;
+    assert_eq!(
+        common::result_claims(&delivered[0].body).claim("destination"),
+        None
+    );
+}
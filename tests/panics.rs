@@ -0,0 +1,83 @@
+//! Panics in request handlers, which are answered like other internal errors
+//! while the plugin keeps serving.
+
+mod common;
+
+use std::sync::Mutex;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use rocket::{
+    get,
+    http::{Header, Status},
+    local::asynchronous::Client,
+    routes,
+};
+use serde_json::{json, Value};
+use verder_helpen_auth_irma::{create_rocket, panics};
+
+#[get("/panic")]
+fn panic_route() -> &'static str {
+    panic!("Panic in handler")
+}
+
+// Logger keeping the messages of errors, to inspect what the hook logs
+struct ErrorCapture(Mutex<Vec<String>>);
+
+impl Log for ErrorCapture {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= Level::Error
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if self.enabled(record.metadata()) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static ERRORS: ErrorCapture = ErrorCapture(Mutex::new(Vec::new()));
+
+async fn panics(client: &Client) -> u64 {
+    let health: Value = client
+        .get("/health")
+        .dispatch()
+        .await
+        .into_json()
+        .await
+        .unwrap();
+    health["panics"].as_u64().expect("Missing panic count")
+}
+
+#[rocket::async_test]
+async fn panic_is_json_internal_error() {
+    log::set_logger(&ERRORS).unwrap();
+    log::set_max_level(LevelFilter::Error);
+    panics::install_hook();
+    let config = common::config("http://127.0.0.1:1", json!({}));
+    let rocket =
+        create_rocket(config, None).mount("/test", panics::with_request_id(routes![panic_route]));
+    let client = Client::tracked(rocket).await.unwrap();
+    let panics_before = panics(&client).await;
+
+    let response = client
+        .get("/test/panic")
+        .header(Header::new("X-Request-Id", "req-1234"))
+        .dispatch()
+        .await;
+
+    assert_eq!(response.status(), Status::InternalServerError);
+    let body: Value = response.into_json().await.unwrap();
+    assert_eq!(
+        body,
+        json!({ "error": "internal_server_error", "request_id": "req-1234" })
+    );
+    assert_eq!(panics(&client).await, panics_before + 1);
+    let errors = ERRORS.0.lock().unwrap().clone();
+    assert!(errors
+        .iter()
+        .any(|error| error.contains("Panic in handler") && error.contains("(request req-1234)")));
+    let response = client.get("/readyz").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+}
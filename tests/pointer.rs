@@ -0,0 +1,94 @@
+//! Validation of session pointers before they are signed for the irma ui.
+
+mod common;
+
+use base64::URL_SAFE_NO_PAD;
+use rocket::http::Status;
+use serde_json::{json, Value};
+use verder_helpen_auth_irma::irma::validate_pointer;
+
+const ALLOWED_HOSTS: &[&str] = &["irma.example.com"];
+
+fn validate(qr: &str) -> Option<Value> {
+    let hosts: Vec<String> = ALLOWED_HOSTS.iter().map(|host| host.to_string()).collect();
+    validate_pointer(qr, &hosts)
+        .ok()
+        .map(|qr| serde_json::from_str(&qr).expect("Invalid validated pointer"))
+}
+
+#[test]
+fn pointer_to_irma_server_is_accepted() {
+    let qr = json!({ "u": "https://irma.example.com/irma/session/token", "irmaqr": "disclosing" });
+
+    assert_eq!(validate(&qr.to_string()), Some(qr));
+}
+
+#[test]
+fn garbage_is_refused() {
+    for qr in [
+        "",
+        "not json",
+        "[]",
+        r#""https://irma.example.com/irma/session/token""#,
+        r#"{"u":"https://irma.example.com/irma/session/token""#,
+        r#"{"u":"https://irma.example.com/irma/session/token"}"#,
+        r#"{"u":"https://irma.example.com/irma/session/token","irmaqr":"signing-everything"}"#,
+        r#"{"u":42,"irmaqr":"disclosing"}"#,
+        r#"{"u":"not a url","irmaqr":"disclosing"}"#,
+    ] {
+        assert_eq!(validate(qr), None, "{} is accepted", qr);
+    }
+}
+
+#[test]
+fn other_hosts_are_refused() {
+    for url in [
+        "https://attacker.example.com/irma/session/token",
+        "https://irma.example.com.attacker.example.com/irma/session/token",
+        "https://attacker.example.com/?irma.example.com",
+        "ftp://irma.example.com/irma/session/token",
+        "javascript://irma.example.com/%0aalert(1)",
+    ] {
+        let qr = json!({ "u": url, "irmaqr": "disclosing" }).to_string();
+        assert_eq!(validate(&qr), None, "{} is accepted", url);
+    }
+}
+
+#[test]
+fn host_is_matched_case_insensitively() {
+    let qr = json!({ "u": "https://IRMA.example.com/irma/session/token", "irmaqr": "disclosing" });
+
+    assert!(validate(&qr.to_string()).is_some());
+}
+
+#[test]
+fn extra_fields_are_dropped() {
+    let qr = json!({
+        "u": "https://irma.example.com/irma/session/token",
+        "irmaqr": "disclosing",
+        "continuation": "https://attacker.example.com",
+    });
+
+    assert_eq!(
+        validate(&qr.to_string()),
+        Some(json!({ "u": "https://irma.example.com/irma/session/token", "irmaqr": "disclosing" }))
+    );
+}
+
+#[rocket::async_test]
+async fn invalid_pointer_is_a_bad_request() {
+    let client = common::client(common::config("http://127.0.0.1:1", json!({}))).await;
+    let qr =
+        json!({ "u": "https://attacker.example.com/irma/session/token", "irmaqr": "disclosing" });
+
+    let response = client
+        .get(format!(
+            "/auth/{}",
+            base64::encode_config(qr.to_string(), URL_SAFE_NO_PAD)
+        ))
+        .dispatch()
+        .await;
+
+    assert_eq!(response.status(), Status::BadRequest);
+    assert_eq!(response.headers().get_one("Location"), None);
+}
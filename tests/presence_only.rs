@@ -0,0 +1,34 @@
+//! Sessions requesting no attributes, which disclose the configured
+//! presence-only attribute instead.
+
+mod common;
+
+use serde_json::json;
+use verder_helpen_auth_irma::irma::{IrmaDisclosureRequest, IrmaRequest};
+
+const PRESENCE_ONLY_ATTRIBUTE: &str = "pbdf.sidn-pbdf.mobilenumber.mobilenumber";
+
+#[test]
+fn empty_request_discloses_presence_only_attribute() {
+    let config = common::config(
+        "http://127.0.0.1:1",
+        json!({ "presence_only_attribute": PRESENCE_ONLY_ATTRIBUTE }),
+    );
+    assert!(config.allows_presence_only());
+
+    let request = IrmaRequest::Disclosure(IrmaDisclosureRequest {
+        disclose: config.map_attributes(&[]).unwrap(),
+        return_url: None,
+        augment_return: false,
+    });
+
+    assert_eq!(
+        serde_json::to_value(&request).unwrap(),
+        json!({
+            "@context": "https://irma.app/ld/request/disclosure/v2",
+            "disclose": [[[PRESENCE_ONLY_ATTRIBUTE]]],
+            "clientReturnUrl": null,
+            "augmentReturnUrl": false,
+        })
+    );
+}
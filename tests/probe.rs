@@ -0,0 +1,124 @@
+//! Background probing of the irma server, against a mock server that can be
+//! taken down and brought up again, as observed through /readyz.
+
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use rocket::{get, http::Status, routes, tokio, State};
+use serde_json::{json, Value};
+
+// Mock irma server, answering probes with 503 while down
+#[derive(Clone, Default)]
+struct MockIrma {
+    down: Arc<AtomicBool>,
+    probes: Arc<AtomicUsize>,
+}
+
+#[get("/")]
+fn probed(mock: &State<MockIrma>) -> Status {
+    mock.probes.fetch_add(1, Ordering::SeqCst);
+    if mock.down.load(Ordering::SeqCst) {
+        Status::ServiceUnavailable
+    } else {
+        Status::Ok
+    }
+}
+
+async fn mock_irma() -> (String, MockIrma) {
+    let mock = MockIrma::default();
+    let port = common::free_port();
+    common::launch(
+        rocket::build()
+            .manage(mock.clone())
+            .mount("/", routes![probed]),
+        port,
+    )
+    .await;
+    (format!("http://127.0.0.1:{}", port), mock)
+}
+
+async fn readiness(plugin_url: &str) -> (reqwest::StatusCode, Value) {
+    let response = reqwest::get(format!("{}/readyz", plugin_url))
+        .await
+        .unwrap();
+    (response.status(), response.json().await.unwrap())
+}
+
+// Wait until the probe reports the irma server up or down
+async fn wait_for_probe(plugin_url: &str, up: bool) -> (reqwest::StatusCode, Value) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let (status, health) = readiness(plugin_url).await;
+        if health["irma_probe"]["up"] == json!(up) {
+            return (status, health);
+        }
+        assert!(
+            Instant::now() < deadline,
+            "Probe did not report up = {}",
+            up
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[rocket::async_test]
+async fn probe_follows_the_irma_server() {
+    let (irma_url, mock) = mock_irma().await;
+    let plugin_url = common::plugin_server(&irma_url, json!({ "irma_probe_interval": 1 })).await;
+
+    let (status, health) = wait_for_probe(&plugin_url, true).await;
+    assert_eq!(status, reqwest::StatusCode::OK);
+    assert_eq!(health["status"], "ok");
+    assert!(health["irma_probe"]["latency_ms"].is_u64());
+
+    mock.down.store(true, Ordering::SeqCst);
+    let (status, health) = wait_for_probe(&plugin_url, false).await;
+    assert_eq!(status, reqwest::StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(health["status"], "irma_unreachable");
+
+    mock.down.store(false, Ordering::SeqCst);
+    let (status, _) = wait_for_probe(&plugin_url, true).await;
+    assert_eq!(status, reqwest::StatusCode::OK);
+}
+
+#[rocket::async_test]
+async fn readiness_does_not_call_the_irma_server() {
+    let (irma_url, mock) = mock_irma().await;
+    // Only the probe at startup runs during the test
+    let plugin_url = common::plugin_server(&irma_url, json!({ "irma_probe_interval": 3600 })).await;
+    wait_for_probe(&plugin_url, true).await;
+    let probes = mock.probes.load(Ordering::SeqCst);
+
+    for _ in 0..5 {
+        readiness(&plugin_url).await;
+    }
+
+    assert_eq!(mock.probes.load(Ordering::SeqCst), probes);
+}
+
+#[rocket::async_test]
+async fn unreachable_irma_server_is_reported() {
+    let irma_url = format!("http://127.0.0.1:{}", common::free_port());
+    let plugin_url = common::plugin_server(&irma_url, json!({ "irma_probe_interval": 1 })).await;
+
+    let (status, _) = wait_for_probe(&plugin_url, false).await;
+    assert_eq!(status, reqwest::StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[rocket::async_test]
+async fn disabled_probe_reports_nothing() {
+    let (irma_url, mock) = mock_irma().await;
+    let plugin_url = common::plugin_server(&irma_url, json!({})).await;
+
+    let (status, health) = readiness(&plugin_url).await;
+    assert_eq!(status, reqwest::StatusCode::OK);
+    assert_eq!(health.get("irma_probe"), None);
+    assert_eq!(mock.probes.load(Ordering::SeqCst), 0);
+}
@@ -0,0 +1,215 @@
+//! Signing through a remote signing service, against a mock service signing
+//! with the test key.
+
+mod common;
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use base64::URL_SAFE_NO_PAD;
+use common::CONTINUATION;
+use josekit::{
+    jwk::alg::rsa::RsaKeyPair,
+    jws::{JwsSigner, RS256},
+};
+use rocket::{
+    http::{ContentType, Status},
+    local::asynchronous::Client,
+    post, routes,
+    serde::json::Json,
+    tokio::runtime::{Builder, Runtime},
+    State,
+};
+use serde_json::{json, Value};
+use verder_helpen_auth_irma::config::{self, Config};
+
+// Mock signing service, failing with the queued statuses before it signs
+#[derive(Clone, Default)]
+struct MockSigner {
+    failures: Arc<Mutex<VecDeque<Status>>>,
+    requests: Arc<AtomicUsize>,
+}
+
+impl MockSigner {
+    fn fail_with(&self, statuses: &[Status]) {
+        self.failures.lock().unwrap().extend(statuses);
+    }
+
+    fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+}
+
+#[post("/sign", data = "<request>")]
+fn sign(mock: &State<MockSigner>, request: Json<Value>) -> Result<Json<Value>, Status> {
+    mock.requests.fetch_add(1, Ordering::SeqCst);
+    if let Some(status) = mock.failures.lock().unwrap().pop_front() {
+        return Err(status);
+    }
+    assert_eq!(request["alg"], "RS256");
+    assert_eq!(request["kid"], "remote-1");
+    let message =
+        base64::decode_config(request["message"].as_str().unwrap(), URL_SAFE_NO_PAD).unwrap();
+    let signature = RS256
+        .signer_from_pem(common::PRIVATE_KEY)
+        .unwrap()
+        .sign(&message)
+        .unwrap();
+    Ok(Json(json!({
+        "signature": base64::encode_config(signature, URL_SAFE_NO_PAD),
+    })))
+}
+
+async fn mock_signer() -> (String, MockSigner) {
+    let mock = MockSigner::default();
+    let port = common::free_port();
+    common::launch(
+        rocket::build()
+            .manage(mock.clone())
+            .mount("/", routes![sign]),
+        port,
+    )
+    .await;
+    (format!("http://127.0.0.1:{}/sign", port), mock)
+}
+
+fn key_pair() -> RsaKeyPair {
+    RsaKeyPair::from_pem(common::PRIVATE_KEY).unwrap()
+}
+
+fn remote_config(url: &str, public_key: Value) -> Result<Config, config::Error> {
+    common::try_config(
+        "http://127.0.0.1:1",
+        json!({
+            "insecure_dev_mode": true,
+            "allow_insecure_urls": true,
+            "test_mode": { "attributes": { "email": "test@example.com" } },
+            "signing_privkey": {
+                "remote": {
+                    "url": url,
+                    "algorithm": "RS256",
+                    "key_id": "remote-1",
+                    "public_key": public_key,
+                    "attempts": 3,
+                },
+            },
+        }),
+    )
+}
+
+fn public_jwk() -> Value {
+    Value::Object(key_pair().to_jwk_public_key().as_ref().clone())
+}
+
+// Complete a test mode session, returning the response to its confirmation
+async fn complete_session(client: &Client) -> (Status, Option<String>) {
+    let response = client
+        .post("/start_authentication")
+        .header(ContentType::JSON)
+        .body(json!({ "attributes": ["email"], "continuation": CONTINUATION }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let started: Value = response.into_json().await.unwrap();
+    let confirm_url = started["client_url"].as_str().unwrap();
+    let response = client
+        .post(common::local_path(confirm_url))
+        .dispatch()
+        .await;
+    let retry_after = response
+        .headers()
+        .get_one("Retry-After")
+        .map(str::to_string);
+    let location = response.headers().get_one("Location").map(str::to_string);
+    (response.status(), location.or(retry_after))
+}
+
+#[rocket::async_test]
+async fn results_are_signed_remotely() {
+    let (url, mock) = mock_signer().await;
+    let client = common::client(remote_config(&url, public_jwk()).unwrap()).await;
+    let before = mock.requests();
+
+    let (status, location) = complete_session(&client).await;
+
+    assert_eq!(status, Status::SeeOther);
+    let result = common::query_param(&location.unwrap(), "result").unwrap();
+    // Verified with the public key of the test key pair
+    assert_eq!(
+        common::result_attributes(&result)["email"],
+        "test@example.com"
+    );
+    assert_eq!(mock.requests(), before + 1);
+}
+
+#[rocket::async_test]
+async fn unavailable_signer_is_retried() {
+    let (url, mock) = mock_signer().await;
+    let client = common::client(remote_config(&url, public_jwk()).unwrap()).await;
+    let before = mock.requests();
+
+    mock.fail_with(&[Status::ServiceUnavailable, Status::TooManyRequests]);
+    let (status, _) = complete_session(&client).await;
+
+    assert_eq!(status, Status::SeeOther);
+    assert_eq!(mock.requests(), before + 3);
+}
+
+#[rocket::async_test]
+async fn unavailable_signer_is_503() {
+    let (url, mock) = mock_signer().await;
+    let client = common::client(remote_config(&url, public_jwk()).unwrap()).await;
+    let before = mock.requests();
+
+    mock.fail_with(&[Status::ServiceUnavailable; 3]);
+    let (status, retry_after) = complete_session(&client).await;
+
+    assert_eq!(status, Status::ServiceUnavailable);
+    assert_eq!(retry_after.as_deref(), Some("5"));
+    assert_eq!(mock.requests(), before + 3);
+}
+
+#[rocket::async_test]
+async fn refusal_is_not_retried() {
+    let (url, mock) = mock_signer().await;
+    let client = common::client(remote_config(&url, public_jwk()).unwrap()).await;
+    let before = mock.requests();
+
+    mock.fail_with(&[Status::Forbidden]);
+    let (status, _) = complete_session(&client).await;
+
+    assert_eq!(status, Status::InternalServerError);
+    assert_eq!(mock.requests(), before + 1);
+}
+
+#[rocket::async_test]
+async fn public_key_with_private_parameters_is_refused() {
+    let (url, mock) = mock_signer().await;
+    let private_jwk = Value::Object(key_pair().to_jwk_private_key().as_ref().clone());
+
+    assert!(remote_config(&url, private_jwk).is_err());
+    assert_eq!(mock.requests(), 0);
+}
+
+// Configurations are also loaded and used outside of multi-threaded
+// runtimes, such as by the command line tools
+#[test]
+fn signing_works_on_a_single_threaded_runtime() {
+    // The mock runs on a runtime of its own, as signing blocks the runtime it
+    // is called on
+    let server = Runtime::new().unwrap();
+    let (url, mock) = server.block_on(mock_signer());
+    let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+
+    runtime.block_on(async {
+        let config = remote_config(&url, public_jwk()).unwrap();
+        let before = mock.requests();
+        assert!(config.signer().sign(b"message").is_ok());
+        assert_eq!(mock.requests(), before + 1);
+    });
+}
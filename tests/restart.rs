@@ -0,0 +1,171 @@
+//! Restarting unscanned sessions through `/restart_authentication/<token>`,
+//! against a mock irma server of which the session status is controlled by
+//! the test.
+
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use common::CONTINUATION;
+use rocket::{
+    delete, get,
+    http::{ContentType, Status},
+    local::asynchronous::Client,
+    post, routes,
+    serde::json::Json,
+    tokio, State,
+};
+use serde_json::{json, Value};
+
+// Mock irma server, reporting the same status for every session. Status
+// requests are answered with a delay, so restarts can overlap.
+#[derive(Clone)]
+struct MockIrma {
+    url: String,
+    status: Arc<Mutex<&'static str>>,
+    sessions: Arc<AtomicUsize>,
+    cancelled: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockIrma {
+    fn set_status(&self, status: &'static str) {
+        *self.status.lock().unwrap() = status;
+    }
+
+    fn cancelled(&self) -> Vec<String> {
+        self.cancelled.lock().unwrap().clone()
+    }
+}
+
+#[post("/session", data = "<_request>")]
+fn start_session(mock: &State<MockIrma>, _request: Json<Value>) -> Json<Value> {
+    let token = format!("session-{}", mock.sessions.fetch_add(1, Ordering::SeqCst));
+    Json(json!({
+        "token": token,
+        "sessionPtr": {
+            "u": format!("{}/irma/session/{}", mock.url, token),
+            "irmaqr": "disclosing",
+        },
+    }))
+}
+
+#[get("/session/<_token>/status")]
+async fn session_status(mock: &State<MockIrma>, _token: String) -> Json<&'static str> {
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    Json(*mock.status.lock().unwrap())
+}
+
+#[delete("/session/<token>")]
+fn cancel_session(mock: &State<MockIrma>, token: String) -> Status {
+    mock.cancelled.lock().unwrap().push(token);
+    Status::NoContent
+}
+
+async fn mock_irma() -> MockIrma {
+    let port = common::free_port();
+    let mock = MockIrma {
+        url: format!("http://127.0.0.1:{}", port),
+        status: Arc::new(Mutex::new("INITIALIZED")),
+        sessions: Arc::default(),
+        cancelled: Arc::default(),
+    };
+    common::launch(
+        rocket::build()
+            .manage(mock.clone())
+            .mount("/", routes![start_session, session_status, cancel_session]),
+        port,
+    )
+    .await;
+    mock
+}
+
+// Session token in the continuation of an in-band client url
+fn session_token(client_url: &str) -> String {
+    let (_, continuation) = common::split_client_url(client_url);
+    common::query_param(&continuation.unwrap(), "token").expect("Missing session token")
+}
+
+// Start an in-band session, returning its session token
+async fn start(client: &Client) -> String {
+    let response = client
+        .post("/start_authentication")
+        .header(ContentType::JSON)
+        .body(json!({ "attributes": ["email"], "continuation": CONTINUATION }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let started: Value = response.into_json().await.unwrap();
+    session_token(started["client_url"].as_str().unwrap())
+}
+
+async fn restart(client: &Client, token: &str) -> (Status, Option<Value>) {
+    let response = client
+        .post(format!("/restart_authentication/{}", token))
+        .dispatch()
+        .await;
+    (response.status(), response.into_json().await)
+}
+
+#[rocket::async_test]
+async fn unscanned_session_is_replaced() {
+    let mock = mock_irma().await;
+    let client = common::client(common::config(&mock.url, json!({}))).await;
+    let token = start(&client).await;
+
+    let (status, started) = restart(&client, &token).await;
+
+    assert_eq!(status, Status::Ok);
+    let new_token = session_token(started.unwrap()["client_url"].as_str().unwrap());
+    assert_ne!(new_token, token);
+    assert_eq!(mock.cancelled(), [token.clone()]);
+    // The old session is gone, the new one can be restarted in turn
+    let (status, _) = restart(&client, &token).await;
+    assert_eq!(status, Status::NotFound);
+    let (status, _) = restart(&client, &new_token).await;
+    assert_eq!(status, Status::Ok);
+}
+
+#[rocket::async_test]
+async fn connected_session_is_a_conflict() {
+    let mock = mock_irma().await;
+    let client = common::client(common::config(&mock.url, json!({}))).await;
+    let token = start(&client).await;
+
+    mock.set_status("CONNECTED");
+    let (status, _) = restart(&client, &token).await;
+
+    assert_eq!(status, Status::Conflict);
+    assert!(mock.cancelled().is_empty());
+}
+
+#[rocket::async_test]
+async fn concurrent_restarts_replace_the_session_once() {
+    let mock = mock_irma().await;
+    let client = common::client(common::config(&mock.url, json!({}))).await;
+    let token = start(&client).await;
+
+    let (first, second) = tokio::join!(restart(&client, &token), restart(&client, &token));
+
+    let mut statuses = [first.0, second.0];
+    statuses.sort_by_key(|status| status.code);
+    assert_eq!(statuses, [Status::Ok, Status::NotFound]);
+    assert_eq!(mock.cancelled(), [token]);
+    assert_eq!(mock.sessions.load(Ordering::SeqCst), 2);
+}
+
+#[rocket::async_test]
+async fn unknown_session_is_not_found() {
+    let mock = mock_irma().await;
+    let client = common::client(common::config(&mock.url, json!({}))).await;
+
+    let (status, _) = restart(&client, "unknown").await;
+
+    assert_eq!(status, Status::NotFound);
+    assert!(mock.cancelled().is_empty());
+}
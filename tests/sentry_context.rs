@@ -0,0 +1,120 @@
+//! Sentry events for failed requests, captured through a mock transport.
+#![cfg(feature = "sentry")]
+
+mod common;
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use base64::URL_SAFE_NO_PAD;
+use common::{Recorder, CONTINUATION};
+use rocket::http::{Header, Status};
+use sentry::{
+    protocol::{Event, Level},
+    ClientOptions, Envelope, Hub, Transport,
+};
+use serde_json::json;
+
+const TOKEN: &str = "secret-session-token";
+
+// Transport keeping the events instead of sending them
+#[derive(Default)]
+struct MockTransport(Mutex<Vec<Event<'static>>>);
+
+impl Transport for MockTransport {
+    fn send_envelope(&self, envelope: Envelope) {
+        if let Some(event) = envelope.event() {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+}
+
+impl MockTransport {
+    fn take(&self) -> Vec<Event<'static>> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+fn tags(event: &Event<'static>) -> BTreeMap<&str, &str> {
+    event
+        .tags
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect()
+}
+
+// All cases share one test, as they all capture through the main hub
+#[rocket::async_test]
+async fn failed_requests_are_captured_with_their_context() {
+    let transport = Arc::new(MockTransport::default());
+    Hub::main().bind_client(Some(Arc::new(
+        ClientOptions {
+            dsn: Some("https://public@sentry.invalid/1".parse().unwrap()),
+            transport: Some(Arc::new(transport.clone())),
+            ..ClientOptions::default()
+        }
+        .into(),
+    )));
+    let (irma_url, _) = Recorder::spawn().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    // Internal error of the in-band flow
+    let path = format!(
+        "/decorated_continue/{}/{}?token={}",
+        base64::encode_config(r#"["email"]"#, URL_SAFE_NO_PAD),
+        base64::encode_config(CONTINUATION, URL_SAFE_NO_PAD),
+        TOKEN
+    );
+    let response = client
+        .get(path)
+        .header(Header::new("X-Request-Id", "req-1"))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::InternalServerError);
+    let events = transport.take();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].level, Level::Error);
+    let tags = tags(&events[0]);
+    assert_eq!(tags["route"], "decorated_continue");
+    assert_eq!(tags["flow"], "in_band");
+    assert_eq!(tags["request_id"], "req-1");
+    assert_eq!(tags["error_code"], "irma");
+    assert_eq!(tags["client_error"], "false");
+    assert_ne!(tags["session"], TOKEN);
+    let event = format!("{:?}", events[0]);
+    for detail in [TOKEN, CONTINUATION, irma_url.as_str()] {
+        assert!(!event.contains(detail), "{} attached to event", detail);
+    }
+
+    // Client error answered by the route
+    let response = client
+        .get("/decorated_continue/not-base64!/not-base64!?token=other-token")
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::BadRequest);
+    let events = transport.take();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].level, Level::Warning);
+    let tags = tags(&events[0]);
+    assert_eq!(tags["error_code"], "invalid_parameter");
+    assert_eq!(tags["client_error"], "true");
+    assert!(!tags.contains_key("request_id"));
+
+    // Client error answered by a catcher
+    let response = client.get("/no_such_route").dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+    let events = transport.take();
+    assert_eq!(events.len(), 1);
+    let tags = tags(&events[0]);
+    assert_eq!(tags["error_code"], "not_found");
+    assert_eq!(tags["client_error"], "true");
+    assert!(!tags.contains_key("route"));
+
+    // The tags of failed requests stay with their own events
+    sentry::capture_message("Unrelated", Level::Info);
+    let events = transport.take();
+    assert_eq!(events.len(), 1);
+    assert!(tags(&events[0]).is_empty());
+}
@@ -0,0 +1,132 @@
+//! Progress of sessions as reported to the waiting page, including why
+//! finished sessions failed.
+
+#![cfg(feature = "mock-irma")]
+
+mod common;
+
+use base64::URL_SAFE_NO_PAD;
+use common::CONTINUATION;
+use rocket::{
+    get,
+    http::{ContentType, Status},
+    local::asynchronous::Client,
+    routes,
+    serde::json::Json,
+};
+use serde_json::{json, Value};
+
+// Start an in-band session for the email attribute, returning its irma
+// session token
+async fn start(client: &Client) -> String {
+    let response = client
+        .post("/start_authentication")
+        .header(ContentType::JSON)
+        .body(json!({ "attributes": ["email"], "continuation": CONTINUATION }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let started: Value = response.into_json().await.unwrap();
+    let (_, continuation) = common::split_client_url(started["client_url"].as_str().unwrap());
+    common::query_param(&continuation.unwrap(), "token").expect("Missing session token")
+}
+
+async fn status(client: &Client, path: &str) -> Value {
+    let response = client.get(path).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    response.into_json().await.unwrap()
+}
+
+fn attributes_param(attributes: &[&str]) -> String {
+    base64::encode_config(json!(attributes).to_string(), URL_SAFE_NO_PAD)
+}
+
+#[rocket::async_test]
+async fn progress_follows_the_session() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+    let token = start(&client).await;
+    let path = format!(
+        "/session_status/{}?attributes={}",
+        token,
+        attributes_param(&["email"])
+    );
+
+    assert_eq!(
+        status(&client, &path).await,
+        json!({ "status": "INITIALIZED" })
+    );
+    assert_eq!(
+        status(&client, &path).await,
+        json!({ "status": "CONNECTED" })
+    );
+    assert_eq!(status(&client, &path).await, json!({ "status": "DONE" }));
+}
+
+#[rocket::async_test]
+async fn unacceptable_disclosure_is_failed() {
+    let irma_url = common::mock_irma_server().await;
+    // The mock irma server discloses "mock value", which is no email address
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "attribute_formats": { "email": { "type": "email" } } }),
+    ))
+    .await;
+    let token = start(&client).await;
+    let path = format!(
+        "/session_status/{}?attributes={}",
+        token,
+        attributes_param(&["email"])
+    );
+
+    status(&client, &path).await;
+    status(&client, &path).await;
+    assert_eq!(
+        status(&client, &path).await,
+        json!({ "status": "FAILED", "reason": "invalid_proof" })
+    );
+}
+
+#[rocket::async_test]
+async fn cancelled_session_is_failed() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+    let token = start(&client).await;
+    reqwest::Client::new()
+        .delete(format!("{}/session/{}", irma_url, token))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(
+        status(&client, &format!("/session_status/{}", token)).await,
+        json!({ "status": "FAILED", "reason": "cancelled" })
+    );
+}
+
+#[get("/session/<_token>/status")]
+fn pairing_status(_token: String) -> Json<&'static str> {
+    Json("PAIRING")
+}
+
+#[rocket::async_test]
+async fn pairing_session_is_reported() {
+    let port = common::free_port();
+    common::launch(rocket::build().mount("/", routes![pairing_status]), port).await;
+    let irma_url = format!("http://127.0.0.1:{}", port);
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    assert_eq!(
+        status(&client, "/session_status/some-token").await,
+        json!({ "status": "PAIRING" })
+    );
+}
+
+#[rocket::async_test]
+async fn unknown_session_is_not_found() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    let response = client.get("/session_status/unknown-token").dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+}
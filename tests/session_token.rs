@@ -0,0 +1,47 @@
+//! Session tokens, which never show their raw value when formatted.
+
+use verder_helpen_auth_irma::irma::SessionToken;
+
+const RAW: &str = "Xy7mQ2vLp9RtKw4NbZc8";
+
+#[test]
+fn formatting_hides_raw_token() {
+    let token = SessionToken::new(RAW.to_string());
+
+    for formatted in [
+        format!("{}", token),
+        format!("{:?}", token),
+        format!("{:#?}", Some(&token)),
+        token.display_token(),
+    ] {
+        assert!(!formatted.contains(RAW), "{} reveals the token", formatted);
+        // Not even beyond the prefix identifying the session in logs
+        assert!(
+            !formatted.contains(&RAW[6..12]),
+            "{} reveals the token",
+            formatted
+        );
+    }
+}
+
+#[test]
+fn display_token_correlates_sessions() {
+    let token = SessionToken::new(RAW.to_string());
+    let other = SessionToken::new(format!("{}x", RAW));
+
+    assert!(token.display_token().starts_with(&RAW[..6]));
+    assert_eq!(token.display_token(), token.clone().display_token());
+    assert_ne!(token.display_token(), other.display_token());
+}
+
+#[test]
+fn raw_token_is_exposed_explicitly() {
+    let token = SessionToken::new(RAW.to_string());
+
+    assert_eq!(token.expose(), RAW);
+    // The irma server and the app need the raw value
+    assert_eq!(
+        serde_json::to_string(&token).unwrap(),
+        format!("\"{}\"", RAW)
+    );
+}
@@ -0,0 +1,379 @@
+//! Tests of starting sessions through `/start_authentication`, in both flows,
+//! and of how errors of the routes are answered.
+
+#![cfg(feature = "mock-irma")]
+
+mod common;
+
+use base64::URL_SAFE_NO_PAD;
+use common::{Recorder, CONTINUATION};
+use rocket::{
+    http::{ContentType, Status},
+    local::asynchronous::Client,
+};
+use serde_json::{json, Value};
+
+const ATTR_URL: &str = "https://core.example.com/attributes";
+
+fn encode(value: &str) -> String {
+    base64::encode_config(value, URL_SAFE_NO_PAD)
+}
+
+// Start a session, returning the client url of the response
+async fn start(client: &Client, request: Value) -> String {
+    let response = client
+        .post("/start_authentication")
+        .header(ContentType::JSON)
+        .body(request.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let started: Value = response.into_json().await.expect("Invalid start response");
+    started["client_url"]
+        .as_str()
+        .expect("Missing client_url")
+        .to_string()
+}
+
+// Status and error message of the response to a start request
+async fn try_start(client: &Client, request: Value) -> (Status, String) {
+    let response = client
+        .post("/start_authentication")
+        .header(ContentType::JSON)
+        .body(request.to_string())
+        .dispatch()
+        .await;
+    (
+        response.status(),
+        common::error_message(&response.into_string().await.unwrap_or_default()),
+    )
+}
+
+// Status and location of the redirect to the irma ui for a client url
+async fn open_client_url(client: &Client, client_url: &str) -> (Status, Option<String>) {
+    let response = client.get(common::local_path(client_url)).dispatch().await;
+    (
+        response.status(),
+        response.headers().get_one("Location").map(str::to_string),
+    )
+}
+
+#[rocket::async_test]
+async fn in_band_start_continues_through_plugin() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    let client_url = start(
+        &client,
+        json!({ "attributes": ["email"], "continuation": CONTINUATION }),
+    )
+    .await;
+
+    let (_, continuation) = common::split_client_url(&client_url);
+    let continuation = continuation.expect("Missing continuation");
+    let path = common::local_path(&continuation);
+    let (path, _) = path.split_once('?').expect("Missing query");
+    assert!(path.starts_with("/decorated_continue/"));
+    assert!(path.ends_with(&format!("/{}", encode(CONTINUATION))));
+    assert!(common::query_param(&continuation, "token").is_some());
+
+    let (status, location) = open_client_url(&client, &client_url).await;
+    assert_eq!(status, Status::SeeOther);
+    assert!(location.unwrap().starts_with(common::UI_IRMA_URL));
+}
+
+#[rocket::async_test]
+async fn out_of_band_start_without_continuation() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    let client_url = start(
+        &client,
+        json!({ "attributes": ["email"], "attr_url": ATTR_URL }),
+    )
+    .await;
+
+    let (_, continuation) = common::split_client_url(&client_url);
+    assert_eq!(continuation, None);
+    let (status, location) = open_client_url(&client, &client_url).await;
+    assert_eq!(status, Status::SeeOther);
+    assert!(location.unwrap().starts_with(common::UI_IRMA_URL));
+}
+
+#[rocket::async_test]
+async fn out_of_band_start_continues_directly() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    let client_url = start(
+        &client,
+        json!({
+            "attributes": ["email"],
+            "attr_url": ATTR_URL,
+            "continuation": CONTINUATION,
+        }),
+    )
+    .await;
+
+    // The attributes go to the attr_url, so the browser returns to the
+    // continuation without passing the plugin
+    let (_, continuation) = common::split_client_url(&client_url);
+    assert_eq!(continuation.as_deref(), Some(CONTINUATION));
+    let (status, _) = open_client_url(&client, &client_url).await;
+    assert_eq!(status, Status::SeeOther);
+}
+
+#[rocket::async_test]
+async fn bad_request_is_answered_with_description() {
+    let client = common::client(common::config("http://127.0.0.1:1", json!({}))).await;
+
+    let response = client
+        .post("/start_authentication")
+        .header(ContentType::JSON)
+        .body(json!({ "attributes": ["email"] }).to_string())
+        .dispatch()
+        .await;
+
+    assert_eq!(response.status(), Status::BadRequest);
+    assert_eq!(
+        common::error_message(&response.into_string().await.unwrap()),
+        "Either a continuation or an attr_url is required"
+    );
+}
+
+#[rocket::async_test]
+async fn empty_attributes_are_a_bad_request() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    let response = client
+        .post("/start_authentication")
+        .header(ContentType::JSON)
+        .body(json!({ "attributes": [], "continuation": CONTINUATION }).to_string())
+        .dispatch()
+        .await;
+
+    assert_eq!(response.status(), Status::BadRequest);
+    assert_eq!(
+        common::error_message(&response.into_string().await.unwrap()),
+        "No attributes requested"
+    );
+}
+
+#[rocket::async_test]
+async fn invalid_parameter_is_a_bad_request() {
+    let client = common::client(common::config("http://127.0.0.1:1", json!({}))).await;
+
+    let response = client.get("/auth/not*base64").dispatch().await;
+
+    assert_eq!(response.status(), Status::BadRequest);
+}
+
+#[rocket::async_test]
+async fn unknown_session_is_gone() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    let path = format!(
+        "/decorated_continue/{}/{}?token=unknown",
+        encode(r#"["email"]"#),
+        encode(CONTINUATION),
+    );
+    let response = client.get(path).dispatch().await;
+
+    assert_eq!(response.status(), Status::Gone);
+    assert_eq!(
+        common::error_message(&response.into_string().await.unwrap()),
+        "Unknown or expired session"
+    );
+}
+
+#[rocket::async_test]
+async fn internal_error_has_no_details() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    // A disclosure of other attributes than requested does not map
+    let client_url = start(
+        &client,
+        json!({ "attributes": ["email"], "continuation": CONTINUATION }),
+    )
+    .await;
+    let (_, continuation) = common::split_client_url(&client_url);
+    let continuation = continuation.unwrap();
+    let tampered = continuation.replacen(&encode(r#"["email"]"#), &encode(r#"["city"]"#), 1);
+    let response = client.get(common::local_path(&tampered)).dispatch().await;
+
+    assert_eq!(response.status(), Status::InternalServerError);
+    let body = response.into_string().await.unwrap_or_default();
+    assert!(!body.contains("pbdf"));
+    assert!(!body.contains(&irma_url));
+}
+
+const INSECURE: &str = "attr_url and continuations must be https urls";
+
+#[rocket::async_test]
+async fn http_urls_are_refused() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    for request in [
+        json!({ "attributes": ["email"], "continuation": "http://core.example.com/continue" }),
+        json!({ "attributes": ["email"], "attr_url": "http://core.example.com/attributes" }),
+        json!({
+            "attributes": ["email"],
+            "attr_url": ATTR_URL,
+            "continuation": "http://core.example.com/continue",
+        }),
+        json!({
+            "attributes": ["email"],
+            "continuation": CONTINUATION,
+            "failure_continuation": "http://core.example.com/failed",
+        }),
+    ] {
+        assert_eq!(
+            try_start(&client, request).await,
+            (Status::BadRequest, INSECURE.to_string())
+        );
+    }
+}
+
+#[rocket::async_test]
+async fn https_and_loopback_urls_are_accepted() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    for url in [
+        "https://core.example.com/continue",
+        "http://localhost/continue",
+        "http://localhost:8000/continue",
+        "http://127.0.0.1:8000/continue",
+        "http://[::1]:8000/continue",
+    ] {
+        let (status, _) = try_start(
+            &client,
+            json!({ "attributes": ["email"], "continuation": url }),
+        )
+        .await;
+        assert_eq!(status, Status::Ok, "{} is refused", url);
+    }
+}
+
+#[rocket::async_test]
+async fn http_urls_are_accepted_when_allowed() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "allow_insecure_urls": true, "insecure_dev_mode": true }),
+    ))
+    .await;
+
+    let (status, _) = try_start(
+        &client,
+        json!({ "attributes": ["email"], "attr_url": "http://core.example.com/attributes" }),
+    )
+    .await;
+
+    assert_eq!(status, Status::Ok);
+}
+
+#[test]
+fn allowing_http_urls_requires_insecure_dev_mode() {
+    let config = common::try_config("http://127.0.0.1:1", json!({ "allow_insecure_urls": true }));
+
+    assert!(config.is_err());
+}
+
+#[rocket::async_test]
+async fn overlong_nonce_is_refused() {
+    let irma_url = common::mock_irma_server().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    let (status, body) = try_start(
+        &client,
+        json!({ "attributes": ["email"], "continuation": CONTINUATION, "nonce": "n".repeat(257) }),
+    )
+    .await;
+    assert_eq!(status, Status::BadRequest);
+    assert_eq!(body, "nonce too long");
+
+    let (status, _) = try_start(
+        &client,
+        json!({ "attributes": ["email"], "continuation": CONTINUATION, "nonce": "n".repeat(256) }),
+    )
+    .await;
+    assert_eq!(status, Status::Ok);
+}
+
+// Session request the plugin sends to the irma server for a start request,
+// with augment_return_url configured as `mode`
+async fn session_request(mode: &str, request: Value) -> Value {
+    let (irma_url, irma) = Recorder::spawn().await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "augment_return_url": mode }),
+    ))
+    .await;
+
+    // The recorder is no irma server, so the start itself fails
+    try_start(&client, request).await;
+
+    let requests = irma.requests();
+    assert_eq!(requests[0].uri, "/session");
+    let request: Value = serde_json::from_str(&requests[0].body).unwrap();
+    // Requests with a callback wrap the disclosure request
+    match request.get("request") {
+        Some(inner) => inner.clone(),
+        None => request,
+    }
+}
+
+async fn augments_in_band(mode: &str, client: &str) -> bool {
+    let request = session_request(
+        mode,
+        json!({ "attributes": ["email"], "continuation": CONTINUATION, "client": client }),
+    )
+    .await;
+    assert!(request["clientReturnUrl"].is_string());
+    request["augmentReturnUrl"]
+        .as_bool()
+        .expect("Missing augmentReturnUrl")
+}
+
+#[rocket::async_test]
+async fn return_url_is_augmented_always() {
+    assert!(augments_in_band("always", "web").await);
+    assert!(augments_in_band("always", "app").await);
+}
+
+#[rocket::async_test]
+async fn return_url_is_augmented_never() {
+    assert!(!augments_in_band("never", "web").await);
+    assert!(!augments_in_band("never", "app").await);
+}
+
+#[rocket::async_test]
+async fn return_url_is_augmented_for_web_only() {
+    assert!(augments_in_band("web_only", "web").await);
+    assert!(!augments_in_band("web_only", "app").await);
+}
+
+#[rocket::async_test]
+async fn sessions_are_web_sessions_by_default() {
+    let request = session_request(
+        "web_only",
+        json!({ "attributes": ["email"], "continuation": CONTINUATION }),
+    )
+    .await;
+    assert_eq!(request["augmentReturnUrl"], true);
+}
+
+#[rocket::async_test]
+async fn out_of_band_return_url_is_not_augmented() {
+    let request = session_request(
+        "always",
+        json!({ "attributes": ["email"], "attr_url": ATTR_URL, "continuation": CONTINUATION }),
+    )
+    .await;
+    assert_eq!(request["augmentReturnUrl"], false);
+}
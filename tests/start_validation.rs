@@ -0,0 +1,123 @@
+//! Validation of start requests, their urls and their size, which happens
+//! before any irma session is created.
+
+mod common;
+
+use common::{Recorder, CONTINUATION};
+use rocket::http::{ContentType, Status};
+use serde_json::{json, Value};
+
+#[rocket::async_test]
+async fn malformed_urls_are_refused_without_irma_call() {
+    let (irma_url, irma) = Recorder::spawn().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    for (field, url) in [
+        ("attr_url", "core.example.com/attributes"),
+        ("attr_url", "https://core example.com/attributes"),
+        ("attr_url", "https://"),
+        ("attr_url", "https://core.example.com:99999/attributes"),
+        ("attr_url", "mailto:core@example.com"),
+        ("continuation", "not a url"),
+        ("continuation", "/continue"),
+        ("failure_continuation", "https://[core.example.com]/failed"),
+    ] {
+        let mut request = json!({ "attributes": ["email"], "continuation": CONTINUATION });
+        request[field] = Value::String(url.to_string());
+        let response = client
+            .post("/start_authentication")
+            .header(ContentType::JSON)
+            .body(request.to_string())
+            .dispatch()
+            .await;
+
+        assert_eq!(response.status(), Status::BadRequest, "{} is accepted", url);
+        let body = common::error_message(&response.into_string().await.unwrap_or_default());
+        assert!(
+            body.starts_with(&format!("Invalid {}: ", field)),
+            "Unexpected error {} for {}",
+            body,
+            url
+        );
+    }
+
+    assert!(irma.requests().is_empty());
+}
+
+#[rocket::async_test]
+async fn callback_carries_normalized_attr_url() {
+    let (irma_url, irma) = Recorder::spawn().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    // The recorder does not answer like an irma server, so the start fails
+    // after the session was requested
+    client
+        .post("/start_authentication")
+        .header(ContentType::JSON)
+        .body(
+            json!({ "attributes": ["email"], "attr_url": "HTTPS://Core.Example.com/attributes" })
+                .to_string(),
+        )
+        .dispatch()
+        .await;
+
+    let requests = irma.wait_for(1).await;
+    let session_request: Value = serde_json::from_str(&requests[0].body).unwrap();
+    let callback_url = session_request["callbackUrl"].as_str().unwrap();
+    let attr_url = callback_url
+        .split('?')
+        .next()
+        .unwrap()
+        .rsplit('/')
+        .next()
+        .unwrap();
+    assert_eq!(
+        common::decode_b64(attr_url),
+        "https://core.example.com/attributes"
+    );
+}
+
+#[rocket::async_test]
+async fn oversized_request_is_refused_without_irma_call() {
+    let (irma_url, irma) = Recorder::spawn().await;
+    let client = common::client(common::config(
+        &irma_url,
+        json!({ "start_request_limit": 1024 }),
+    ))
+    .await;
+
+    // A valid request, padded with a nonce beyond the limit
+    let request = json!({
+        "attributes": ["email"],
+        "continuation": CONTINUATION,
+        "nonce": "n".repeat(2048),
+    });
+    let response = client
+        .post("/start_authentication")
+        .header(ContentType::JSON)
+        .body(request.to_string())
+        .dispatch()
+        .await;
+
+    assert_eq!(response.status(), Status::PayloadTooLarge);
+    let body: Value = response.into_json().await.unwrap();
+    assert_eq!(body, json!({ "error": "payload_too_large" }));
+    assert!(irma.requests().is_empty());
+}
+
+#[rocket::async_test]
+async fn default_limit_refuses_megabyte_bodies() {
+    let (irma_url, irma) = Recorder::spawn().await;
+    let client = common::client(common::config(&irma_url, json!({}))).await;
+
+    let attributes = vec!["email"; 100_000];
+    let response = client
+        .post("/start_authentication")
+        .header(ContentType::JSON)
+        .body(json!({ "attributes": attributes, "continuation": CONTINUATION }).to_string())
+        .dispatch()
+        .await;
+
+    assert_eq!(response.status(), Status::PayloadTooLarge);
+    assert!(irma.requests().is_empty());
+}
@@ -0,0 +1,174 @@
+//! Test mode sessions, which complete with canned attribute values once
+//! confirmed at /test_confirm/<id>, in both the in-band and the out-of-band
+//! flow.
+
+mod common;
+
+use common::{Recorder, CONTINUATION};
+use rocket::{
+    http::{ContentType, Status},
+    local::asynchronous::Client,
+};
+use serde_json::{json, Value};
+
+async fn test_mode_client() -> Client {
+    common::client(common::config(
+        "http://127.0.0.1:1",
+        json!({
+            "insecure_dev_mode": true,
+            "allow_insecure_urls": true,
+            "test_mode": {
+                "attributes": { "email": "test@example.com", "city": "Testdorp" },
+            },
+        }),
+    ))
+    .await
+}
+
+// Start a test session, returning the path of its confirmation page
+async fn start(client: &Client, request: Value) -> String {
+    let response = client
+        .post("/start_authentication")
+        .header(ContentType::JSON)
+        .body(request.to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let started: Value = response.into_json().await.unwrap();
+    let client_url = started["client_url"].as_str().expect("Missing client_url");
+    let path = common::local_path(client_url);
+    assert!(path.starts_with("/test_confirm/"));
+    path.to_string()
+}
+
+#[rocket::async_test]
+async fn confirmation_page_shows_canned_values() {
+    let client = test_mode_client().await;
+    let path = start(
+        &client,
+        json!({ "attributes": ["email", "city"], "continuation": CONTINUATION }),
+    )
+    .await;
+
+    let response = client.get(path.clone()).dispatch().await;
+
+    assert_eq!(response.status(), Status::Ok);
+    let page = response.into_string().await.unwrap();
+    assert!(page.contains("test@example.com"));
+    assert!(page.contains("Testdorp"));
+    // The confirmation is posted back to the same path
+    let id = path.strip_prefix("/test_confirm/").unwrap();
+    assert!(page.contains(id));
+}
+
+#[rocket::async_test]
+async fn confirmed_session_redirects_with_result() {
+    let client = test_mode_client().await;
+    let path = start(
+        &client,
+        json!({ "attributes": ["email", "city"], "continuation": CONTINUATION }),
+    )
+    .await;
+
+    let response = client.post(path.clone()).dispatch().await;
+
+    assert_eq!(response.status(), Status::SeeOther);
+    let location = response.headers().get_one("Location").unwrap();
+    assert!(location.starts_with(CONTINUATION));
+    let result = common::query_param(location, "result").expect("Missing result");
+    let attributes = common::result_attributes(&result);
+    assert_eq!(attributes.len(), 2);
+    assert_eq!(attributes["email"], "test@example.com");
+    assert_eq!(attributes["city"], "Testdorp");
+
+    // Sessions are confirmed only once
+    let response = client.post(path.clone()).dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+    let response = client.get(path).dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn confirmed_session_delivers_result_to_attr_url() {
+    let client = test_mode_client().await;
+    let (receiver_url, receiver) = Recorder::spawn().await;
+    let path = start(
+        &client,
+        json!({
+            "attributes": ["email"],
+            "attr_url": format!("{}/attributes", receiver_url),
+        }),
+    )
+    .await;
+
+    let response = client.post(path).dispatch().await;
+
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(
+        response.into_string().await.as_deref(),
+        Some("Test session completed")
+    );
+    let delivered = receiver.requests();
+    assert_eq!(delivered.len(), 1);
+    assert_eq!(delivered[0].uri, "/attributes");
+    assert_eq!(
+        common::result_attributes(&delivered[0].body)["email"],
+        "test@example.com"
+    );
+}
+
+#[rocket::async_test]
+async fn out_of_band_session_returns_to_continuation_without_result() {
+    let client = test_mode_client().await;
+    let (receiver_url, receiver) = Recorder::spawn().await;
+    let path = start(
+        &client,
+        json!({
+            "attributes": ["email"],
+            "attr_url": format!("{}/attributes", receiver_url),
+            "continuation": CONTINUATION,
+        }),
+    )
+    .await;
+
+    let response = client.post(path).dispatch().await;
+
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(response.headers().get_one("Location"), Some(CONTINUATION));
+    assert_eq!(receiver.requests().len(), 1);
+}
+
+#[rocket::async_test]
+async fn unknown_session_is_not_found() {
+    let client = test_mode_client().await;
+
+    let response = client.get("/test_confirm/unknown").dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+    let response = client.post("/test_confirm/unknown").dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn attributes_without_canned_value_fail() {
+    let client = test_mode_client().await;
+
+    let response = client
+        .post("/start_authentication")
+        .header(ContentType::JSON)
+        .body(json!({ "attributes": ["fullname"], "continuation": CONTINUATION }).to_string())
+        .dispatch()
+        .await;
+
+    // The configuration lacks a value for an attribute it supports
+    assert_eq!(response.status(), Status::InternalServerError);
+}
+
+#[test]
+fn test_mode_requires_insecure_dev_mode() {
+    let config = common::try_config(
+        "http://127.0.0.1:1",
+        json!({ "test_mode": { "attributes": { "email": "test@example.com" } } }),
+    );
+
+    assert!(config.is_err());
+}
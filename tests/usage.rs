@@ -0,0 +1,80 @@
+//! Usage accounting per requested attribute set, at the start and the
+//! completion of sessions in both flows. The accounting is kept per process,
+//! so this binary holds a single test.
+
+#![cfg(feature = "mock-irma")]
+
+mod common;
+
+use common::{Recorder, CONTINUATION};
+use rocket::{
+    http::{ContentType, Header, Status},
+    local::asynchronous::Client,
+};
+use serde_json::{json, Value};
+
+const ADMIN_KEY: &str = "admin-key";
+
+async fn usage(client: &Client, label: &str) -> (u64, u64) {
+    let response = client
+        .get("/admin/usage")
+        .header(Header::new(
+            "Authorization",
+            format!("Bearer {}", ADMIN_KEY),
+        ))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let usage: Value = response.into_json().await.unwrap();
+    let count = |counter: &str| usage[label][counter].as_u64().unwrap_or_default();
+    (count("started"), count("completed"))
+}
+
+// Start an in-band session, returning the path of the continuation the irma
+// app opens once the attributes are disclosed
+async fn start_in_band(client: &Client, attributes: &[&str]) -> String {
+    let response = client
+        .post("/start_authentication")
+        .header(ContentType::JSON)
+        .body(json!({ "attributes": attributes, "continuation": CONTINUATION }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let started: Value = response.into_json().await.unwrap();
+    let (_, continuation) = common::split_client_url(started["client_url"].as_str().unwrap());
+    common::local_path(&continuation.unwrap()).to_string()
+}
+
+#[rocket::async_test]
+async fn sessions_are_counted_by_attribute_set_at_both_ends() {
+    let irma_url = common::mock_irma_server().await;
+    let overrides = json!({ "admin_api_key": ADMIN_KEY });
+    let client = common::client(common::config(&irma_url, overrides.clone())).await;
+
+    // In-band, with the label independent of the order of the attributes
+    let first = start_in_band(&client, &["email", "city"]).await;
+    let second = start_in_band(&client, &["city", "email"]).await;
+    assert_eq!(usage(&client, "city,email").await, (2, 0));
+    let response = client.get(first).dispatch().await;
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(usage(&client, "city,email").await, (2, 1));
+    let response = client.get(second).dispatch().await;
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(usage(&client, "city,email").await, (2, 2));
+
+    // Out-of-band, completing once the irma server calls back
+    let plugin_url = common::plugin_server(&irma_url, overrides).await;
+    let (receiver_url, receiver) = Recorder::spawn().await;
+    let response = reqwest::Client::new()
+        .post(format!("{}/start_authentication", plugin_url))
+        .json(&json!({
+            "attributes": ["fullname", "city"],
+            "attr_url": format!("{}/attributes", receiver_url),
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    receiver.wait_for(1).await;
+    assert_eq!(usage(&client, "city,fullname").await, (1, 1));
+}
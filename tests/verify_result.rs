@@ -0,0 +1,217 @@
+//! The `verify-result` subcommand, run against results produced by the
+//! plugin's own signing and encryption code.
+
+mod common;
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Output, Stdio},
+    time::Duration,
+};
+
+use josekit::{jwe::RSA_OAEP, jws::RS256};
+use serde_json::{json, Value};
+use verder_helpen_auth_irma::{
+    config::StatusSpelling,
+    jwe::{self, ResultClaims},
+    verify_result::{EXIT_DECRYPTION, EXIT_EXPIRED, EXIT_SIGNATURE, EXIT_USAGE},
+};
+use verder_helpen_proto::{AuthResult, AuthStatus};
+
+const DECRYPTION_KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/keys/rsa.pem");
+const VERIFICATION_KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/keys/rsa.pub.pem");
+
+fn result(validity: Duration) -> String {
+    let auth_result = AuthResult {
+        status: AuthStatus::Success,
+        attributes: Some(HashMap::from([(
+            "email".to_string(),
+            "user@example.com".to_string(),
+        )])),
+        session_url: None,
+    };
+    let claims = ResultClaims {
+        validity,
+        not_before: false,
+        issuer: common::SERVER_URL.to_string(),
+        audience: None,
+        auth_time: None,
+        disclosed_keys: None,
+        status_spelling: StatusSpelling::Legacy,
+        jti: "result-id".to_string(),
+        credentials: None,
+        nonce: None,
+        destination: None,
+    };
+    let signer = RS256.signer_from_pem(common::PRIVATE_KEY).unwrap();
+    let encrypter = RSA_OAEP.encrypter_from_pem(common::PUBLIC_KEY).unwrap();
+    jwe::sign_and_encrypt_auth_result(
+        &auth_result,
+        &claims,
+        &signer,
+        None,
+        &encrypter,
+        None,
+        false,
+    )
+    .expect("Could not sign result")
+}
+
+fn valid_result() -> String {
+    result(Duration::from_secs(300))
+}
+
+// Write a freshly generated key pair to temporary files, returning the paths
+// of its private and public key
+fn other_key_pair(name: &str) -> (PathBuf, PathBuf) {
+    let key_pair = RS256.generate_key_pair(2048).unwrap();
+    let base = std::env::temp_dir().join(format!("auth-irma-{}-{}", name, std::process::id()));
+    let private_key = base.with_extension("pem");
+    let public_key = base.with_extension("pub.pem");
+    std::fs::write(&private_key, key_pair.to_pem_private_key()).unwrap();
+    std::fs::write(&public_key, key_pair.to_pem_public_key()).unwrap();
+    (private_key, public_key)
+}
+
+// Run the subcommand with the given arguments, passing `stdin` as its input
+fn verify_result(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_verder-helpen-auth-irma"))
+        .arg("verify-result")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Could not run verify-result");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn verify(token: &str, extra_args: &[&str]) -> Output {
+    let mut args = vec![
+        "--decryption-key",
+        DECRYPTION_KEY,
+        "--verification-key",
+        VERIFICATION_KEY,
+    ];
+    args.extend_from_slice(extra_args);
+    args.push(token);
+    verify_result(&args, "")
+}
+
+fn claims(output: &Output) -> Value {
+    serde_json::from_slice(&output.stdout).expect("Claims are not json")
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn valid_result_prints_masked_claims() {
+    let output = verify(&valid_result(), &[]);
+
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    let claims = claims(&output);
+    assert_eq!(claims["attributes"], json!({ "email": "***" }));
+    assert_eq!(claims["status"], "succes");
+    assert_eq!(claims["iss"], common::SERVER_URL);
+    assert!(stderr(&output).starts_with("Expires in "));
+}
+
+#[test]
+fn values_are_shown_when_asked() {
+    let output = verify(&valid_result(), &["--show-values"]);
+
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(
+        claims(&output)["attributes"],
+        json!({ "email": "user@example.com" })
+    );
+}
+
+#[test]
+fn token_is_read_from_stdin() {
+    let output = verify_result(
+        &[
+            "--decryption-key",
+            DECRYPTION_KEY,
+            "--verification-key",
+            VERIFICATION_KEY,
+        ],
+        &format!("{}\n", valid_result()),
+    );
+
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(claims(&output)["attributes"], json!({ "email": "***" }));
+}
+
+#[test]
+fn other_decryption_key_fails_decryption() {
+    let (private_key, public_key) = other_key_pair("decryption");
+
+    let output = verify_result(
+        &[
+            "--decryption-key",
+            private_key.to_str().unwrap(),
+            "--verification-key",
+            VERIFICATION_KEY,
+            &valid_result(),
+        ],
+        "",
+    );
+
+    assert_eq!(output.status.code(), Some(EXIT_DECRYPTION));
+    assert!(output.stdout.is_empty());
+    std::fs::remove_file(private_key).unwrap();
+    std::fs::remove_file(public_key).unwrap();
+}
+
+#[test]
+fn other_verification_key_fails_verification() {
+    let (private_key, public_key) = other_key_pair("verification");
+
+    let output = verify_result(
+        &[
+            "--decryption-key",
+            DECRYPTION_KEY,
+            "--verification-key",
+            public_key.to_str().unwrap(),
+            &valid_result(),
+        ],
+        "",
+    );
+
+    assert_eq!(output.status.code(), Some(EXIT_SIGNATURE));
+    assert!(output.stdout.is_empty());
+    std::fs::remove_file(private_key).unwrap();
+    std::fs::remove_file(public_key).unwrap();
+}
+
+#[test]
+fn expired_result_is_reported() {
+    let token = result(Duration::ZERO);
+    std::thread::sleep(Duration::from_secs(1));
+
+    let output = verify(&token, &[]);
+
+    assert_eq!(output.status.code(), Some(EXIT_EXPIRED));
+    // The claims are still printed, to help debugging
+    assert_eq!(claims(&output)["attributes"], json!({ "email": "***" }));
+    assert!(stderr(&output).starts_with("Expired "));
+}
+
+#[test]
+fn missing_keys_are_usage_errors() {
+    let output = verify_result(&["--decryption-key", DECRYPTION_KEY], "");
+
+    assert_eq!(output.status.code(), Some(EXIT_USAGE));
+    assert!(stderr(&output).starts_with("Usage: verify-result"));
+}
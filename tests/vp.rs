@@ -0,0 +1,136 @@
+//! Results in the vp format: verifiable presentations holding the disclosed
+//! attributes as verifiable credential, encrypted to the result key.
+
+mod common;
+
+use common::CONTINUATION;
+use josekit::{
+    jwe::{self, RSA_OAEP},
+    jwt::{self, JwtPayload},
+};
+use rocket::http::{ContentType, Status};
+use serde_json::{json, Value};
+
+// Complete a test mode session with vp results, returning the result it
+// redirects with
+async fn result(overrides: Value) -> String {
+    let mut config = json!({
+        "insecure_dev_mode": true,
+        "test_mode": { "attributes": { "email": "test@example.com" } },
+        "result_format": "vp",
+    });
+    if let (Value::Object(config), Value::Object(overrides)) = (&mut config, overrides) {
+        config.extend(overrides);
+    }
+    let client = common::client(common::config("http://127.0.0.1:1", config)).await;
+    let response = client
+        .post("/start_authentication")
+        .header(ContentType::JSON)
+        .body(
+            json!({ "attributes": ["email"], "continuation": CONTINUATION, "nonce": "nonce-1" })
+                .to_string(),
+        )
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let started: Value = response.into_json().await.unwrap();
+    let response = client
+        .post(common::local_path(started["client_url"].as_str().unwrap()))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::SeeOther);
+    let location = response.headers().get_one("Location").unwrap();
+    common::query_param(location, "result").expect("Missing result")
+}
+
+// Verify a jwt signed with the test signing key, returning its claims
+fn verify(token: &str) -> JwtPayload {
+    let config = common::config("http://127.0.0.1:1", json!({}));
+    let (payload, _) = jwt::decode_with_verifier(token, config.signing_verifier().unwrap())
+        .expect("Invalid signature");
+    payload
+}
+
+// Decrypt and verify a result, returning the claims of the presentation and
+// of the credentials in it
+fn presentation(result: &str) -> (JwtPayload, Vec<JwtPayload>) {
+    assert_eq!(common::header(result)["cty"], "JWT");
+    let decrypter = RSA_OAEP.decrypter_from_pem(common::PRIVATE_KEY).unwrap();
+    let (presentation, _) = jwe::deserialize_compact(result, &decrypter).unwrap();
+    let presentation = verify(std::str::from_utf8(&presentation).unwrap());
+    let credentials = presentation.claim("vp").unwrap()["verifiableCredential"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|credential| verify(credential.as_str().unwrap()))
+        .collect();
+    (presentation, credentials)
+}
+
+#[rocket::async_test]
+async fn attributes_are_presented_as_credential() {
+    let result = result(json!({
+        "vc_contexts": ["https://example.com/contexts/irma/v1"],
+        "vc_types": ["IrmaAttributesCredential"],
+    }))
+    .await;
+
+    let (presentation, credentials) = presentation(&result);
+
+    let vp = presentation.claim("vp").unwrap();
+    assert_eq!(vp["type"], json!(["VerifiablePresentation"]));
+    assert_eq!(
+        vp["@context"],
+        json!([
+            "https://www.w3.org/2018/credentials/v1",
+            "https://example.com/contexts/irma/v1",
+        ])
+    );
+    assert_eq!(credentials.len(), 1);
+    let vc = credentials[0].claim("vc").unwrap();
+    assert_eq!(
+        vc["type"],
+        json!(["VerifiableCredential", "IrmaAttributesCredential"])
+    );
+    assert_eq!(vc["@context"], vp["@context"]);
+    assert_eq!(
+        vc["credentialSubject"],
+        json!({ "email": "test@example.com" })
+    );
+    assert_eq!(credentials[0].issuer(), presentation.issuer());
+}
+
+#[rocket::async_test]
+async fn presentation_carries_the_result_claims() {
+    let result = result(json!({ "bind_destination": true, "include_auth_time": true })).await;
+
+    let (presentation, _) = presentation(&result);
+
+    assert_eq!(presentation.claim("status"), Some(&json!("succes")));
+    assert_eq!(presentation.claim("nonce"), Some(&json!("nonce-1")));
+    assert_eq!(
+        presentation.claim("destination"),
+        Some(&json!(CONTINUATION))
+    );
+    assert!(presentation.claim("auth_time").is_some());
+    assert_eq!(
+        presentation.claim("disclosed_keys"),
+        Some(&json!(["email"]))
+    );
+    assert!(presentation.jwt_id().is_some());
+    // The attributes are only in the credential
+    assert_eq!(presentation.claim("attributes"), None);
+}
+
+#[rocket::async_test]
+async fn not_before_is_only_set_when_configured() {
+    let (presentation, credentials) = presentation(&result(json!({})).await);
+    assert_eq!(presentation.not_before(), None);
+    assert_eq!(credentials[0].not_before(), None);
+
+    let (presentation, credentials) =
+        presentation(&result(json!({ "result_not_before": true })).await);
+    assert_eq!(presentation.not_before(), presentation.issued_at());
+    assert_eq!(credentials[0].not_before(), credentials[0].issued_at());
+    assert!(credentials[0].not_before().is_some());
+}